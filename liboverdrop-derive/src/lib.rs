@@ -0,0 +1,63 @@
+//! Derive macro for the `liboverdrop::Merge` trait.
+//!
+//! This crate is re-exported by `liboverdrop` with its `derive` feature, and should
+//! not be used directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Index};
+
+/// Derive `liboverdrop::Merge` for a struct, merging each field in turn.
+///
+/// All fields must implement `Merge` themselves, and so must all type parameters.
+#[proc_macro_derive(Merge)]
+pub fn derive_merge(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "Merge can only be derived for structs",
+            ))
+        }
+    };
+    let merges: Vec<_> = match fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| {
+                let name = &field.ident;
+                quote!(::liboverdrop::Merge::merge(&mut self.#name, higher.#name);)
+            })
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|idx| {
+                let idx = Index::from(idx);
+                quote!(::liboverdrop::Merge::merge(&mut self.#idx, higher.#idx);)
+            })
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::liboverdrop::Merge));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::liboverdrop::Merge for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn merge(&mut self, higher: Self) {
+                #(#merges)*
+            }
+        }
+    })
+}
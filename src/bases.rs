@@ -0,0 +1,176 @@
+//! Curated sets of base directories, and helpers to adjust them.
+
+use std::path::{Component, Path, PathBuf};
+
+/// [`SYSTEMD_CONVENTIONAL_BASES`](crate::SYSTEMD_CONVENTIONAL_BASES), without `/usr/local/lib`.
+///
+/// This is meant for projects where `/usr/local` is a distinct writable area from
+/// the OS image base.
+pub const SYSTEMD_BASES_WITHOUT_LOCAL: &[&str] = &["/usr/lib", "/etc", "/run"];
+
+/// [`SYSTEMD_CONVENTIONAL_BASES`](crate::SYSTEMD_CONVENTIONAL_BASES), with multilib
+/// (`lib64`) directories right after their `lib` counterparts.
+pub const MULTILIB_CONVENTIONAL_BASES: &[&str] = &[
+    "/usr/lib",
+    "/usr/lib64",
+    "/usr/local/lib",
+    "/usr/local/lib64",
+    "/etc",
+    "/run",
+];
+
+/// [`SYSTEMD_CONVENTIONAL_BASES`](crate::SYSTEMD_CONVENTIONAL_BASES), for ostree-based systems.
+///
+/// There, `/usr/etc` holds the vendor copy of `/etc`, which is merged into the
+/// writable `/etc` on upgrades; it is thus scanned right below `/etc`.
+pub const OSTREE_CONVENTIONAL_BASES: &[&str] =
+    &["/usr/lib", "/usr/local/lib", "/usr/etc", "/etc", "/run"];
+
+/// An ordered list of base directories, in increasing priority order.
+///
+/// This allows deriving a slightly different set of base directories from one of
+/// the curated ones, instead of re-declaring it:
+///
+/// ```rust
+/// # use liboverdrop::BaseDirs;
+/// let bases = BaseDirs::from(liboverdrop::SYSTEMD_CONVENTIONAL_BASES)
+///     .without("/usr/local/lib")
+///     .insert_after("/etc", "/etc/my-site");
+/// assert_eq!(bases.dirs(), ["/usr/lib", "/etc", "/etc/my-site", "/run"].map(std::path::PathBuf::from));
+/// ```
+///
+/// It can be passed directly wherever base directories are expected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BaseDirs {
+    dirs: Vec<PathBuf>,
+}
+
+impl BaseDirs {
+    /// Create a list from `dirs`, in increasing priority order.
+    pub fn new<BdS: AsRef<Path>, BdI: IntoIterator<Item = BdS>>(dirs: BdI) -> Self {
+        Self {
+            dirs: dirs.into_iter().map(|d| d.as_ref().to_path_buf()).collect(),
+        }
+    }
+
+    /// Base directories, in increasing priority order.
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Remove `dir`, if present.
+    #[must_use]
+    pub fn without(mut self, dir: impl AsRef<Path>) -> Self {
+        self.dirs.retain(|d| d != dir.as_ref());
+        self
+    }
+
+    /// Add `dir` with the highest priority.
+    #[must_use]
+    pub fn push(mut self, dir: impl AsRef<Path>) -> Self {
+        self.dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Insert `dir` right below `anchor` (i.e. with a lower priority), or with the lowest priority if `anchor` is absent.
+    #[must_use]
+    pub fn insert_before(mut self, anchor: impl AsRef<Path>, dir: impl AsRef<Path>) -> Self {
+        let idx = self.position(anchor.as_ref()).unwrap_or(0);
+        self.dirs.insert(idx, dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Insert `dir` right above `anchor` (i.e. with a higher priority), or with the highest priority if `anchor` is absent.
+    #[must_use]
+    pub fn insert_after(mut self, anchor: impl AsRef<Path>, dir: impl AsRef<Path>) -> Self {
+        let idx = self
+            .position(anchor.as_ref())
+            .map_or(self.dirs.len(), |idx| idx + 1);
+        self.dirs.insert(idx, dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Prefix all directories with `root`, e.g. to scan an image mounted elsewhere.
+    ///
+    /// See also [`Overdrop::root`](crate::Overdrop::root).
+    #[must_use]
+    pub fn prefixed(mut self, root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+        for dir in &mut self.dirs {
+            let relative: PathBuf = dir
+                .components()
+                .filter(|c| !matches!(c, Component::Prefix(_) | Component::RootDir))
+                .collect();
+            *dir = root.join(relative);
+        }
+        self
+    }
+
+    fn position(&self, dir: &Path) -> Option<usize> {
+        self.dirs.iter().position(|d| d == dir)
+    }
+}
+
+impl<S: AsRef<Path>> From<&[S]> for BaseDirs {
+    fn from(dirs: &[S]) -> Self {
+        Self::new(dirs)
+    }
+}
+
+impl IntoIterator for BaseDirs {
+    type Item = PathBuf;
+    type IntoIter = std::vec::IntoIter<PathBuf>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.dirs.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a BaseDirs {
+    type Item = &'a PathBuf;
+    type IntoIter = std::slice::Iter<'a, PathBuf>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.dirs.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SYSTEMD_CONVENTIONAL_BASES;
+
+    fn paths(dirs: &[&str]) -> Vec<PathBuf> {
+        dirs.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn curated_bases() {
+        let without_local = BaseDirs::from(SYSTEMD_CONVENTIONAL_BASES).without("/usr/local/lib");
+        assert_eq!(without_local.dirs(), paths(SYSTEMD_BASES_WITHOUT_LOCAL));
+        let ostree = BaseDirs::from(SYSTEMD_CONVENTIONAL_BASES).insert_before("/etc", "/usr/etc");
+        assert_eq!(ostree.dirs(), paths(OSTREE_CONVENTIONAL_BASES));
+    }
+
+    #[test]
+    fn compose_bases() {
+        let bases = BaseDirs::from(SYSTEMD_BASES_WITHOUT_LOCAL)
+            .insert_before("/missing", "/opt/lowest")
+            .insert_after("/missing", "/opt/highest")
+            .push("/run/extra")
+            .prefixed("/sysroot");
+        assert_eq!(
+            bases.dirs(),
+            paths(&[
+                "/sysroot/opt/lowest",
+                "/sysroot/usr/lib",
+                "/sysroot/etc",
+                "/sysroot/run",
+                "/sysroot/opt/highest",
+                "/sysroot/run/extra",
+            ])
+        );
+        let fragments = crate::scan(&bases, "liboverdrop.d", &["conf"], false);
+        assert!(fragments.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Export and import of effective fragment sets as a single bundle file.
+//!
+//! A bundle holds the name, contents and origin of every fragment in a
+//! [`FragmentSet`], so that a machine's effective configuration can be captured
+//! and reproduced elsewhere. The format is a simple line-oriented header per
+//! fragment, followed by its raw contents:
+//!
+//! ```text
+//! liboverdrop-bundle 1
+//! <name>\t<origin path>\t<length>
+//! <contents>
+//! ...
+//! ```
+
+use crate::FragmentSet;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &str = "liboverdrop-bundle 1";
+
+/// A configuration fragment captured in a [`Bundle`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BundleEntry {
+    origin: PathBuf,
+    contents: Vec<u8>,
+}
+
+impl BundleEntry {
+    /// Path where the fragment was located when captured.
+    pub fn origin(&self) -> &Path {
+        &self.origin
+    }
+
+    /// Contents of the fragment.
+    pub fn contents(&self) -> &[u8] {
+        &self.contents
+    }
+}
+
+/// An effective fragment set, with the contents of all fragments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bundle {
+    entries: BTreeMap<OsString, BundleEntry>,
+}
+
+impl Bundle {
+    /// Capture the contents of all fragments in `fragments`.
+    pub fn capture(fragments: &FragmentSet) -> io::Result<Self> {
+        let mut entries = BTreeMap::new();
+        for (name, fragment) in fragments {
+            let entry = BundleEntry {
+                origin: fragment.path().to_path_buf(),
+                contents: fs::read(fragment.path())?,
+            };
+            entries.insert(name.clone(), entry);
+        }
+        Ok(Self { entries })
+    }
+
+    /// Return the fragment with the given filename, if any.
+    pub fn get(&self, name: impl AsRef<OsStr>) -> Option<&BundleEntry> {
+        self.entries.get(name.as_ref())
+    }
+
+    /// Number of fragments in the bundle.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the bundle holds no fragment.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over fragments, in alphanumeric order by filename.
+    pub fn iter(&self) -> btree_map::Iter<'_, OsString, BundleEntry> {
+        self.entries.iter()
+    }
+
+    /// Write the bundle to `writer`.
+    ///
+    /// Fragment names and origins must be valid UTF-8, without tabs or newlines.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", MAGIC)?;
+        for (name, entry) in &self.entries {
+            let name = header_field(name)?;
+            let origin = header_field(entry.origin.as_os_str())?;
+            writeln!(writer, "{}\t{}\t{}", name, origin, entry.contents.len())?;
+            writer.write_all(&entry.contents)?;
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+
+    /// Read a bundle previously written with [`write_to`](Self::write_to).
+    pub fn read_from<R: BufRead>(mut reader: R) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim_end_matches('\n') != MAGIC {
+            return Err(invalid("not a liboverdrop bundle"));
+        }
+
+        let mut entries = BTreeMap::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let mut fields = line.trim_end_matches('\n').split('\t');
+            let (name, origin, len) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(n), Some(o), Some(l)) if fields.next().is_none() => (n, o, l),
+                _ => return Err(invalid("malformed bundle header")),
+            };
+            let len: usize = len
+                .parse()
+                .map_err(|_| invalid("malformed fragment length"))?;
+
+            let mut contents = vec![0; len];
+            reader.read_exact(&mut contents)?;
+            let mut newline = [0; 1];
+            reader.read_exact(&mut newline)?;
+            if newline != *b"\n" {
+                return Err(invalid("malformed fragment terminator"));
+            }
+
+            let entry = BundleEntry {
+                origin: PathBuf::from(origin),
+                contents,
+            };
+            entries.insert(OsString::from(name), entry);
+        }
+        Ok(Self { entries })
+    }
+
+    /// Write all fragments into `dir`, returning the path of each written fragment.
+    ///
+    /// The directory can then be scanned as a single base directory (with an empty
+    /// shared path) to reproduce the captured set.
+    pub fn unpack(&self, dir: impl AsRef<Path>) -> io::Result<BTreeMap<OsString, PathBuf>> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut paths = BTreeMap::new();
+        for (name, entry) in &self.entries {
+            if Path::new(name).file_name() != Some(name.as_os_str()) {
+                return Err(invalid("invalid fragment name"));
+            }
+            let path = dir.join(name);
+            fs::write(&path, &entry.contents)?;
+            paths.insert(name.clone(), path);
+        }
+        Ok(paths)
+    }
+}
+
+impl<'a> IntoIterator for &'a Bundle {
+    type Item = (&'a OsString, &'a BundleEntry);
+    type IntoIter = btree_map::Iter<'a, OsString, BundleEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+fn header_field(value: &OsStr) -> io::Result<&str> {
+    match value.to_str() {
+        Some(s) if !s.contains(['\t', '\n']) => Ok(s),
+        _ => Err(invalid(
+            "fragment name or origin cannot be stored in a bundle",
+        )),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Overdrop;
+
+    fn scan_tree() -> FragmentSet {
+        let dirs =
+            ["usr/lib", "etc", "run"].map(|d| Path::new("tests/fixtures/tree-merge").join(d));
+        Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs)
+            .scan_fragments()
+            .unwrap()
+    }
+
+    #[test]
+    fn bundle_roundtrip() {
+        let bundle = Bundle::capture(&scan_tree()).unwrap();
+        assert_eq!(bundle.len(), 4);
+        let entry = bundle.get("30-local.conf").unwrap();
+        assert_eq!(entry.contents(), b"retries=5\n");
+        assert_eq!(
+            entry.origin(),
+            Path::new("tests/fixtures/tree-merge/etc/liboverdrop.d/30-local.conf")
+        );
+
+        let mut buf = Vec::new();
+        bundle.write_to(&mut buf).unwrap();
+        let imported = Bundle::read_from(buf.as_slice()).unwrap();
+        assert_eq!(imported, bundle);
+
+        assert!(Bundle::read_from(&b"garbage\n"[..]).is_err());
+        assert!(Bundle::read_from(&buf[..buf.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn bundle_unpack() {
+        let bundle = Bundle::capture(&scan_tree()).unwrap();
+        let dir = std::env::temp_dir().join(format!("liboverdrop-bundle-{}", std::process::id()));
+        let paths = bundle.unpack(&dir).unwrap();
+        assert_eq!(paths.len(), 4);
+
+        let rescanned = crate::scan::<_, _, _, &str>([&dir], "", &[], false);
+        assert_eq!(rescanned, paths);
+        for (name, path) in &rescanned {
+            assert_eq!(
+                fs::read(path).unwrap(),
+                bundle.get(name).unwrap().contents()
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,421 @@
+//! Caching of scanned directories and parsed fragments across reloads.
+
+use crate::overdrop::files_only;
+use crate::watch::watch_events;
+use crate::{
+    fold_partials, read_dir_entries, DirScan, Entry, Fragment, FragmentSet, Overdrop, Partial,
+    Rejection, ScanError, StdFs, WatchEvent,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// How old a directory modification time must be for its listing to be cached.
+///
+/// Changes within the same timestamp tick as a previous scan would go unnoticed otherwise.
+const MTIME_SLACK: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+struct CacheEntry<P> {
+    fragment: Fragment,
+    hash: u64,
+    parsed: P,
+}
+
+/// A cache of parsed fragments, to skip re-parsing unchanged fragments on reload.
+///
+/// For each fragment, the cache keeps its modification time, size and a hash of its
+/// contents, together with the parsed (e.g. [`Partial`]) value. On reload, fragments
+/// with unchanged path, modification time and size are not read at all, and fragments
+/// whose contents hash the same as before are not re-parsed; only the final fold
+/// is run again.
+///
+/// Like any mtime-based check, a fragment rewritten with the same size within the
+/// filesystem timestamp granularity is not detected as changed.
+#[derive(Clone, Debug)]
+pub struct FragmentCache<P> {
+    entries: BTreeMap<OsString, CacheEntry<P>>,
+}
+
+impl<P> Default for FragmentCache<P> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<P> FragmentCache<P> {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bring the cache up to date with `fragments`, the result of a (re)scan.
+    ///
+    /// `parse` is called, with the fragment filename and contents, only for new or
+    /// changed fragments; entries for fragments which are not in `fragments` anymore
+    /// are dropped. Returns the number of fragments which were parsed.
+    ///
+    /// On error, the cache is left partially updated, and the next reload retries
+    /// the fragments which could not be parsed.
+    pub fn reload<E, F>(&mut self, fragments: &FragmentSet, mut parse: F) -> Result<usize, E>
+    where
+        E: From<io::Error>,
+        F: FnMut(&OsStr, &[u8]) -> Result<P, E>,
+    {
+        self.entries.retain(|name, _| fragments.get(name).is_some());
+
+        let mut parsed = 0;
+        for (name, fragment) in fragments {
+            if let Some(entry) = self.entries.get(name) {
+                if is_unchanged(&entry.fragment, fragment) {
+                    continue;
+                }
+            }
+
+            let contents = fs::read(fragment.path())?;
+            let hash = hash_of(&contents);
+            let value = match self.entries.remove(name) {
+                Some(entry) if entry.hash == hash => entry.parsed,
+                _ => {
+                    parsed += 1;
+                    parse(name, &contents)?
+                }
+            };
+            let entry = CacheEntry {
+                fragment: fragment.clone(),
+                hash,
+                parsed: value,
+            };
+            self.entries.insert(name.clone(), entry);
+        }
+        Ok(parsed)
+    }
+
+    /// Return the cached value for the fragment with the given filename, if any.
+    pub fn get(&self, name: impl AsRef<OsStr>) -> Option<&P> {
+        self.entries.get(name.as_ref()).map(|entry| &entry.parsed)
+    }
+
+    /// Number of cached fragments.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over cached values, in alphanumeric order by filename.
+    pub fn iter(&self) -> impl Iterator<Item = (&OsStr, &P)> {
+        self.entries
+            .iter()
+            .map(|(name, entry)| (name.as_os_str(), &entry.parsed))
+    }
+
+    /// Fold all cached values over `defaults`, as [`fold_partials`] does.
+    pub fn fold<T>(&self, defaults: T) -> T
+    where
+        P: Partial<T> + Clone,
+    {
+        fold_partials(defaults, self.entries.values().map(|e| e.parsed.clone()))
+    }
+}
+
+/// The cached listing of a directory, see [`CachedScanner`].
+#[derive(Clone, Debug)]
+struct CachedDir {
+    modified: SystemTime,
+    entries: Vec<(OsString, Entry<Fragment>)>,
+    matched: usize,
+    unmatched: Vec<OsString>,
+    symlinks: Vec<PathBuf>,
+    /// Entries rejected while listing (i.e. untrusted ones), before validation.
+    rejected: Vec<Rejection>,
+}
+
+/// A scanner skipping directories which did not change since the previous scan.
+///
+/// The listing of each scanned directory is cached together with the directory
+/// modification time, and reused on [`rescan`](Self::rescan) if the directory was
+/// not modified since. Override resolution (and validation, if any) still runs on
+/// every rescan, so results are the same as a fresh [`Overdrop::scan_fragments`],
+/// except that fragments modified in place without changing their directory (e.g.
+/// rewritten instead of replaced) are not detected.
+///
+/// Listings are never cached for recursive scans (see [`Overdrop::max_depth`]), nor
+/// for directories modified too recently, as changes within the same timestamp tick
+/// as a previous scan would go unnoticed.
+#[derive(Clone, Debug)]
+pub struct CachedScanner {
+    scanner: Overdrop,
+    dirs: BTreeMap<PathBuf, CachedDir>,
+    current: FragmentSet,
+}
+
+impl CachedScanner {
+    /// Create a cached scanner, performing the initial scan.
+    pub fn new(scanner: Overdrop) -> Result<Self, ScanError> {
+        let mut cached = Self {
+            scanner,
+            dirs: BTreeMap::new(),
+            current: FragmentSet::default(),
+        };
+        cached.rescan()?;
+        Ok(cached)
+    }
+
+    /// The effective fragment set, as of the last scan.
+    pub fn fragments(&self) -> &FragmentSet {
+        &self.current
+    }
+
+    /// Scan again, reusing the listings of unchanged directories.
+    ///
+    /// Returns whether the effective fragment set changed. On error (e.g. because of
+    /// [strictness checks](Overdrop::require_nonempty)), the previous fragment set is kept.
+    pub fn rescan(&mut self) -> Result<bool, ScanError> {
+        let previous = self.refresh()?;
+        Ok(previous != self.current)
+    }
+
+    /// Scan again, describing how the effective fragment set changed.
+    ///
+    /// This works like [`rescan`](Self::rescan), with one event per changed filename,
+    /// as [`PollWatcher::poll_events`](crate::PollWatcher::poll_events) does.
+    pub fn rescan_events(&mut self) -> Result<Vec<WatchEvent>, ScanError> {
+        let previous = self.refresh()?;
+        let changed = previous.changed_names(&self.current);
+        Ok(watch_events(
+            &self.scanner,
+            &previous,
+            &self.current,
+            changed,
+        ))
+    }
+
+    /// Scan again, returning the previous fragment set.
+    fn refresh(&mut self) -> Result<FragmentSet, ScanError> {
+        let Self {
+            scanner,
+            dirs,
+            current,
+        } = self;
+        let started = SystemTime::now();
+        let cacheable = scanner.options().max_depth == 0;
+        let (sources, statuses) = scanner.scan_layers_with(|dir, found| {
+            let modified = fs::metadata(dir).and_then(|meta| meta.modified()).ok();
+            if let (Some(modified), Some(cached)) = (modified, dirs.get(dir)) {
+                if cacheable && cached.modified == modified {
+                    for (name, entry) in &cached.entries {
+                        found(name.clone(), entry.clone());
+                    }
+                    return Ok(DirScan {
+                        matched: cached.matched,
+                        unmatched: cached.unmatched.clone(),
+                        rejected: cached.rejected.clone(),
+                        symlinks: cached.symlinks.clone(),
+                    });
+                }
+            }
+
+            dirs.remove(dir);
+            let mut entries = Vec::new();
+            let scan = read_dir_entries(&StdFs, dir, scanner.options(), |name, entry| {
+                entries.push((name, entry));
+            })?;
+            for (name, entry) in &entries {
+                found(name.clone(), entry.clone());
+            }
+            let settled = modified.filter(|modified| {
+                started
+                    .duration_since(*modified)
+                    .map_or(false, |age| age >= MTIME_SLACK)
+            });
+            if let (Some(modified), true) = (settled, cacheable) {
+                let cached = CachedDir {
+                    modified,
+                    entries,
+                    matched: scan.matched,
+                    unmatched: scan.unmatched.clone(),
+                    symlinks: scan.symlinks.clone(),
+                    rejected: scan.rejected.clone(),
+                };
+                dirs.insert(dir.to_path_buf(), cached);
+            }
+            Ok(scan)
+        });
+        let (sources, _) = scanner.check_scan(sources, statuses)?;
+
+        let fresh = FragmentSet::from_map(files_only(sources));
+        Ok(mem::replace(current, fresh))
+    }
+}
+
+/// Whether `fragment` can be assumed unchanged from `cached`, without reading it.
+fn is_unchanged(cached: &Fragment, fragment: &Fragment) -> bool {
+    fragment.modified().is_some() && cached == fragment
+}
+
+fn hash_of(contents: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Overdrop;
+    use std::path::Path;
+
+    #[derive(Clone, Debug)]
+    struct Settings(Vec<(String, String)>);
+
+    impl Partial<BTreeMap<String, String>> for Settings {
+        fn apply_to(self, target: &mut BTreeMap<String, String>) {
+            target.extend(self.0);
+        }
+    }
+
+    fn parse(_: &OsStr, contents: &[u8]) -> io::Result<Settings> {
+        let contents = String::from_utf8_lossy(contents);
+        let settings = contents
+            .lines()
+            .filter_map(|l| l.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Ok(Settings(settings))
+    }
+
+    fn scan_tree(dirs: &[&str]) -> FragmentSet {
+        Overdrop::new("liboverdrop.d")
+            .base_dirs(
+                dirs.iter()
+                    .map(|d| Path::new("tests/fixtures/tree-merge").join(d)),
+            )
+            .scan_fragments()
+            .unwrap()
+    }
+
+    #[test]
+    fn reload_only_parses_changes() {
+        let mut cache = FragmentCache::new();
+        assert_eq!(
+            cache
+                .reload(&scan_tree(&["usr/lib", "etc"]), parse)
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            cache
+                .reload(&scan_tree(&["usr/lib", "etc"]), parse)
+                .unwrap(),
+            0
+        );
+
+        // A new fragment.
+        let all = scan_tree(&["usr/lib", "etc", "run"]);
+        assert_eq!(cache.reload(&all, parse).unwrap(), 1);
+        assert_eq!(cache.len(), 4);
+        let config = cache.fold(BTreeMap::new());
+        assert_eq!(config["name"], "runtime");
+        assert_eq!(config["verbose"], "true");
+
+        // A changed fragment (overridden from another directory) and a removed one.
+        assert_eq!(cache.reload(&scan_tree(&["usr/lib"]), parse).unwrap(), 1);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("30-local.conf").is_none());
+        let config = cache.fold(BTreeMap::new());
+        assert_eq!(config["verbose"], "false");
+        let names: Vec<_> = cache.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["10-base.conf", "20-extra.conf"]);
+    }
+
+    #[test]
+    fn cached_rescan() {
+        use crate::testing::TreeBuilder;
+
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "a=1\n")
+            .base_dir("etc/svc.d");
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "etc"]));
+        let mut cached = CachedScanner::new(scanner.clone()).unwrap();
+        assert_eq!(cached.fragments().len(), 1);
+        assert!(!cached.rescan().unwrap());
+        // Directories are only cached once their modification time settled.
+        assert!(!cached.dirs.contains_key(&tree.path("etc/svc.d")));
+        std::thread::sleep(MTIME_SLACK);
+        assert!(!cached.rescan().unwrap());
+        assert!(cached.dirs.contains_key(&tree.path("etc/svc.d")));
+
+        let tree = tree.fragment("etc/svc.d/10-a.conf", "a=2\n");
+        assert!(cached.rescan().unwrap());
+        assert!(!cached.dirs.contains_key(&tree.path("etc/svc.d")));
+        assert!(cached.dirs.contains_key(&tree.path("usr/lib/svc.d")));
+        assert_eq!(*cached.fragments(), scanner.scan_fragments().unwrap());
+        assert!(!cached.rescan().unwrap());
+
+        fs::remove_file(tree.path("etc/svc.d/10-a.conf")).unwrap();
+        assert!(cached.rescan().unwrap());
+        assert_eq!(*cached.fragments(), scanner.scan_fragments().unwrap());
+    }
+
+    #[test]
+    fn cached_rescan_events() {
+        use crate::testing::TreeBuilder;
+
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "a=1\n")
+            .fragment("usr/lib/svc.d/20-b.conf", "b=1\n")
+            .base_dir("etc/svc.d");
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "etc"]));
+        let mut cached = CachedScanner::new(scanner).unwrap();
+        assert!(cached.rescan_events().unwrap().is_empty());
+
+        let tree = tree
+            .fragment("etc/svc.d/10-a.conf", "a=2\n")
+            .mask("etc/svc.d/20-b.conf")
+            .fragment("etc/svc.d/30-c.conf", "c=1\n");
+        let events = cached.rescan_events().unwrap();
+        let a = cached.fragments().get("10-a.conf").unwrap();
+        assert_eq!(a.path(), tree.path("etc/svc.d/10-a.conf"));
+        assert!(matches!(
+            &events[..],
+            [
+                WatchEvent::FragmentOverridden { name: a, previous, .. },
+                WatchEvent::FragmentMasked { name: b, mask, .. },
+                WatchEvent::FragmentAdded { name: c, .. },
+            ] if a == "10-a.conf"
+                && previous.path() == tree.path("usr/lib/svc.d/10-a.conf")
+                && b == "20-b.conf"
+                && *mask == tree.path("etc/svc.d/20-b.conf")
+                && c == "30-c.conf"
+        ));
+        assert!(cached.rescan_events().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reload_error_is_retried() {
+        let mut cache = FragmentCache::new();
+        let fragments = scan_tree(&["usr/lib"]);
+        let res = cache.reload(&fragments, |name, contents| {
+            if name == "20-extra.conf" {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "bad fragment"))
+            } else {
+                parse(name, contents)
+            }
+        });
+        assert!(res.is_err());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.reload(&fragments, parse).unwrap(), 1);
+    }
+}
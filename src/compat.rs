@@ -0,0 +1,80 @@
+//! Compatibility shim for the liboverdrop 0.0.x API.
+
+#![allow(deprecated)]
+
+use crate::scan;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Configuration fragments scanner, as in liboverdrop 0.0.x.
+///
+/// This is only available with the `compat-0_0` feature, and is implemented
+/// on top of [`scan`]. See the crate documentation for how to migrate.
+#[deprecated(note = "use liboverdrop::scan() instead")]
+#[derive(Clone, Debug)]
+pub struct FragmentScanner {
+    base_dirs: Vec<String>,
+    shared_path: String,
+    ignore_dotfiles: bool,
+    allowed_extensions: Vec<String>,
+}
+
+impl FragmentScanner {
+    /// Create a new scanner; arguments are the same as for [`scan`], in a different order.
+    pub fn new(
+        base_dirs: Vec<String>,
+        shared_path: &str,
+        ignore_dotfiles: bool,
+        allowed_extensions: Vec<String>,
+    ) -> Self {
+        Self {
+            base_dirs,
+            shared_path: shared_path.to_string(),
+            ignore_dotfiles,
+            allowed_extensions,
+        }
+    }
+
+    /// Scan unique configuration fragments.
+    ///
+    /// As in liboverdrop 0.0.x, fragments whose filename is not valid UTF-8 are skipped.
+    pub fn scan(&self) -> BTreeMap<String, PathBuf> {
+        scan(
+            &self.base_dirs,
+            &self.shared_path,
+            &self.allowed_extensions,
+            self.ignore_dotfiles,
+        )
+        .into_iter()
+        .filter_map(|(name, path)| Some((name.into_string().ok()?, path)))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    #[test]
+    fn compat_scan() {
+        let treedir = "tests/fixtures/tree-basic";
+        let dirs = vec![
+            format!("{}/{}", treedir, "usr/lib"),
+            format!("{}/{}", treedir, "etc"),
+        ];
+
+        let fragments = FragmentScanner::new(dirs.clone(), "liboverdrop.d", true, vec![]).scan();
+        let expected: BTreeMap<OsString, PathBuf> =
+            scan::<_, _, _, &str>(&dirs, "liboverdrop.d", &[], true);
+        let expected: BTreeMap<String, PathBuf> = expected
+            .into_iter()
+            .map(|(k, v)| (k.into_string().unwrap(), v))
+            .collect();
+        assert_eq!(fragments, expected);
+
+        let fragments =
+            FragmentScanner::new(dirs, "liboverdrop.d", false, vec!["toml".to_string()]).scan();
+        assert_eq!(fragments.len(), 6);
+    }
+}
@@ -0,0 +1,153 @@
+//! Integration with the `config` crate, as a configuration source.
+
+use crate::{scan_and_merge_value, ArrayMerge, Format};
+use config::{ConfigError, Map, Source, Value, ValueKind};
+use std::path::{Path, PathBuf};
+
+/// A [`config::Source`] of configuration fragments, merged with drop-in precedence.
+///
+/// Fragments are scanned and merged as by [`scan_and_merge_value`] whenever the
+/// configuration is built, so that the whole drop-in tree is a single source, which
+/// can be layered below e.g. environment variables:
+///
+/// ```rust,no_run
+/// # use liboverdrop::{ConfigSource, Format, SYSTEMD_CONVENTIONAL_BASES};
+/// use config::{Config, Environment};
+///
+/// let config = Config::builder()
+///     .set_default("port", 8000)
+///     .unwrap()
+///     .add_source(ConfigSource::new(SYSTEMD_CONVENTIONAL_BASES, "my-crate/config.d", Format::Json))
+///     .add_source(Environment::with_prefix("MY_CRATE"))
+///     .build()
+///     .unwrap();
+/// let port: u16 = config.get("port").unwrap();
+/// ```
+///
+/// This is available with the `config` feature.
+#[derive(Clone, Debug)]
+pub struct ConfigSource {
+    base_dirs: Vec<PathBuf>,
+    shared_path: PathBuf,
+    format: Format,
+    arrays: ArrayMerge,
+}
+
+impl ConfigSource {
+    /// Create a source of the fragments in `format` under `shared_path`, see [`scan_and_merge_value`].
+    pub fn new<BdS, BdI, Sp>(base_dirs: BdI, shared_path: Sp, format: Format) -> Self
+    where
+        BdS: AsRef<Path>,
+        BdI: IntoIterator<Item = BdS>,
+        Sp: AsRef<Path>,
+    {
+        Self {
+            base_dirs: base_dirs
+                .into_iter()
+                .map(|dir| dir.as_ref().to_path_buf())
+                .collect(),
+            shared_path: shared_path.as_ref().to_path_buf(),
+            format,
+            arrays: ArrayMerge::default(),
+        }
+    }
+
+    /// Set how arrays of different fragments are merged.
+    #[must_use]
+    pub fn arrays(mut self, arrays: ArrayMerge) -> Self {
+        self.arrays = arrays;
+        self
+    }
+}
+
+impl Source for ConfigSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        let value =
+            scan_and_merge_value(&self.base_dirs, &self.shared_path, self.format, self.arrays)
+                .map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+        let origin = self.shared_path.display().to_string();
+        match to_value(&origin, value).kind {
+            ValueKind::Table(table) => Ok(table),
+            // `scan_and_merge_value()` always starts from a table.
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Convert a merged JSON value to a `config` value.
+fn to_value(origin: &String, value: serde_json::Value) -> Value {
+    let kind = match value {
+        serde_json::Value::Null => ValueKind::Nil,
+        serde_json::Value::Bool(b) => ValueKind::Boolean(b),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                ValueKind::I64(i)
+            } else if let Some(u) = n.as_u64() {
+                ValueKind::U64(u)
+            } else {
+                ValueKind::Float(n.as_f64().unwrap_or(f64::NAN))
+            }
+        }
+        serde_json::Value::String(s) => ValueKind::String(s),
+        serde_json::Value::Array(values) => ValueKind::Array(
+            values
+                .into_iter()
+                .map(|value| to_value(origin, value))
+                .collect(),
+        ),
+        serde_json::Value::Object(map) => ValueKind::Table(
+            map.into_iter()
+                .map(|(key, value)| (key, to_value(origin, value)))
+                .collect(),
+        ),
+    };
+    Value::new(Some(origin), kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TreeBuilder;
+    use config::Config;
+
+    #[test]
+    fn source() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/app.d/10-a.json", r#"{"a": 1, "b": {"x": 1}}"#)
+            .fragment(
+                "usr/lib/app.d/20-b.json",
+                r#"{"b": {"y": true, "z": "s"}, "c": [1]}"#,
+            )
+            .fragment("etc/app.d/10-a.json", r#"{"a": 2.5}"#)
+            .fragment("etc/app.d/30-c.json", r#"{"c": [2]}"#);
+        let source = ConfigSource::new(tree.base_dirs(["usr/lib", "etc"]), "app.d", Format::Json);
+
+        // The admin `10-a.json` replaces the vendor one, and other sources may still override.
+        let config = Config::builder()
+            .add_source(source.clone())
+            .set_override("b.z", "t")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(config.get::<f64>("a").unwrap(), 2.5);
+        assert!(config.get::<i64>("b.x").is_err());
+        assert!(config.get::<bool>("b.y").unwrap());
+        assert_eq!(config.get::<String>("b.z").unwrap(), "t");
+        assert_eq!(config.get::<Vec<i64>>("c").unwrap(), [2]);
+
+        let config = Config::builder()
+            .add_source(source.arrays(ArrayMerge::Append))
+            .build()
+            .unwrap();
+        assert_eq!(config.get::<Vec<i64>>("c").unwrap(), [1, 2]);
+
+        let tree = tree.fragment("run/app.d/40-d.json", "{");
+        let source = ConfigSource::new(tree.base_dirs(["usr/lib", "run"]), "app.d", Format::Json);
+        let err = Config::builder().add_source(source).build().unwrap_err();
+        assert!(err.to_string().contains("40-d.json"), "{}", err);
+    }
+}
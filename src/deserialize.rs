@@ -0,0 +1,286 @@
+//! Deserialization of configuration fragments with serde.
+
+use crate::scan;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Format of the configuration fragments, for [`scan_and_deserialize`].
+///
+/// Each format is available with the feature of the same name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    /// JSON documents, with the `.json` extension.
+    #[cfg(feature = "json")]
+    Json,
+}
+
+impl Format {
+    /// File extensions of fragments in this format.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            #[cfg(feature = "json")]
+            Format::Json => &["json"],
+        }
+    }
+
+    fn parse(self, reader: BufReader<File>) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        match self {
+            #[cfg(feature = "json")]
+            Format::Json => Ok(serde_json::from_reader(reader)?),
+        }
+    }
+}
+
+/// Error while deserializing configuration fragments, see [`scan_and_deserialize`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DeserializeError {
+    /// A fragment could not be read.
+    Io { path: PathBuf, source: io::Error },
+    /// A fragment could not be parsed in the chosen format.
+    Parse {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The merged fragments do not match the target type.
+    Invalid(serde_json::Error),
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeserializeError::Io { path, source } => {
+                write!(f, "failed to read '{}': {}", path.display(), source)
+            }
+            DeserializeError::Parse { path, source } => {
+                write!(f, "failed to parse '{}': {}", path.display(), source)
+            }
+            DeserializeError::Invalid(e) => write!(f, "invalid merged configuration: {}", e),
+        }
+    }
+}
+
+impl Error for DeserializeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DeserializeError::Io { source, .. } => Some(source),
+            DeserializeError::Parse { source, .. } => Some(source.as_ref()),
+            DeserializeError::Invalid(e) => Some(e),
+        }
+    }
+}
+
+/// How arrays are merged by [`scan_and_merge_value`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArrayMerge {
+    /// An array in a later fragment replaces the earlier one, as any other non-table value.
+    #[default]
+    Replace,
+    /// An array in a later fragment is appended to the earlier one.
+    Append,
+}
+
+/// Scan configuration fragments in the given `format`, and deep-merge them into a dynamic value.
+///
+/// Fragments are scanned as for [`scan`] (with the extensions of `format`, and
+/// ignoring dotfiles), parsed into a [`serde_json::Value`] and merged in the same
+/// order, starting from an empty table:
+///
+/// - tables (e.g. JSON objects) are merged recursively, key by key;
+/// - arrays are replaced, or appended to with [`ArrayMerge::Append`];
+/// - any other value (including `null`, or a value of a different type) in a later
+///   fragment replaces the earlier one.
+///
+/// The first error stops processing and is returned.
+///
+/// The merged value can also be handed to other configuration frameworks as a single
+/// source with drop-in precedence already applied, e.g. to figment with the
+/// `FigmentProvider` of the `figment` feature, or to the `config` crate with the
+/// `ConfigSource` of the `config` feature, layered below environment or command-line
+/// sources.
+pub fn scan_and_merge_value<BdS, BdI, Sp>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    format: Format,
+    arrays: ArrayMerge,
+) -> Result<Value, DeserializeError>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+{
+    let fragments = scan(base_dirs, shared_path, format.extensions(), true);
+    let mut merged = Value::Object(Map::new());
+    for path in fragments.into_values() {
+        let file = File::open(&path).map_err(|source| DeserializeError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let value = format
+            .parse(BufReader::new(file))
+            .map_err(|source| DeserializeError::Parse { path, source })?;
+        merge_value(&mut merged, value, arrays);
+    }
+    Ok(merged)
+}
+
+/// Scan configuration fragments in the given `format`, and deserialize them into a single value.
+///
+/// Fragments are merged field by field as by [`scan_and_merge_value`] (replacing
+/// arrays), and the merged document is then deserialized into `T`, so fields can be
+/// spread across fragments, and missing ones can use `#[serde(default)]`.
+///
+/// This is available with the feature of at least one [`Format`].
+pub fn scan_and_deserialize<T, BdS, BdI, Sp>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    format: Format,
+) -> Result<T, DeserializeError>
+where
+    T: DeserializeOwned,
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+{
+    let merged = scan_and_merge_value(base_dirs, shared_path, format, ArrayMerge::Replace)?;
+    serde_json::from_value(merged).map_err(DeserializeError::Invalid)
+}
+
+/// Merge `value` into `merged`, recursively for tables.
+fn merge_value(merged: &mut Value, value: Value, arrays: ArrayMerge) {
+    match (merged, value) {
+        (Value::Object(merged), Value::Object(table)) => {
+            for (key, value) in table {
+                match merged.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value, arrays),
+                    None => {
+                        merged.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(merged), Value::Array(items)) if arrays == ArrayMerge::Append => {
+            merged.extend(items);
+        }
+        (merged, value) => *merged = value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TreeBuilder;
+    use serde::de::{self, Deserializer, MapAccess, Visitor};
+
+    /// A hand-written `Deserialize` target, as serde's derive macros are not used by this crate.
+    #[derive(Debug, Default, PartialEq)]
+    struct Settings {
+        interval: u64,
+        names: Vec<String>,
+        nested: Option<Value>,
+    }
+
+    impl<'de> serde::Deserialize<'de> for Settings {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct SettingsVisitor;
+            impl<'de> Visitor<'de> for SettingsVisitor {
+                type Value = Settings;
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("settings")
+                }
+                fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Settings, A::Error> {
+                    let mut settings = Settings::default();
+                    while let Some(key) = map.next_key::<String>()? {
+                        match key.as_str() {
+                            "interval" => settings.interval = map.next_value()?,
+                            "names" => settings.names = map.next_value()?,
+                            "nested" => settings.nested = map.next_value()?,
+                            _ => return Err(de::Error::unknown_field(&key, &[])),
+                        }
+                    }
+                    Ok(settings)
+                }
+            }
+            deserializer.deserialize_map(SettingsVisitor)
+        }
+    }
+
+    #[test]
+    fn deserialize_merged() {
+        let tree = TreeBuilder::new()
+            .fragment(
+                "usr/lib/svc.d/10-base.json",
+                r#"{"interval": 5, "names": ["a"], "nested": {"x": 1, "y": 2}}"#,
+            )
+            .fragment("usr/lib/svc.d/20-names.json", r#"{"names": ["b"]}"#)
+            .fragment("etc/svc.d/30-nested.json", r#"{"nested": {"y": 3}}"#)
+            .fragment("etc/svc.d/.40-hidden.json", r#"{"interval": 0}"#);
+        let dirs = tree.base_dirs(["usr/lib", "etc"]);
+        let settings: Settings = scan_and_deserialize(&dirs, "svc.d", Format::Json).unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                interval: 5,
+                names: vec!["b".into()],
+                nested: Some(serde_json::json!({"x": 1, "y": 3})),
+            }
+        );
+
+        let empty: Settings = scan_and_deserialize(&dirs, "missing.d", Format::Json).unwrap();
+        assert_eq!(empty, Settings::default());
+    }
+
+    #[test]
+    fn deserialize_errors() {
+        let tree = TreeBuilder::new().fragment("etc/svc.d/10-broken.json", "{");
+        let err = scan_and_deserialize::<Settings, _, _, _>(
+            tree.base_dirs(["etc"]),
+            "svc.d",
+            Format::Json,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&err, DeserializeError::Parse { path, .. } if path.ends_with("10-broken.json"))
+        );
+
+        let tree = TreeBuilder::new().fragment("etc/svc.d/10-typo.json", r#"{"intervall": 1}"#);
+        let err = scan_and_deserialize::<Settings, _, _, _>(
+            tree.base_dirs(["etc"]),
+            "svc.d",
+            Format::Json,
+        )
+        .unwrap_err();
+        assert!(matches!(err, DeserializeError::Invalid(_)), "{}", err);
+    }
+
+    #[test]
+    fn deep_merge_value() {
+        let tree = TreeBuilder::new()
+            .fragment(
+                "usr/lib/svc.d/10-base.json",
+                r#"{"a": {"b": 1, "c": [1]}, "d": "x", "e": {"f": 1}}"#,
+            )
+            .fragment(
+                "etc/svc.d/20-admin.json",
+                r#"{"a": {"c": [2], "g": null}, "e": 2}"#,
+            );
+        let dirs = tree.base_dirs(["usr/lib", "etc"]);
+        let replaced = scan_and_merge_value(&dirs, "svc.d", Format::Json, ArrayMerge::Replace);
+        assert_eq!(
+            replaced.unwrap(),
+            serde_json::json!({"a": {"b": 1, "c": [2], "g": null}, "d": "x", "e": 2})
+        );
+        let appended = scan_and_merge_value(&dirs, "svc.d", Format::Json, ArrayMerge::Append);
+        assert_eq!(
+            appended.unwrap(),
+            serde_json::json!({"a": {"b": 1, "c": [1, 2], "g": null}, "d": "x", "e": 2})
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Comparison of scan results, by filename.
+
+use crate::Fragment;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Differences between two scan results, by filename.
+///
+/// See [`diff`] and [`FragmentSet::diff`](crate::FragmentSet::diff). All lists are
+/// in alphanumeric order by filename.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanDiff {
+    added: Vec<OsString>,
+    removed: Vec<OsString>,
+    repointed: Vec<OsString>,
+    modified: Vec<OsString>,
+}
+
+impl ScanDiff {
+    /// Filenames with a fragment only in the new result.
+    pub fn added(&self) -> &[OsString] {
+        &self.added
+    }
+
+    /// Filenames with a fragment only in the old result (removed or masked since).
+    pub fn removed(&self) -> &[OsString] {
+        &self.removed
+    }
+
+    /// Filenames whose effective fragment moved to another path (e.g. overridden in a higher layer).
+    pub fn repointed(&self) -> &[OsString] {
+        &self.repointed
+    }
+
+    /// Filenames whose effective fragment kept its path, but whose modification time or size changed.
+    ///
+    /// This is only detected by [`FragmentSet::diff`](crate::FragmentSet::diff).
+    pub fn modified(&self) -> &[OsString] {
+        &self.modified
+    }
+
+    /// Whether both results hold the same fragments.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.repointed.is_empty()
+            && self.modified.is_empty()
+    }
+
+    /// All changed filenames, in alphanumeric order.
+    pub fn changed(&self) -> Vec<&OsString> {
+        let names: BTreeSet<_> = self
+            .added
+            .iter()
+            .chain(&self.removed)
+            .chain(&self.repointed)
+            .chain(&self.modified)
+            .collect();
+        names.into_iter().collect()
+    }
+}
+
+/// Compare two scan results, as returned by [`scan`](crate::scan).
+///
+/// Only paths are compared: use [`FragmentSet::diff`](crate::FragmentSet::diff) to
+/// also detect fragments modified in place.
+///
+/// ```rust
+/// # use std::path::PathBuf;
+/// # use std::collections::BTreeMap;
+/// let old = BTreeMap::from([("10-a.conf".into(), PathBuf::from("/usr/lib/my-crate/10-a.conf"))]);
+/// let new = BTreeMap::from([("10-a.conf".into(), PathBuf::from("/etc/my-crate/10-a.conf"))]);
+/// let diff = liboverdrop::diff(&old, &new);
+/// assert_eq!(diff.repointed(), ["10-a.conf"]);
+/// assert!(diff.added().is_empty() && diff.removed().is_empty());
+/// ```
+pub fn diff(old: &BTreeMap<OsString, PathBuf>, new: &BTreeMap<OsString, PathBuf>) -> ScanDiff {
+    diff_maps(old, new, |path| path, |_, _| false)
+}
+
+/// Compare fragment sets, also detecting fragments modified in place.
+pub(crate) fn diff_fragments(
+    old: &BTreeMap<OsString, Fragment>,
+    new: &BTreeMap<OsString, Fragment>,
+) -> ScanDiff {
+    diff_maps(old, new, Fragment::path, |a, b| a != b)
+}
+
+fn diff_maps<T>(
+    old: &BTreeMap<OsString, T>,
+    new: &BTreeMap<OsString, T>,
+    path: impl Fn(&T) -> &Path,
+    modified: impl Fn(&T, &T) -> bool,
+) -> ScanDiff {
+    let mut diff = ScanDiff::default();
+    let names: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    for name in names {
+        let list = match (old.get(name), new.get(name)) {
+            (None, Some(_)) => &mut diff.added,
+            (Some(_), None) => &mut diff.removed,
+            (Some(a), Some(b)) if path(a) != path(b) => &mut diff.repointed,
+            (Some(a), Some(b)) if modified(a, b) => &mut diff.modified,
+            _ => continue,
+        };
+        list.push(name.clone());
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TreeBuilder;
+    use crate::Overdrop;
+    use std::fs;
+
+    #[test]
+    fn scan_diffs() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "a=1\n")
+            .fragment("usr/lib/svc.d/20-b.conf", "b=1\n")
+            .fragment("usr/lib/svc.d/30-c.conf", "c=1\n");
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "etc"]));
+        let old = scanner.scan_fragments().unwrap();
+        assert!(old.diff(&old).is_empty());
+
+        let tree = tree
+            .fragment("etc/svc.d/10-a.conf", "a=2\n")
+            .mask("etc/svc.d/20-b.conf")
+            .fragment("etc/svc.d/40-d.conf", "d=1\n");
+        fs::write(tree.path("usr/lib/svc.d/30-c.conf"), "c=22\n").unwrap();
+        let new = scanner.scan_fragments().unwrap();
+        let changes = old.diff(&new);
+        assert_eq!(changes.added(), ["40-d.conf"]);
+        assert_eq!(changes.removed(), ["20-b.conf"]);
+        assert_eq!(changes.repointed(), ["10-a.conf"]);
+        assert_eq!(changes.modified(), ["30-c.conf"]);
+        assert_eq!(
+            changes.changed(),
+            ["10-a.conf", "20-b.conf", "30-c.conf", "40-d.conf"]
+        );
+
+        // Plain maps only hold paths.
+        let changes = diff(&old.into_paths(), &new.into_paths());
+        assert_eq!(changes.repointed(), ["10-a.conf"]);
+        assert!(changes.modified().is_empty());
+        assert_eq!(changes.changed().len(), 3);
+    }
+}
@@ -0,0 +1,256 @@
+//! Stable digests of the effective configuration.
+
+use crate::{FragmentSet, Source};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// A SHA-256 digest of an effective fragment set, e.g. as a configuration generation identifier.
+///
+/// The digest covers, in filename order, the filename, winning path and contents of
+/// each fragment, so it changes whenever any of them does, and is the same across
+/// runs, hosts and versions of this crate otherwise. Its `Display` implementation
+/// renders it as lowercase hexadecimal.
+///
+/// See [`FragmentSet::digest`] and [`Overdrop::digest`](crate::Overdrop::digest).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest([u8; 32]);
+
+impl Digest {
+    /// The raw digest bytes.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Incremental computation of a [`Digest`], one fragment at a time.
+#[derive(Default)]
+struct DigestBuilder {
+    hasher: Sha256,
+}
+
+impl DigestBuilder {
+    /// Add a fragment, with its winning path (if not synthetic) and contents.
+    fn add(
+        &mut self,
+        name: &OsStr,
+        path: Option<&Path>,
+        contents: &mut dyn Read,
+    ) -> io::Result<()> {
+        let mut buf = Vec::new();
+        contents.read_to_end(&mut buf)?;
+        // Fields are length-prefixed, so that their boundaries are unambiguous.
+        self.field(name.to_string_lossy().as_bytes());
+        match path {
+            Some(path) => {
+                self.hasher.update(&[1]);
+                self.field(path.to_string_lossy().as_bytes());
+            }
+            None => self.hasher.update(&[0]),
+        }
+        self.field(&buf);
+        Ok(())
+    }
+
+    fn field(&mut self, data: &[u8]) {
+        self.hasher.update(&(data.len() as u64).to_be_bytes());
+        self.hasher.update(data);
+    }
+
+    fn finish(self) -> Digest {
+        Digest(self.hasher.finish())
+    }
+}
+
+impl FragmentSet {
+    /// Compute the [`Digest`] of these fragments, reading all of them.
+    ///
+    /// Fragments are opened with [`Fragment::open`](crate::Fragment::open), so this
+    /// fails if any of them was replaced since the scan.
+    pub fn digest(&self) -> io::Result<Digest> {
+        let mut digest = DigestBuilder::default();
+        for (name, fragment) in self {
+            digest.add(name, Some(fragment.path()), &mut fragment.open()?)?;
+        }
+        Ok(digest.finish())
+    }
+}
+
+/// Compute the [`Digest`] of `sources`, returning the first error with the path it occurred on.
+pub(crate) fn digest_sources(
+    sources: &BTreeMap<OsString, Source>,
+) -> Result<Digest, (&Path, io::Error)> {
+    let mut digest = DigestBuilder::default();
+    for (name, source) in sources {
+        let path = source.fragment().map(|f| f.path());
+        let res = source
+            .open()
+            .and_then(|mut reader| digest.add(name, path, &mut reader));
+        if let Err(e) = res {
+            return Err((path.unwrap_or_else(|| Path::new(name)), e));
+        }
+    }
+    Ok(digest.finish())
+}
+
+/// A minimal SHA-256 implementation (FIPS 180-4).
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let len = data.len().min(64 - self.block_len);
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&data[..len]);
+            self.block_len += len;
+            data = &data[len..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Overdrop;
+
+    fn sha256_hex(data: &[u8]) -> String {
+        let mut hasher = Sha256::default();
+        hasher.update(data);
+        Digest(hasher.finish()).to_string()
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256_hex(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn fragment_digest() {
+        let treedir = Path::new("tests/fixtures/tree-merge");
+        let scanner =
+            Overdrop::new("liboverdrop.d").base_dirs(["usr/lib", "etc"].map(|d| treedir.join(d)));
+        let fragments = scanner.scan_fragments().unwrap();
+        let digest = fragments.digest().unwrap();
+        assert_eq!(digest, scanner.digest().unwrap());
+        assert_eq!(digest.to_string().len(), 64);
+
+        let runtime = scanner
+            .clone()
+            .base_dirs(["usr/lib", "etc", "run"].map(|d| treedir.join(d)));
+        assert_ne!(runtime.digest().unwrap(), digest);
+        let injected = scanner.inject("99-extra.conf", "", 2);
+        assert_ne!(injected.digest().unwrap(), digest);
+        assert_eq!(
+            FragmentSet::default().digest().unwrap().to_string(),
+            sha256_hex(b"")
+        );
+    }
+}
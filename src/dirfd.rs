@@ -0,0 +1,409 @@
+//! Scanning relative to already-open directory file descriptors.
+//!
+//! This module is available on Unix with the `dirfd` feature. It allows callers
+//! which receive base directories as file descriptors (e.g. over fd-passing from
+//! a container runtime or a sandbox broker) to scan configuration fragments
+//! without any path access: all operations are performed relative to the given
+//! descriptors, with the same override and masking rules as [`scan`](crate::scan).
+//! Paths are resolved beneath their base directory: none of their components may
+//! be a symlink or `..`, and mask symlink chains are read (but not followed) as if
+//! the base directory were the filesystem root.
+
+use crate::{
+    read_dir_entries, DirOptions, EntryKind, EntryNames, FragmentSource, LayerPolicy, Resolver,
+};
+use log::trace;
+use rustix::fs::{AtFlags, Dir, FileType, Mode, OFlags};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+/// A unique configuration fragment, located relative to one of the base directory descriptors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FdFragment {
+    layer: usize,
+    path: PathBuf,
+}
+
+impl FdFragment {
+    /// Index of the base directory (in the order passed to [`scan_fds`]) holding the fragment.
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+
+    /// Path of the fragment, relative to its base directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open the fragment for reading, relative to its base directory.
+    ///
+    /// `base_dirs` must be the same descriptors that were passed to [`scan_fds`].
+    /// No path component is followed if it is a symlink.
+    pub fn open<Fd: AsFd>(&self, base_dirs: &[Fd]) -> io::Result<File> {
+        let base = base_dirs
+            .get(self.layer)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing base directory"))?;
+        let (dir, name) = Beneath(base.as_fd()).parent(&self.path)?;
+        let fd = rustix::fs::openat(
+            &dir,
+            name,
+            OFlags::RDONLY | OFlags::CLOEXEC | OFlags::NOFOLLOW,
+            Mode::empty(),
+        )?;
+        Ok(File::from(fd))
+    }
+}
+
+/// Scan unique configuration fragments, relative to base directory descriptors.
+///
+/// This works like [`scan`](crate::scan), with `base_dirs` given as open directory
+/// descriptors (in increasing priority order). `shared_path` is resolved relative to
+/// each of them; an empty `shared_path` scans the base directories themselves.
+///
+/// Returns a `BTreeMap` indexed by configuration fragment filename, holding the
+/// location of the unique configuration fragment relative to its base directory.
+pub fn scan_fds<Sp: AsRef<Path>, As: AsRef<OsStr>>(
+    base_dirs: &[BorrowedFd<'_>],
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> BTreeMap<OsString, FdFragment> {
+    let shared_path = shared_path.as_ref();
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+
+    let mut resolver = Resolver::new();
+    for (layer, base) in base_dirs.iter().enumerate() {
+        // Missing or unreadable directories are simply skipped.
+        let _ = scan_fd_into(
+            &mut resolver,
+            *base,
+            Path::new(""),
+            layer,
+            shared_path,
+            &options,
+        );
+    }
+    resolver.into_map()
+}
+
+/// Scan unique configuration fragments, with base directories relative to an open directory.
+///
+/// This works like [`scan`](crate::scan), with each entry of `base_dirs` (e.g.
+/// [`SYSTEMD_CONVENTIONAL_BASES`](crate::SYSTEMD_CONVENTIONAL_BASES)) resolved relative
+/// to `dir` instead of the filesystem root, ignoring any leading `/`. This allows
+/// sandboxed services to scan a tree they were handed a descriptor for, even if
+/// the tree is not reachable (or is being swapped) at its usual path. Symlinks
+/// (e.g. absolute ones) are never followed out of `dir`: base directories behind
+/// a symlink, or containing `..`, are skipped.
+///
+/// Returns a `BTreeMap` indexed by configuration fragment filename, holding the
+/// path of the unique configuration fragment relative to `dir`, e.g. for `openat`.
+pub fn scan_at<BdS, BdI, Sp, As>(
+    dir: BorrowedFd<'_>,
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> BTreeMap<OsString, PathBuf>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let shared_path = shared_path.as_ref();
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+
+    let bases: Vec<PathBuf> = base_dirs
+        .into_iter()
+        .map(|base| {
+            base.as_ref()
+                .components()
+                .filter(|c| !matches!(c, Component::RootDir))
+                .collect()
+        })
+        .collect();
+    let mut resolver = Resolver::new();
+    for (layer, base) in bases.iter().enumerate() {
+        // Missing or unreadable directories are simply skipped.
+        let _ = scan_fd_into(&mut resolver, dir, base, layer, shared_path, &options);
+    }
+    resolver
+        .into_map()
+        .into_iter()
+        .map(|(name, fragment)| (name, bases[fragment.layer].join(fragment.path)))
+        .collect()
+}
+
+/// Open the directory at `shared_path` beneath `base`, one component at a time.
+///
+/// Symlinks are not followed, and `..` or absolute paths are rejected, so that
+/// the directory cannot be outside of `base`.
+fn open_dir(base: BorrowedFd<'_>, shared_path: &Path) -> io::Result<OwnedFd> {
+    let flags = OFlags::RDONLY | OFlags::DIRECTORY | OFlags::CLOEXEC | OFlags::NOFOLLOW;
+    let mut fd = rustix::fs::openat(base, ".", flags, Mode::empty())?;
+    for component in shared_path.components() {
+        match component {
+            Component::CurDir => continue,
+            Component::Normal(name) => fd = rustix::fs::openat(&fd, name, flags, Mode::empty())?,
+            _ => return Err(beneath_error()),
+        }
+    }
+    Ok(fd)
+}
+
+fn beneath_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "path is not beneath its base directory",
+    )
+}
+
+/// The tree beneath a directory descriptor, as a [`FragmentSource`].
+///
+/// Paths are absolute, with `/` standing for the directory itself.
+struct Beneath<'fd>(BorrowedFd<'fd>);
+
+impl Beneath<'_> {
+    /// Open the parent directory of `path`, returning it with the final component of `path`.
+    fn parent<'p>(&self, path: &'p Path) -> io::Result<(OwnedFd, &'p OsStr)> {
+        let path = path.strip_prefix("/").unwrap_or(path);
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => Ok((open_dir(self.0, parent)?, name)),
+            _ => Err(beneath_error()),
+        }
+    }
+}
+
+impl FragmentSource for Beneath<'_> {
+    fn list(&self, dir: &Path) -> io::Result<EntryNames<'_>> {
+        let dir_fd = open_dir(self.0, dir.strip_prefix("/").unwrap_or(dir))?;
+        let entries = Dir::read_from(&dir_fd)?;
+        Ok(Box::new(entries.filter_map(|entry| match entry {
+            Ok(entry) => {
+                let fname = OsStr::from_bytes(entry.file_name().to_bytes());
+                (fname != "." && fname != "..").then(|| Ok(fname.to_os_string()))
+            }
+            Err(e) => Some(Err(e.into())),
+        })))
+    }
+
+    fn entry_kind(&self, path: &Path) -> io::Result<EntryKind> {
+        let (dir, name) = self.parent(path)?;
+        let stat = rustix::fs::statat(&dir, name, AtFlags::SYMLINK_NOFOLLOW)?;
+        Ok(match FileType::from_raw_mode(stat.st_mode) {
+            FileType::RegularFile => EntryKind::File {
+                len: stat.st_size as u64,
+            },
+            FileType::Directory => EntryKind::Dir,
+            FileType::Symlink => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        let (dir, name) = self.parent(path)?;
+        let fd = rustix::fs::openat(
+            &dir,
+            name,
+            OFlags::RDONLY | OFlags::CLOEXEC | OFlags::NOFOLLOW,
+            Mode::empty(),
+        )?;
+        Ok(Box::new(File::from(fd)))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        let (dir, name) = self.parent(path)?;
+        let target = rustix::fs::readlinkat(&dir, name, Vec::new())?;
+        Ok(OsStr::from_bytes(target.as_bytes()).into())
+    }
+}
+
+/// Scan `shared_path` in the `base` directory (relative to `root`), as `layer`.
+fn scan_fd_into(
+    resolver: &mut Resolver<FdFragment>,
+    root: BorrowedFd<'_>,
+    base: &Path,
+    layer: usize,
+    shared_path: &Path,
+    options: &DirOptions,
+) -> io::Result<()> {
+    trace!(
+        "Scanning directory '{}' in layer {}",
+        shared_path.display(),
+        layer
+    );
+
+    // Mask symlink chains are resolved as if `root` were the filesystem root.
+    let base = Path::new("/").join(base);
+    read_dir_entries(
+        &Beneath(root),
+        &base.join(shared_path),
+        options,
+        |fname, entry| {
+            let entry = entry.map(|fragment| {
+                let path = fragment
+                    .path()
+                    .strip_prefix(&base)
+                    .unwrap_or(fragment.path());
+                FdFragment {
+                    layer,
+                    path: path.to_path_buf(),
+                }
+            });
+            resolver.apply(LayerPolicy::default(), fname, entry)
+        },
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TreeBuilder;
+
+    fn open_layers(treedir: &str, dirs: &[&str]) -> Vec<File> {
+        dirs.iter()
+            .map(|d| File::open(Path::new(treedir).join(d)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn fds_match_scan() {
+        let treedir = "tests/fixtures/tree-basic";
+        let dirs = ["usr/lib", "run", "etc"];
+        let files = open_layers(treedir, &dirs);
+        let fds: Vec<_> = files.iter().map(|f| f.as_fd()).collect();
+
+        let fragments = scan_fds(&fds, "liboverdrop.d", &["toml"], false);
+        let expected = crate::scan(
+            dirs.map(|d| Path::new(treedir).join(d)),
+            "liboverdrop.d",
+            &["toml"],
+            false,
+        );
+        assert_eq!(fragments.len(), expected.len());
+        for (name, fragment) in &fragments {
+            let full = Path::new(treedir)
+                .join(dirs[fragment.layer()])
+                .join(fragment.path());
+            assert_eq!(expected[name], full);
+        }
+    }
+
+    #[test]
+    fn fds_mask_and_open() {
+        let files = open_layers("tests/fixtures/tree-mask", &["usr/lib", "etc", "run"]);
+        let fds: Vec<_> = files.iter().map(|f| f.as_fd()).collect();
+
+        let fragments = scan_fds::<_, &str>(&fds, "liboverdrop.d", &[], false);
+        let names: Vec<_> = fragments.keys().collect();
+        assert_eq!(names, ["10-a.conf", "30-c.conf"]);
+
+        let fragment = &fragments[OsStr::new("10-a.conf")];
+        assert_eq!(fragment.layer(), 2);
+        let mut contents = String::new();
+        fragment
+            .open(&fds)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "a=runtime\n");
+
+        let layers = open_layers("tests/fixtures/tree-mask/usr/lib", &["liboverdrop.d"]);
+        let fds: Vec<_> = layers.iter().map(|f| f.as_fd()).collect();
+        let fragments = scan_fds::<_, &str>(&fds, "", &[], false);
+        assert_eq!(fragments.len(), 3);
+    }
+
+    #[test]
+    fn chained_masks() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "")
+            .fragment("usr/lib/svc.d/20-b.conf", "")
+            .fragment("usr/lib/svc.d/30-c.conf", "")
+            .symlink("etc/svc.d/10-a.conf", "../null-mask")
+            .mask("etc/null-mask")
+            .symlink("etc/svc.d/20-b.conf", "/etc/abs-mask")
+            .mask("etc/abs-mask")
+            .symlink("etc/svc.d/30-c.conf", "../../etc/not-a-mask")
+            .fragment("etc/not-a-mask", "");
+
+        // Absolute targets are resolved relative to the scanned tree.
+        let root = File::open(tree.root()).unwrap();
+        let fragments = scan_at(
+            root.as_fd(),
+            ["/usr/lib", "/etc"],
+            "svc.d",
+            &["conf"],
+            false,
+        );
+        let names: Vec<_> = fragments.keys().collect();
+        assert_eq!(names, ["30-c.conf"]);
+
+        // ... and relative to each base directory.
+        let files = open_layers(tree.root().to_str().unwrap(), &["usr/lib", "etc"]);
+        let fds: Vec<_> = files.iter().map(|f| f.as_fd()).collect();
+        let fragments = scan_fds(&fds, "svc.d", &["conf"], false);
+        let names: Vec<_> = fragments.keys().collect();
+        assert_eq!(names, ["20-b.conf", "30-c.conf"]);
+    }
+
+    #[test]
+    fn scan_beneath_dir() {
+        let outside = TreeBuilder::new().fragment("svc.d/20-outside.conf", "");
+        let tree = TreeBuilder::new()
+            .fragment("root/usr/lib/svc.d/10-a.conf", "")
+            .fragment("root/opt/svc.d/30-c.conf", "")
+            .symlink("root/etc", outside.root())
+            .symlink("root/run", "opt")
+            .symlink("root/usr/local", "/");
+        let root = File::open(tree.path("root")).unwrap();
+
+        // Neither an absolute symlink, a relative one nor `..` may be followed out of the tree.
+        let fragments = scan_at(
+            root.as_fd(),
+            ["/usr/lib", "/etc", "/run", "/usr/local/tmp", "../root/opt"],
+            "svc.d",
+            &["conf"],
+            false,
+        );
+        let names: Vec<_> = fragments.keys().collect();
+        assert_eq!(names, ["10-a.conf"]);
+
+        let fragments = scan_fds::<_, &str>(&[root.as_fd()], "etc/svc.d", &[], false);
+        assert!(fragments.is_empty());
+    }
+
+    #[test]
+    fn scan_relative_to_dir() {
+        let treedir = Path::new("tests/fixtures/tree-mask");
+        let root = File::open(treedir).unwrap();
+        let fragments = scan_at(
+            root.as_fd(),
+            crate::SYSTEMD_CONVENTIONAL_BASES,
+            "liboverdrop.d",
+            &["conf"],
+            false,
+        );
+        let expected = crate::scan(
+            ["usr/lib", "etc", "run"].map(|d| treedir.join(d)),
+            "liboverdrop.d",
+            &["conf"],
+            false,
+        );
+        assert_eq!(fragments.len(), expected.len());
+        for (name, path) in &fragments {
+            assert_eq!(expected[name], treedir.join(path));
+        }
+        assert_eq!(
+            fragments[OsStr::new("10-a.conf")],
+            Path::new("run/liboverdrop.d/10-a.conf")
+        );
+    }
+}
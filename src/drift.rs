@@ -0,0 +1,143 @@
+//! Detection of local configuration drift from vendor defaults.
+
+use crate::{scan, scan_with_masks};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// How the effective configuration differs from the vendor one, see [`scan_drift`].
+///
+/// All maps are indexed by fragment filename.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Drift {
+    vendor: BTreeMap<OsString, PathBuf>,
+    overridden: BTreeMap<OsString, PathBuf>,
+    masked: BTreeMap<OsString, PathBuf>,
+    added: BTreeMap<OsString, PathBuf>,
+}
+
+impl Drift {
+    /// Vendor fragments, as if only the vendor layers were scanned.
+    pub fn vendor(&self) -> &BTreeMap<OsString, PathBuf> {
+        &self.vendor
+    }
+
+    /// Vendor fragments overridden in a higher layer, with the path of the effective fragment.
+    pub fn overridden(&self) -> &BTreeMap<OsString, PathBuf> {
+        &self.overridden
+    }
+
+    /// Vendor fragments masked in a higher layer, with the path of the effective mask.
+    pub fn masked(&self) -> &BTreeMap<OsString, PathBuf> {
+        &self.masked
+    }
+
+    /// Fragments only found in higher layers, with their path.
+    pub fn added(&self) -> &BTreeMap<OsString, PathBuf> {
+        &self.added
+    }
+
+    /// Whether the effective configuration is the vendor one.
+    pub fn is_empty(&self) -> bool {
+        self.overridden.is_empty() && self.masked.is_empty() && self.added.is_empty()
+    }
+}
+
+/// Scan configuration fragments, comparing the vendor layers to the effective configuration.
+///
+/// Arguments are the same as for [`scan`], with the first `vendor_layers` entries of
+/// `base_dirs` being the vendor (i.e. OS image) layers. Vendor fragments are those
+/// found by scanning only these layers; the fragments found by scanning all of them
+/// are then sorted into overridden and masked vendor fragments, and fragments added
+/// by the higher (e.g. admin) layers. This allows showing the local drift of a system,
+/// e.g. after an OS update:
+///
+/// ```rust,no_run
+/// let bases = ["/usr/lib", "/etc", "/run"];
+/// let drift = liboverdrop::scan_drift(bases, 1, "my-crate/config.d", &["conf"], true);
+/// for (name, mask) in drift.masked() {
+///     println!("{} masked by {}", name.to_string_lossy(), mask.display());
+/// }
+/// ```
+pub fn scan_drift<BdS, BdI, Sp, As>(
+    base_dirs: BdI,
+    vendor_layers: usize,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> Drift
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let base_dirs: Vec<_> = base_dirs.into_iter().collect();
+    let shared_path = shared_path.as_ref();
+    let vendor_dirs = base_dirs.iter().take(vendor_layers);
+    let vendor = scan(
+        vendor_dirs,
+        shared_path,
+        allowed_extensions,
+        ignore_dotfiles,
+    );
+    let (effective, mut masks) =
+        scan_with_masks(&base_dirs, shared_path, allowed_extensions, ignore_dotfiles);
+
+    let mut drift = Drift::default();
+    for (name, path) in effective {
+        match vendor.get(&name) {
+            Some(vendor_path) if *vendor_path == path => {}
+            Some(_) => {
+                drift.overridden.insert(name, path);
+            }
+            None => {
+                drift.added.insert(name, path);
+            }
+        }
+    }
+    for name in vendor.keys() {
+        if let Some(mask) = masks.remove(name) {
+            drift.masked.insert(name.clone(), mask);
+        }
+    }
+    drift.vendor = vendor;
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TreeBuilder;
+
+    #[test]
+    fn vendor_drift() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .fragment("usr/lib/svc.d/20-b.conf", "vendor")
+            .fragment("usr/lib/svc.d/30-c.conf", "vendor")
+            .fragment("etc/svc.d/10-a.conf", "admin")
+            .mask("etc/svc.d/20-b.conf")
+            .mask("etc/svc.d/35-none.conf")
+            .fragment("run/svc.d/40-d.conf", "runtime");
+        let dirs = tree.base_dirs(["usr/lib", "etc", "run"]);
+        let drift = scan_drift(&dirs, 1, "svc.d", &["conf"], false);
+        let names = |map: &BTreeMap<OsString, PathBuf>| map.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            names(drift.vendor()),
+            ["10-a.conf", "20-b.conf", "30-c.conf"]
+        );
+        assert_eq!(
+            drift.overridden(),
+            &BTreeMap::from([("10-a.conf".into(), tree.path("etc/svc.d/10-a.conf"))])
+        );
+        assert_eq!(
+            drift.masked(),
+            &BTreeMap::from([("20-b.conf".into(), tree.path("etc/svc.d/20-b.conf"))])
+        );
+        assert_eq!(names(drift.added()), ["40-d.conf"]);
+        assert!(!drift.is_empty());
+
+        assert!(scan_drift(&dirs, 3, "svc.d", &["conf"], false).is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+//! Main configuration files with drop-in directories.
+
+use crate::{classify_candidate, scan, DirOptions, DirScan, Entry, StdFs};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// Scan a main configuration file together with its drop-in directories.
+///
+/// This follows the common systemd convention of a single main file (e.g. `foo.conf`)
+/// plus drop-ins with a given suffix in the matching `.d` directory (e.g. `foo.conf.d/*.conf`),
+/// with `main_name` interpreted relative to each entry of `base_dirs`.
+///
+/// The main file in the highest-priority base directory wins, unless that one is masked
+/// (i.e. a symlink to `/dev/null`). Drop-ins are scanned as [`scan`] does, with
+/// `dropin_suffix` as the only allowed extension. Returns the main file, if any, and
+/// the drop-ins, which are meant to be processed after it in the returned order.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// let (main, dropins) = liboverdrop::scan_main_and_dropins(
+///     ["/usr/lib", "/etc"],
+///     "foo.conf",
+///     "conf",
+///     true,
+/// );
+/// for path in main.iter().chain(dropins.values()) {
+///     println!("Loading {}", path.display());
+/// }
+/// ```
+pub fn scan_main_and_dropins<BdS, BdI, Mn, Ds>(
+    base_dirs: BdI,
+    main_name: Mn,
+    dropin_suffix: Ds,
+    ignore_dotfiles: bool,
+) -> (Option<PathBuf>, BTreeMap<OsString, PathBuf>)
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Mn: AsRef<Path>,
+    Ds: AsRef<OsStr>,
+{
+    let main_name = main_name.as_ref();
+    let base_dirs: Vec<_> = base_dirs.into_iter().collect();
+
+    let options = DirOptions::default();
+    let mut main = None;
+    for base in &base_dirs {
+        let fpath = base.as_ref().join(main_name);
+        let entry = classify_candidate(&StdFs, fpath, &options, &mut DirScan::default());
+        match entry.ok().flatten() {
+            Some(Entry::Fragment(fragment) | Entry::Empty(fragment)) => {
+                main = Some(fragment.into_path())
+            }
+            Some(Entry::Mask) => main = None,
+            None => {}
+        }
+    }
+
+    let mut dropin_dir = main_name.as_os_str().to_os_string();
+    dropin_dir.push(".d");
+    let dropins = scan(&base_dirs, dropin_dir, &[dropin_suffix], ignore_dotfiles);
+
+    (main, dropins)
+}
+
+/// Scan the drop-ins of a systemd unit, e.g. `foo@bar.service`, following its fallback chain.
+///
+/// Each entry of `base_dirs` is a unit search directory (e.g. `/etc/systemd/system`).
+/// Drop-in directories are looked up for, in increasing priority order:
+///
+/// * the unit type, e.g. `service.d`;
+/// * each dash-separated prefix of the unit name, e.g. `foo-.service.d` for `foo-bar.service`;
+/// * the template, for template instances, e.g. `foo@.service.d` for `foo@bar.service`;
+/// * the unit itself, e.g. `foo@bar.service.d`.
+///
+/// Drop-ins are `.conf` files, and dotfiles are ignored. Those in the directories of
+/// more specific names override the ones with the same filename in less specific
+/// directories, regardless of the base directories they are in; within the same name,
+/// later base directories override earlier ones as in [`scan`].
+pub fn scan_unit_dropins<BdS, BdI>(base_dirs: BdI, unit_name: &str) -> BTreeMap<OsString, PathBuf>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+{
+    let base_dirs: Vec<_> = base_dirs.into_iter().collect();
+    let dirs = unit_fallback_names(unit_name).into_iter().flat_map(|name| {
+        let dropin_dir = format!("{}.d", name);
+        base_dirs
+            .iter()
+            .map(move |base| base.as_ref().join(&dropin_dir))
+    });
+    scan(dirs, "", &["conf"], true)
+}
+
+/// Names whose drop-ins apply to `unit_name`, from the least to the most specific one.
+fn unit_fallback_names(unit_name: &str) -> Vec<String> {
+    let (stem, unit_type) = match unit_name.rsplit_once('.') {
+        Some(parts) => parts,
+        None => return vec![unit_name.to_string()],
+    };
+    let (prefix, instance) = match stem.split_once('@') {
+        Some((prefix, instance)) => (prefix, Some(instance)),
+        None => (stem, None),
+    };
+
+    let mut names = vec![unit_type.to_string()];
+    names.extend(
+        prefix
+            .match_indices('-')
+            .map(|(idx, _)| format!("{}.{}", &prefix[..=idx], unit_type)),
+    );
+    if let Some(instance) = instance {
+        if !instance.is_empty() {
+            names.push(format!("{}@.{}", prefix, unit_type));
+        }
+    }
+    names.push(unit_name.to_string());
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREEDIR: &str = "tests/fixtures/tree-dropins";
+
+    #[test]
+    fn main_and_dropins() {
+        let dirs = ["usr/lib", "etc"].map(|d| Path::new(TREEDIR).join(d));
+        let (main, dropins) = scan_main_and_dropins(&dirs, "foo.conf", "conf", false);
+        assert_eq!(main, Some(dirs[1].join("foo.conf")));
+        let expected: BTreeMap<OsString, PathBuf> = [
+            (
+                "10-vendor.conf".into(),
+                dirs[0].join("foo.conf.d/10-vendor.conf"),
+            ),
+            (
+                "20-admin.conf".into(),
+                dirs[1].join("foo.conf.d/20-admin.conf"),
+            ),
+        ]
+        .into();
+        assert_eq!(dropins, expected);
+
+        // The main file is masked in /run, its drop-ins still apply.
+        let dirs = ["usr/lib", "etc", "run"].map(|d| Path::new(TREEDIR).join(d));
+        let (main, dropins) = scan_main_and_dropins(&dirs, "foo.conf", ".conf", false);
+        assert_eq!(main, None);
+        assert_eq!(
+            dropins[OsStr::new("10-vendor.conf")],
+            dirs[2].join("foo.conf.d/10-vendor.conf")
+        );
+
+        let (main, dropins) = scan_main_and_dropins(&dirs, "bar.conf", "conf", false);
+        assert_eq!(main, None);
+        assert!(dropins.is_empty());
+    }
+
+    #[test]
+    fn fallback_names() {
+        assert_eq!(
+            unit_fallback_names("foo-bar@baz.service"),
+            [
+                "service",
+                "foo-.service",
+                "foo-bar@.service",
+                "foo-bar@baz.service"
+            ]
+        );
+        assert_eq!(
+            unit_fallback_names("a-b-c.mount"),
+            ["mount", "a-.mount", "a-b-.mount", "a-b-c.mount"]
+        );
+        assert_eq!(
+            unit_fallback_names("foo@.socket"),
+            ["socket", "foo@.socket"]
+        );
+    }
+
+    #[test]
+    fn unit_dropins() {
+        let treedir = Path::new("tests/fixtures/tree-units");
+        let dirs = ["usr/lib", "etc"].map(|d| treedir.join(d));
+        let dropins = scan_unit_dropins(&dirs, "foo-bar@baz.service");
+        let dropins: Vec<_> = dropins
+            .values()
+            .map(|path| path.strip_prefix(treedir).unwrap())
+            .collect();
+        assert_eq!(
+            dropins,
+            [
+                "usr/lib/service.d/10-all.conf",
+                "usr/lib/foo-.service.d/15-prefix.conf",
+                "usr/lib/foo-bar@.service.d/20-template.conf",
+                "etc/foo-bar@baz.service.d/30-shared.conf",
+                "etc/foo-bar@baz.service.d/40-instance.conf",
+            ]
+            .map(Path::new)
+        );
+
+        let dropins = scan_unit_dropins(&dirs, "foo-bar@.service");
+        assert_eq!(dropins.len(), 4);
+        assert_eq!(
+            dropins[OsStr::new("30-shared.conf")],
+            dirs[0].join("foo-bar@.service.d/30-shared.conf")
+        );
+    }
+}
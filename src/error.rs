@@ -0,0 +1,84 @@
+//! Error types.
+
+use crate::Rejection;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Error returned by fallible scans.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ScanError {
+    /// Some base directories do not exist, while all were required to.
+    MissingDirs(Vec<PathBuf>),
+    /// No configuration fragment was found, while at least one was required.
+    NoFragments,
+    /// A fragment was rejected by the validator, while rejections were fatal.
+    Rejected(Rejection),
+    /// A symlink which is neither a mask nor followed was found, see [`SymlinkPolicy::Error`](crate::SymlinkPolicy::Error).
+    Symlink(PathBuf),
+    /// A directory could not be read, for other reasons than not existing, or a fragment could not be opened.
+    Io {
+        /// Path of the directory or fragment.
+        path: PathBuf,
+        /// Underlying error.
+        source: io::Error,
+    },
+    /// A fragment is larger than the [per-fragment limit](crate::Overdrop::max_fragment_size).
+    FragmentTooLarge {
+        /// Filename of the fragment.
+        name: OsString,
+        /// The limit, in bytes.
+        limit: u64,
+    },
+    /// A fragment does not fit in the [limit for all fragments](crate::Overdrop::max_total_size).
+    TotalTooLarge {
+        /// Filename of the first fragment over the limit.
+        name: OsString,
+        /// The limit, in bytes.
+        limit: u64,
+    },
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::MissingDirs(dirs) => {
+                write!(f, "missing configuration directories:")?;
+                for dir in dirs {
+                    write!(f, " '{}'", dir.display())?;
+                }
+                Ok(())
+            }
+            ScanError::NoFragments => write!(f, "no configuration fragments found"),
+            ScanError::Rejected(rejection) => rejection.fmt(f),
+            ScanError::Symlink(path) => write!(f, "unexpected symlink '{}'", path.display()),
+            ScanError::Io { path, source } => {
+                write!(f, "failed to read '{}': {}", path.display(), source)
+            }
+            ScanError::FragmentTooLarge { name, limit } => write!(
+                f,
+                "fragment '{}' is larger than {} bytes",
+                name.to_string_lossy(),
+                limit
+            ),
+            ScanError::TotalTooLarge { name, limit } => write!(
+                f,
+                "fragments are larger than {} bytes in total, as of '{}'",
+                limit,
+                name.to_string_lossy()
+            ),
+        }
+    }
+}
+
+impl Error for ScanError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScanError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
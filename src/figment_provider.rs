@@ -0,0 +1,118 @@
+//! Integration with figment, as a configuration provider.
+
+use crate::{scan_and_merge_value, ArrayMerge, Format};
+use figment::providers::Serialized;
+use figment::value::{Dict, Map};
+use figment::{Error, Metadata, Profile, Provider};
+use std::path::{Path, PathBuf};
+
+/// A [`figment::Provider`] of configuration fragments, merged with drop-in precedence.
+///
+/// Fragments are scanned and merged as by [`scan_and_merge_value`] whenever figment
+/// asks for data, so that the whole drop-in tree is a single source, which can be
+/// layered below e.g. environment variables:
+///
+/// ```rust,no_run
+/// # use liboverdrop::{FigmentProvider, Format, SYSTEMD_CONVENTIONAL_BASES};
+/// use figment::providers::{Env, Serialized};
+/// use figment::Figment;
+///
+/// let figment = Figment::from(Serialized::defaults(serde_json::json!({ "port": 8000 })))
+///     .merge(FigmentProvider::new(SYSTEMD_CONVENTIONAL_BASES, "my-crate/config.d", Format::Json))
+///     .merge(Env::prefixed("MY_CRATE_"));
+/// let port: u16 = figment.extract_inner("port").unwrap();
+/// ```
+///
+/// This is available with the `figment` feature.
+#[derive(Clone, Debug)]
+pub struct FigmentProvider {
+    base_dirs: Vec<PathBuf>,
+    shared_path: PathBuf,
+    format: Format,
+    arrays: ArrayMerge,
+    profile: Profile,
+}
+
+impl FigmentProvider {
+    /// Create a provider of the fragments in `format` under `shared_path`, see [`scan_and_merge_value`].
+    pub fn new<BdS, BdI, Sp>(base_dirs: BdI, shared_path: Sp, format: Format) -> Self
+    where
+        BdS: AsRef<Path>,
+        BdI: IntoIterator<Item = BdS>,
+        Sp: AsRef<Path>,
+    {
+        Self {
+            base_dirs: base_dirs
+                .into_iter()
+                .map(|dir| dir.as_ref().to_path_buf())
+                .collect(),
+            shared_path: shared_path.as_ref().to_path_buf(),
+            format,
+            arrays: ArrayMerge::default(),
+            profile: Profile::Default,
+        }
+    }
+
+    /// Set how arrays of different fragments are merged.
+    #[must_use]
+    pub fn arrays(mut self, arrays: ArrayMerge) -> Self {
+        self.arrays = arrays;
+        self
+    }
+
+    /// Set the figment profile the merged fragments are provided for (the default one, by default).
+    #[must_use]
+    pub fn profile(mut self, profile: impl Into<Profile>) -> Self {
+        self.profile = profile.into();
+        self
+    }
+}
+
+impl Provider for FigmentProvider {
+    fn metadata(&self) -> Metadata {
+        Metadata::named(format!("drop-ins in '{}'", self.shared_path.display()))
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, Error> {
+        let value =
+            scan_and_merge_value(&self.base_dirs, &self.shared_path, self.format, self.arrays)
+                .map_err(|e| Error::from(e.to_string()))?;
+        Serialized::from(value, self.profile.clone()).data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TreeBuilder;
+    use figment::Figment;
+
+    #[test]
+    fn provider() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/app.d/10-a.json", r#"{"a": 1, "b": {"x": 1}}"#)
+            .fragment("usr/lib/app.d/20-b.json", r#"{"b": {"y": 1}, "c": [1]}"#)
+            .fragment("etc/app.d/10-a.json", r#"{"a": 2}"#)
+            .fragment("etc/app.d/30-c.json", r#"{"c": [2]}"#);
+        let provider =
+            FigmentProvider::new(tree.base_dirs(["usr/lib", "etc"]), "app.d", Format::Json);
+
+        // The admin `10-a.json` replaces the vendor one, and other sources may still override.
+        let figment = Figment::from(provider.clone()).merge(Serialized::default("b.y", 3));
+        assert_eq!(figment.extract_inner::<i64>("a").unwrap(), 2);
+        assert!(figment.extract_inner::<i64>("b.x").is_err());
+        assert_eq!(figment.extract_inner::<i64>("b.y").unwrap(), 3);
+        assert_eq!(figment.extract_inner::<Vec<i64>>("c").unwrap(), [2]);
+
+        let figment = Figment::from(provider.arrays(ArrayMerge::Append));
+        assert_eq!(figment.extract_inner::<Vec<i64>>("c").unwrap(), [1, 2]);
+
+        let tree = tree.fragment("run/app.d/40-d.json", "{");
+        let provider =
+            FigmentProvider::new(tree.base_dirs(["usr/lib", "run"]), "app.d", Format::Json);
+        let err = Figment::from(provider)
+            .extract_inner::<i64>("a")
+            .unwrap_err();
+        assert!(err.to_string().contains("40-d.json"), "{}", err);
+    }
+}
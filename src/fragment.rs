@@ -0,0 +1,461 @@
+//! Scan results with per-fragment details.
+
+use crate::diff::diff_fragments;
+use crate::{
+    effective_search_dirs, read_dir_entries, DirOptions, LayerPolicy, Resolver, ScanDiff, StdFs,
+};
+use std::collections::btree_map;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, Metadata};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A unique configuration fragment, as found while scanning.
+///
+/// Two fragments compare equal (and hash the same) if they have the same
+/// path, modification time and size.
+#[derive(Clone, Debug)]
+pub struct Fragment {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+    /// Metadata captured while scanning (of the target, for followed symlinks); shared as it is large.
+    metadata: Option<Arc<Metadata>>,
+    /// Device and inode numbers, where available.
+    file_id: Option<(u64, u64)>,
+    /// Index of the scanned directory holding the fragment, for layer ordering.
+    search_layer: usize,
+    /// Whether the fragment is a symlink, accepted under [`SymlinkPolicy::Follow`](crate::SymlinkPolicy::Follow).
+    followed: bool,
+}
+
+impl Fragment {
+    pub(crate) fn new(path: PathBuf, meta: &Metadata) -> Self {
+        Self {
+            path,
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            metadata: Some(Arc::new(meta.clone())),
+            file_id: file_id(meta),
+            search_layer: 0,
+            followed: false,
+        }
+    }
+
+    /// A fragment of `len` bytes, from a source without metadata (e.g. a [`MemoryTree`](crate::MemoryTree)).
+    pub(crate) fn without_metadata(path: PathBuf, len: u64) -> Self {
+        Self {
+            path,
+            modified: None,
+            len,
+            metadata: None,
+            file_id: None,
+            search_layer: 0,
+            followed: false,
+        }
+    }
+
+    pub(crate) fn followed(mut self) -> Self {
+        self.followed = true;
+        self
+    }
+
+    pub(crate) fn in_search_layer(mut self, search_layer: usize) -> Self {
+        self.search_layer = search_layer;
+        self
+    }
+
+    /// Open the fragment for reading, making sure it is the same file that was scanned.
+    ///
+    /// This fails if the fragment was replaced since the scan (e.g. by a symlink, or
+    /// by another file renamed over it), so that the contents read are those of the
+    /// file which was evaluated for precedence. Modifications in place are not
+    /// detected. On platforms without stable file identifiers, this only checks that
+    /// the opened path is still a regular file.
+    ///
+    /// On unix, symlinks are not followed unless the fragment was accepted as one
+    /// (see [`SymlinkPolicy::Follow`](crate::SymlinkPolicy::Follow)), and opening
+    /// does not block if the fragment was replaced by e.g. a FIFO.
+    pub fn open(&self) -> io::Result<File> {
+        let file = open_file(&self.path, self.followed)?;
+        let meta = file.metadata()?;
+        if !meta.is_file() || file_id(&meta) != self.file_id {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "fragment was replaced since it was scanned",
+            ));
+        }
+        Ok(file)
+    }
+
+    /// Path where the fragment is located.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Metadata of the fragment, as captured while scanning.
+    ///
+    /// This avoids stat-ing winning fragments again right after a scan, e.g. for
+    /// cache validation; it reflects the state of the file at scan time. This is
+    /// `None` for fragments scanned from a [`FragmentSource`](crate::FragmentSource)
+    /// providing no metadata.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_deref()
+    }
+
+    /// Last modification time of the fragment, if available on this platform.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// Size of the fragment, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the fragment is an empty file.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Device and inode numbers, identifying the physical file where available.
+    pub(crate) fn file_id(&self) -> Option<(u64, u64)> {
+        self.file_id
+    }
+
+    pub(crate) fn search_layer(&self) -> usize {
+        self.search_layer
+    }
+
+    pub(crate) fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    fn key(&self) -> (&Path, Option<SystemTime>, u64) {
+        (&self.path, self.modified, self.len)
+    }
+}
+
+#[cfg(unix)]
+fn open_file(path: &Path, follow: bool) -> io::Result<File> {
+    use rustix::fs::{Mode, OFlags};
+    let mut flags = OFlags::RDONLY | OFlags::CLOEXEC | OFlags::NONBLOCK;
+    if !follow {
+        flags |= OFlags::NOFOLLOW;
+    }
+    Ok(File::from(rustix::fs::open(path, flags, Mode::empty())?))
+}
+
+#[cfg(not(unix))]
+fn open_file(path: &Path, _follow: bool) -> io::Result<File> {
+    File::open(path)
+}
+
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+impl PartialEq for Fragment {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Fragment {}
+
+impl Hash for Fragment {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// A unique configuration fragment, with its provenance across layers.
+///
+/// See [`scan_detailed`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FragmentDetails {
+    fragment: Fragment,
+    layer: usize,
+    shadowed: Vec<PathBuf>,
+}
+
+impl FragmentDetails {
+    /// The winning fragment.
+    pub fn fragment(&self) -> &Fragment {
+        &self.fragment
+    }
+
+    /// Path where the winning fragment is located.
+    pub fn path(&self) -> &Path {
+        self.fragment.path()
+    }
+
+    /// Index of the base directory holding the winning fragment.
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+
+    /// Paths of lower-priority fragments with the same filename, in increasing priority order.
+    ///
+    /// These are the fragments that the winning one overrides, e.g. for printing
+    /// systemd-style "overridden by" diagnostics.
+    pub fn shadowed(&self) -> &[PathBuf] {
+        &self.shadowed
+    }
+}
+
+/// Scan unique configuration fragments, with their provenance.
+///
+/// Arguments are the same as for [`scan`](crate::scan), and the same fragments
+/// are returned, together with the index of the base directory each one comes from
+/// and the lower-priority fragments it shadowed.
+pub fn scan_detailed<BdS, BdI, Sp, As>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> BTreeMap<OsString, FragmentDetails>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+
+    let mut resolver = Resolver::new();
+    // All fragments seen so far for each filename, with their layer.
+    let mut seen: BTreeMap<OsString, Vec<(usize, PathBuf)>> = BTreeMap::new();
+    for (layer, dir) in effective_search_dirs(base_dirs, shared_path)
+        .iter()
+        .enumerate()
+    {
+        // Missing or unreadable directories are simply skipped.
+        let _ = read_dir_entries(&StdFs, dir, &options, |fname, entry| {
+            let entry = entry.map(|fragment| {
+                let candidates = seen.entry(fname.clone()).or_default();
+                candidates.push((layer, fragment.path().to_path_buf()));
+                (layer, fragment)
+            });
+            resolver.apply(LayerPolicy::default(), fname, entry);
+        });
+    }
+
+    resolver
+        .into_map()
+        .into_iter()
+        .map(|(name, (layer, fragment))| {
+            let shadowed = seen
+                .remove(&name)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(l, _)| *l < layer)
+                .map(|(_, path)| path)
+                .collect();
+            let details = FragmentDetails {
+                fragment,
+                layer,
+                shadowed,
+            };
+            (name, details)
+        })
+        .collect()
+}
+
+/// The set of unique configuration fragments resulting from a scan, by filename.
+///
+/// Two sets compare equal (and hash the same) if they hold the same fragment
+/// names, paths, modification times and sizes. This allows keeping the previous
+/// set around and cheaply detecting whether a rescan changed anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FragmentSet {
+    fragments: BTreeMap<OsString, Fragment>,
+}
+
+impl FragmentSet {
+    pub(crate) fn from_map(fragments: BTreeMap<OsString, Fragment>) -> Self {
+        Self { fragments }
+    }
+
+    /// Set (or remove) the fragment for `name`, returning whether anything changed.
+    pub(crate) fn update(&mut self, name: OsString, fragment: Option<Fragment>) -> bool {
+        match fragment {
+            Some(f) => self.fragments.insert(name, f.clone()).as_ref() != Some(&f),
+            None => self.fragments.remove(&name).is_some(),
+        }
+    }
+
+    /// Filenames whose fragment differs between `self` and `other` (added, removed or replaced).
+    pub(crate) fn changed_names(&self, other: &FragmentSet) -> Vec<OsString> {
+        let names: BTreeSet<_> = self
+            .fragments
+            .keys()
+            .chain(other.fragments.keys())
+            .collect();
+        names
+            .into_iter()
+            .filter(|name| self.fragments.get(*name) != other.fragments.get(*name))
+            .cloned()
+            .collect()
+    }
+
+    /// Return the fragment with the given filename, if any.
+    pub fn get(&self, name: impl AsRef<OsStr>) -> Option<&Fragment> {
+        self.fragments.get(name.as_ref())
+    }
+
+    /// Number of fragments in the set.
+    pub fn len(&self) -> usize {
+        self.fragments.len()
+    }
+
+    /// Whether the set holds no fragment.
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// Iterate over fragments, in alphanumeric order by filename.
+    pub fn iter(&self) -> btree_map::Iter<'_, OsString, Fragment> {
+        self.fragments.iter()
+    }
+
+    /// Compare with `new`, e.g. the result of a later scan.
+    ///
+    /// Besides added, removed and repointed filenames, this detects fragments modified
+    /// in place, by modification time and size.
+    pub fn diff(&self, new: &FragmentSet) -> ScanDiff {
+        diff_fragments(&self.fragments, &new.fragments)
+    }
+
+    /// Convert into a map of fragment paths, as returned by [`scan`](crate::scan).
+    pub fn into_paths(self) -> BTreeMap<OsString, PathBuf> {
+        self.fragments
+            .into_iter()
+            .map(|(name, fragment)| (name, fragment.into_path()))
+            .collect()
+    }
+}
+
+impl<'a> IntoIterator for &'a FragmentSet {
+    type Item = (&'a OsString, &'a Fragment);
+    type IntoIter = btree_map::Iter<'a, OsString, Fragment>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for FragmentSet {
+    type Item = (OsString, Fragment);
+    type IntoIter = btree_map::IntoIter<OsString, Fragment>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fragments.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Overdrop;
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash_of<T: Hash>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn scan_tree(treedir: &str) -> FragmentSet {
+        let dirs = ["usr/lib", "etc", "run"].map(|d| Path::new(treedir).join(d));
+        Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs)
+            .scan_fragments()
+            .unwrap()
+    }
+
+    #[test]
+    fn fragment_set_change_detection() {
+        let first = scan_tree("tests/fixtures/tree-merge");
+        let second = scan_tree("tests/fixtures/tree-merge");
+        assert_eq!(first, second);
+        assert_eq!(hash_of(&first), hash_of(&second));
+
+        let other = scan_tree("tests/fixtures/tree-basic");
+        assert_ne!(first, other);
+
+        let mut touched = first.clone();
+        let fragment = touched
+            .fragments
+            .get_mut(OsStr::new("30-local.conf"))
+            .unwrap();
+        fragment.modified = Some(SystemTime::UNIX_EPOCH);
+        assert_ne!(first, touched);
+
+        let mut resized = first.clone();
+        resized
+            .fragments
+            .get_mut(OsStr::new("30-local.conf"))
+            .unwrap()
+            .len += 1;
+        assert_ne!(first, resized);
+    }
+
+    #[test]
+    fn detailed_provenance() {
+        let treedir = Path::new("tests/fixtures/tree-mask");
+        let dirs = ["usr/lib", "etc", "run"].map(|d| treedir.join(d));
+        let details = scan_detailed::<_, _, _, &str>(&dirs, "liboverdrop.d", &[], false);
+        let paths: BTreeMap<_, _> = details
+            .iter()
+            .map(|(name, details)| (name.clone(), details.path().to_path_buf()))
+            .collect();
+        assert_eq!(
+            paths,
+            crate::scan::<_, _, _, &str>(&dirs, "liboverdrop.d", &[], false)
+        );
+
+        let a = &details[OsStr::new("10-a.conf")];
+        assert_eq!(a.layer(), 2);
+        assert_eq!(a.shadowed(), [dirs[0].join("liboverdrop.d/10-a.conf")]);
+        let c = &details[OsStr::new("30-c.conf")];
+        assert_eq!(c.layer(), 0);
+        assert!(c.shadowed().is_empty());
+    }
+
+    #[test]
+    fn fragment_set_details() {
+        let set = scan_tree("tests/fixtures/tree-merge");
+        assert_eq!(set.len(), 4);
+
+        let fragment = set.get("30-local.conf").unwrap();
+        assert_eq!(
+            fragment.path(),
+            Path::new("tests/fixtures/tree-merge/etc/liboverdrop.d/30-local.conf")
+        );
+        assert_eq!(fragment.len(), "retries=5\n".len() as u64);
+        assert!(fragment.modified().is_some());
+        let meta = std::fs::metadata(fragment.path()).unwrap();
+        assert!(fragment.metadata().unwrap().is_file());
+        assert_eq!(fragment.metadata().unwrap().len(), meta.len());
+        assert_eq!(
+            fragment.metadata().unwrap().modified().ok(),
+            meta.modified().ok()
+        );
+
+        let names: Vec<_> = set.iter().map(|(name, _)| name.clone()).collect();
+        let paths = set.into_paths();
+        assert_eq!(names, paths.into_keys().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,99 @@
+//! Interning of fragment names and paths across repeated scans.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A pool of interned fragment names and paths.
+///
+/// Daemons which rescan frequently can keep a pool around and pass it to
+/// [`Overdrop::scan_interned`](crate::Overdrop::scan_interned), so that identical
+/// names and paths across scans share a single allocation (and can be cheaply
+/// cloned and compared) instead of being held in fresh `OsString`s and `PathBuf`s.
+#[derive(Clone, Debug, Default)]
+pub struct Interner {
+    names: HashSet<Arc<OsStr>>,
+    paths: HashSet<Arc<Path>>,
+}
+
+impl Interner {
+    /// Create an empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the interned copy of `name`, adding it to the pool if needed.
+    pub fn name(&mut self, name: &OsStr) -> Arc<OsStr> {
+        if let Some(interned) = self.names.get(name) {
+            return Arc::clone(interned);
+        }
+        let interned: Arc<OsStr> = Arc::from(name);
+        self.names.insert(Arc::clone(&interned));
+        interned
+    }
+
+    /// Return the interned copy of `path`, adding it to the pool if needed.
+    pub fn path(&mut self, path: &Path) -> Arc<Path> {
+        if let Some(interned) = self.paths.get(path) {
+            return Arc::clone(interned);
+        }
+        let interned: Arc<Path> = Arc::from(path);
+        self.paths.insert(Arc::clone(&interned));
+        interned
+    }
+
+    /// Number of interned names and paths.
+    pub fn len(&self) -> usize {
+        self.names.len() + self.paths.len()
+    }
+
+    /// Whether the pool is empty.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    /// Drop interned values which are not referenced anymore outside of the pool.
+    ///
+    /// This keeps the pool from growing unboundedly as fragments come and go.
+    pub fn shrink(&mut self) {
+        self.names.retain(|n| Arc::strong_count(n) > 1);
+        self.paths.retain(|p| Arc::strong_count(p) > 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Overdrop;
+
+    #[test]
+    fn interned_rescans_share_allocations() {
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs(
+            ["usr/lib", "etc", "run"].map(|d| Path::new("tests/fixtures/tree-merge").join(d)),
+        );
+        let mut pool = Interner::new();
+
+        let first = scanner.scan_interned(&mut pool).unwrap();
+        let second = scanner.scan_interned(&mut pool).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(pool.len(), 8);
+        for ((n1, p1), (n2, p2)) in first.iter().zip(second.iter()) {
+            assert!(Arc::ptr_eq(n1, n2));
+            assert!(Arc::ptr_eq(p1, p2));
+        }
+
+        let expected = scanner.scan().unwrap();
+        assert!(first
+            .iter()
+            .zip(expected.iter())
+            .all(|((n1, p1), (n2, p2))| **n1 == **n2 && **p1 == **p2));
+
+        drop(first);
+        pool.shrink();
+        assert_eq!(pool.len(), 8);
+        drop(second);
+        pool.shrink();
+        assert!(pool.is_empty());
+    }
+}
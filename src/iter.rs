@@ -0,0 +1,135 @@
+//! Lazy iteration over unique configuration fragments.
+
+use crate::{
+    classify_candidate, effective_search_dirs, DirOptions, DirScan, Entry, FragmentSource, StdFs,
+};
+use log::trace;
+use std::ffi::{OsStr, OsString};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::vec;
+
+/// Lazily iterate over unique configuration fragments, in the same order as [`scan`](crate::scan).
+///
+/// Arguments are the same as for [`scan`](crate::scan). Directories are listed up
+/// front, but without any `lstat` and without building a map of fragments: each
+/// filename is resolved only when the iterator reaches it (with the same checks
+/// as [`scan`](crate::scan)), by checking candidates from the highest-priority
+/// directory down, so that shadowed entries are never looked at. This is cheaper
+/// for callers consuming the result only once.
+///
+/// As fragments are resolved while iterating, changes to the directories in the
+/// meanwhile may or may not be reflected in the results.
+pub fn scan_iter<BdS, BdI, Sp, As>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> impl Iterator<Item = (OsString, PathBuf)>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    ScanIter::new(effective_search_dirs(base_dirs, shared_path), options)
+}
+
+/// Iterator returned by [`scan_iter`].
+pub(crate) struct ScanIter {
+    dirs: Vec<PathBuf>,
+    options: DirOptions,
+    /// Filenames with the index of the directory holding them, see [`scan_iter`].
+    candidates: Peekable<vec::IntoIter<(OsString, usize)>>,
+}
+
+impl ScanIter {
+    /// List the candidates in `dirs` (in increasing priority order) matching `options`.
+    pub(crate) fn new(dirs: Vec<PathBuf>, options: DirOptions) -> Self {
+        let mut candidates = Vec::new();
+        for (idx, dir) in dirs.iter().enumerate() {
+            trace!("Listing directory '{}'", dir.display());
+            let names = match StdFs.list(dir) {
+                Ok(names) => names,
+                _ => continue,
+            };
+            for fname in names {
+                match fname {
+                    Ok(fname) if options.accepts_name(&fname) => candidates.push((fname, idx)),
+                    Ok(_) => {}
+                    Err(e) => trace!("Skipping unreadable entry in '{}': {}", dir.display(), e),
+                }
+            }
+        }
+        // By filename, then in decreasing priority order.
+        candidates.sort_unstable_by(|(a, i), (b, j)| a.cmp(b).then(j.cmp(i)));
+
+        ScanIter {
+            dirs,
+            options,
+            candidates: candidates.into_iter().peekable(),
+        }
+    }
+
+    /// Pop the next lower-priority candidate directory for `fname`, if any.
+    fn next_candidate(&mut self, fname: &OsStr) -> Option<usize> {
+        self.candidates
+            .next_if(|(name, _)| name == fname)
+            .map(|(_, idx)| idx)
+    }
+}
+
+impl Iterator for ScanIter {
+    type Item = (OsString, PathBuf);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((fname, idx)) = self.candidates.next() {
+            let mut candidate = Some(idx);
+            let mut winner = None;
+            // The first file or mask wins; other entries (e.g. directories) are skipped.
+            while let Some(idx) = candidate {
+                let fpath = self.dirs[idx].join(&fname);
+                let entry =
+                    classify_candidate(&StdFs, fpath, &self.options, &mut DirScan::default());
+                match entry.ok().flatten() {
+                    Some(Entry::Fragment(fragment) | Entry::Empty(fragment)) => {
+                        winner = Some(fragment.path().to_path_buf());
+                        break;
+                    }
+                    Some(Entry::Mask) => break,
+                    None => {}
+                }
+                candidate = self.next_candidate(&fname);
+            }
+            while self.next_candidate(&fname).is_some() {}
+
+            if let Some(path) = winner {
+                return Some((fname, path));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan;
+
+    #[test]
+    fn matches_scan() {
+        for (treedir, bases) in [
+            ("tests/fixtures/tree-basic", ["usr/lib", "run", "etc"]),
+            ("tests/fixtures/tree-mask", ["usr/lib", "etc", "run"]),
+        ] {
+            let dirs = bases.map(|d| Path::new(treedir).join(d));
+            for (extensions, ignore_dotfiles) in [(&[][..], false), (&["toml"][..], true)] {
+                let expected = scan(&dirs, "liboverdrop.d", extensions, ignore_dotfiles);
+                let fragments: Vec<_> =
+                    scan_iter(&dirs, "liboverdrop.d", extensions, ignore_dotfiles).collect();
+                assert_eq!(fragments, expected.into_iter().collect::<Vec<_>>());
+            }
+        }
+    }
+}
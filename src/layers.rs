@@ -0,0 +1,505 @@
+//! Compact layer specification strings, for configurable search order.
+
+use crate::{scan_dir_into, DirOptions, FragmentSet, Resolver};
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Error while parsing a [`LayerSpec`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LayerSpecError {
+    /// A `${` variable reference is not terminated by `}`.
+    UnterminatedVariable(String),
+    /// A required layer references an unset (or empty) environment variable.
+    UnsetVariable { layer: String, variable: String },
+    /// A required layer is empty.
+    EmptyRequiredLayer,
+}
+
+impl fmt::Display for LayerSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayerSpecError::UnterminatedVariable(layer) => {
+                write!(f, "unterminated variable reference in layer '{}'", layer)
+            }
+            LayerSpecError::UnsetVariable { layer, variable } => write!(
+                f,
+                "required layer '{}' references unset variable '{}'",
+                layer, variable
+            ),
+            LayerSpecError::EmptyRequiredLayer => write!(f, "empty required layer"),
+        }
+    }
+}
+
+impl Error for LayerSpecError {}
+
+/// Masking and override policy for a single layer (i.e. base directory).
+///
+/// By default, fragments in a layer can mask (via `/dev/null` symlinks) and override
+/// fragments from lower layers, and can themselves be masked and overridden by higher
+/// layers. Policies are attached to layers via
+/// [`Overdrop::layer_policy`](crate::Overdrop::layer_policy), e.g. to model that only
+/// `/etc` and `/run` may mask vendor fragments:
+///
+/// ```rust,no_run
+/// # use liboverdrop::{LayerPolicy, Overdrop};
+/// let fragments = Overdrop::new("my-crate/config.d")
+///     .base_dirs(["/usr/lib", "/usr/local/lib", "/run", "/etc"])
+///     .layer_policy("/usr/local/lib", LayerPolicy::new().masks(false))
+///     .layer_policy("/run", LayerPolicy::new().overridable(false).maskable(false))
+///     .layer_policy("/etc", LayerPolicy::new().empty_file_masks(true))
+///     .scan()?;
+/// # Ok::<(), liboverdrop::ScanError>(())
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayerPolicy {
+    pub(crate) masks: bool,
+    pub(crate) empty_file_masks: bool,
+    pub(crate) maskable: bool,
+    pub(crate) overridable: bool,
+}
+
+impl Default for LayerPolicy {
+    fn default() -> Self {
+        Self {
+            masks: true,
+            empty_file_masks: false,
+            maskable: true,
+            overridable: true,
+        }
+    }
+}
+
+impl LayerPolicy {
+    /// Create the default policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `/dev/null` symlinks in this layer mask fragments from lower layers.
+    #[must_use]
+    pub fn masks(mut self, masks: bool) -> Self {
+        self.masks = masks;
+        self
+    }
+
+    /// Whether empty files in this layer mask fragments from lower layers, like `/dev/null` symlinks.
+    ///
+    /// This is only honored if the layer [`masks`](Self::masks) at all.
+    #[must_use]
+    pub fn empty_file_masks(mut self, empty_file_masks: bool) -> Self {
+        self.empty_file_masks = empty_file_masks;
+        self
+    }
+
+    /// Whether fragments from this layer may be masked by higher layers.
+    #[must_use]
+    pub fn maskable(mut self, maskable: bool) -> Self {
+        self.maskable = maskable;
+        self
+    }
+
+    /// Whether fragments from this layer may be overridden by higher layers.
+    #[must_use]
+    pub fn overridable(mut self, overridable: bool) -> Self {
+        self.overridable = overridable;
+        self
+    }
+}
+
+/// A base directory, together with the scanning options to apply to it.
+///
+/// This allows options to differ per directory, e.g. to accept legacy `.conf`
+/// fragments in `/etc` only, and to only ignore dotfiles in `/run`:
+///
+/// ```rust,no_run
+/// # use liboverdrop::Layer;
+/// let layers = [
+///     Layer::new("/usr/lib").allowed_extensions(&["toml"]),
+///     Layer::new("/etc").allowed_extensions(&["toml", "conf"]),
+///     Layer::new("/run")
+///         .allowed_extensions(&["toml"])
+///         .ignore_dotfiles(true),
+/// ];
+/// let fragments = liboverdrop::scan_layers(layers, "my-crate/config.d");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layer {
+    base_dir: PathBuf,
+    allowed_extensions: Vec<OsString>,
+    ignore_dotfiles: bool,
+    policy: LayerPolicy,
+}
+
+impl Layer {
+    /// Create a layer for `base_dir`, accepting all extensions and dotfiles, with the default policy.
+    pub fn new(base_dir: impl AsRef<Path>) -> Self {
+        Self {
+            base_dir: base_dir.as_ref().to_path_buf(),
+            allowed_extensions: Vec::new(),
+            ignore_dotfiles: false,
+            policy: LayerPolicy::default(),
+        }
+    }
+
+    /// Base directory of this layer.
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    /// Only accept fragments with one of these extensions, with the same semantics as in [`scan`](crate::scan).
+    #[must_use]
+    pub fn allowed_extensions<As: AsRef<OsStr>>(mut self, allowed_extensions: &[As]) -> Self {
+        self.allowed_extensions = allowed_extensions
+            .iter()
+            .map(|ext| ext.as_ref().to_os_string())
+            .collect();
+        self
+    }
+
+    /// Whether to ignore dotfiles in this layer.
+    #[must_use]
+    pub fn ignore_dotfiles(mut self, ignore_dotfiles: bool) -> Self {
+        self.ignore_dotfiles = ignore_dotfiles;
+        self
+    }
+
+    /// Masking and override policy of this layer.
+    #[must_use]
+    pub fn policy(mut self, policy: LayerPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+/// Scan unique configuration fragments, with per-directory options.
+///
+/// This works like [`scan`](crate::scan), with layers in increasing priority order,
+/// but each [`Layer`] carries its own allowed extensions, dotfile handling and
+/// [`LayerPolicy`]. A fragment from one layer overrides (or is masked by) a
+/// same-named entry from a higher layer even if the latter would not have been
+/// accepted in the lower one.
+pub fn scan_layers<LI, Sp>(layers: LI, shared_path: Sp) -> BTreeMap<OsString, PathBuf>
+where
+    LI: IntoIterator<Item = Layer>,
+    Sp: AsRef<Path>,
+{
+    let shared_path = shared_path.as_ref();
+
+    let mut resolver = Resolver::new();
+    for layer in layers {
+        let dir = layer.base_dir.join(shared_path);
+        let options = DirOptions::new(&layer.allowed_extensions, layer.ignore_dotfiles);
+        // Missing or unreadable directories are simply skipped.
+        let _ = scan_dir_into(&mut resolver, &dir, &options, layer.policy);
+    }
+
+    FragmentSet::from_map(resolver.into_map()).into_paths()
+}
+
+/// An ordered list of base directories, parsed from a compact specification string.
+///
+/// The specification lists base directories in increasing priority order, separated
+/// by `:` (e.g. `/usr/lib:/etc:/run:$XDG_CONFIG_HOME`), so that the search order can
+/// be configured via a single setting or environment variable:
+///
+///  * environment variables are expanded, as `$NAME` or `${NAME}`;
+///  * a layer prefixed with `!` is required to exist (see
+///    [`Overdrop::layers`](crate::Overdrop::layers));
+///  * empty layers, and optional layers referencing an unset (or empty) variable,
+///    are skipped.
+///
+/// ```rust
+/// # use liboverdrop::LayerSpec;
+/// let spec: LayerSpec = "!/usr/lib:/etc:/run".parse()?;
+/// assert_eq!(spec.base_dirs().count(), 3);
+/// assert_eq!(spec.required_dirs().count(), 1);
+/// # Ok::<(), liboverdrop::LayerSpecError>(())
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LayerSpec {
+    layers: Vec<(PathBuf, bool)>,
+}
+
+impl LayerSpec {
+    /// Parse `spec`, expanding variables from the process environment.
+    pub fn parse(spec: &str) -> Result<Self, LayerSpecError> {
+        Self::parse_with(spec, |name| env::var(name).ok())
+    }
+
+    /// Parse `spec`, expanding variables via `lookup`.
+    pub fn parse_with<F>(spec: &str, mut lookup: F) -> Result<Self, LayerSpecError>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let mut layers = Vec::new();
+        for raw in spec.split(':') {
+            let (layer, required) = match raw.strip_prefix('!') {
+                Some(layer) => (layer, true),
+                None => (raw, false),
+            };
+            match expand(layer, &mut lookup)? {
+                Ok(path) if path.is_empty() => {
+                    if required {
+                        return Err(LayerSpecError::EmptyRequiredLayer);
+                    }
+                }
+                Ok(path) => layers.push((PathBuf::from(path), required)),
+                Err(variable) if required => {
+                    return Err(LayerSpecError::UnsetVariable {
+                        layer: layer.to_string(),
+                        variable,
+                    })
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(Self { layers })
+    }
+
+    /// Base directories, in increasing priority order.
+    pub fn base_dirs(&self) -> impl Iterator<Item = &Path> {
+        self.layers.iter().map(|(dir, _)| dir.as_path())
+    }
+
+    /// Base directories which are required to exist.
+    pub fn required_dirs(&self) -> impl Iterator<Item = &Path> {
+        self.layers
+            .iter()
+            .filter(|(_, required)| *required)
+            .map(|(dir, _)| dir.as_path())
+    }
+
+    /// Iterate over base directories, together with whether each is required.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, bool)> {
+        self.layers.iter().map(|(dir, req)| (dir.as_path(), *req))
+    }
+}
+
+impl FromStr for LayerSpec {
+    type Err = LayerSpecError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        Self::parse(spec)
+    }
+}
+
+/// Read base directories from the environment variable `var`, falling back to `fallback`.
+///
+/// The variable lists base directories in increasing priority order (as in the rest
+/// of this crate), separated as in `$PATH` (`:` on Unix). As with
+/// `$SYSTEMD_UNIT_PATH`, an empty entry (e.g. a leading or trailing `:`) inserts the
+/// `fallback` directories at its position, so that e.g. `:/opt/site` adds an
+/// override layer on top of the defaults; only the first empty entry is expanded.
+/// If `var` is unset or empty, `fallback` is returned.
+///
+/// ```rust,no_run
+/// let base_dirs = liboverdrop::base_dirs_from_env(
+///     "MY_CRATE_CONFIG_DIRS",
+///     liboverdrop::SYSTEMD_CONVENTIONAL_BASES,
+/// );
+/// ```
+pub fn base_dirs_from_env<BdS, BdI>(var: impl AsRef<OsStr>, fallback: BdI) -> Vec<PathBuf>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+{
+    let value = env::var_os(var).unwrap_or_default();
+    split_base_dirs(&value, fallback)
+}
+
+/// Split a list of base directories, as read by [`base_dirs_from_env`].
+pub fn split_base_dirs<BdS, BdI>(value: &OsStr, fallback: BdI) -> Vec<PathBuf>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+{
+    let mut fallback = Some(fallback);
+    let mut dirs = Vec::new();
+    if value.is_empty() {
+        dirs.extend(
+            fallback
+                .into_iter()
+                .flatten()
+                .map(|d| d.as_ref().to_path_buf()),
+        );
+        return dirs;
+    }
+    for dir in env::split_paths(value) {
+        if !dir.as_os_str().is_empty() {
+            dirs.push(dir);
+        } else if let Some(fallback) = fallback.take() {
+            dirs.extend(fallback.into_iter().map(|d| d.as_ref().to_path_buf()));
+        }
+    }
+    dirs
+}
+
+/// Expand variables in `layer`; the inner error holds the name of an unset variable.
+fn expand<F>(layer: &str, lookup: &mut F) -> Result<Result<String, String>, LayerSpecError>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(layer.len());
+    let mut rest = layer;
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        let name = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| LayerSpecError::UnterminatedVariable(layer.to_string()))?;
+            rest = &braced[end + 1..];
+            &braced[..end]
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            rest = &rest[end..];
+            name
+        };
+
+        if name.is_empty() {
+            out.push('$');
+            continue;
+        }
+        match lookup(name) {
+            Some(value) if !value.is_empty() => out.push_str(&value),
+            _ => return Ok(Err(name.to_string())),
+        }
+    }
+    out.push_str(rest);
+    Ok(Ok(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "XDG_CONFIG_HOME" => Some("/home/user/.config".into()),
+            "PREFIX" => Some("/opt/svc".into()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parse_layers() {
+        let spec = LayerSpec::parse_with(
+            "!/usr/lib:${PREFIX}/lib::/etc:/run:$XDG_CONFIG_HOME:$UNSET/x:$EMPTY:/a$",
+            lookup,
+        )
+        .unwrap();
+        let dirs: Vec<_> = spec.base_dirs().collect();
+        assert_eq!(
+            dirs,
+            [
+                "/usr/lib",
+                "/opt/svc/lib",
+                "/etc",
+                "/run",
+                "/home/user/.config",
+                "/a$"
+            ]
+            .map(Path::new)
+        );
+        let required: Vec<_> = spec.required_dirs().collect();
+        assert_eq!(required, [Path::new("/usr/lib")]);
+
+        assert_eq!(
+            LayerSpec::parse_with("", lookup).unwrap(),
+            LayerSpec::default()
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = LayerSpec::parse_with("/usr/lib:!$UNSET/x", lookup).unwrap_err();
+        assert_eq!(
+            err,
+            LayerSpecError::UnsetVariable {
+                layer: "$UNSET/x".into(),
+                variable: "UNSET".into()
+            }
+        );
+        let err = LayerSpec::parse_with("/usr/lib:${PREFIX", lookup).unwrap_err();
+        assert!(matches!(err, LayerSpecError::UnterminatedVariable(_)));
+        let err = LayerSpec::parse_with("/usr/lib:!", lookup).unwrap_err();
+        assert_eq!(err, LayerSpecError::EmptyRequiredLayer);
+    }
+
+    #[test]
+    fn split_dirs() {
+        let split = |value: &str| split_base_dirs(OsStr::new(value), ["/usr/lib", "/etc"]);
+        let paths = |dirs: &[&str]| dirs.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(split(""), paths(&["/usr/lib", "/etc"]));
+        assert_eq!(split("/opt/a:/opt/b"), paths(&["/opt/a", "/opt/b"]));
+        assert_eq!(
+            split(":/opt/site"),
+            paths(&["/usr/lib", "/etc", "/opt/site"])
+        );
+        assert_eq!(
+            split("/opt/vendor:"),
+            paths(&["/opt/vendor", "/usr/lib", "/etc"])
+        );
+        assert_eq!(
+            split("/opt/a::/opt/b:"),
+            paths(&["/opt/a", "/usr/lib", "/etc", "/opt/b"])
+        );
+
+        let var = format!("LIBOVERDROP_TEST_DIRS_{}", std::process::id());
+        assert_eq!(base_dirs_from_env(&var, ["/etc"]), paths(&["/etc"]));
+        env::set_var(&var, "/run:");
+        assert_eq!(base_dirs_from_env(&var, ["/etc"]), paths(&["/run", "/etc"]));
+        env::remove_var(&var);
+    }
+
+    #[test]
+    fn per_layer_options() {
+        let tree = crate::testing::TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.toml", "")
+            .fragment("usr/lib/svc.d/20-legacy.conf", "")
+            .fragment("etc/svc.d/30-admin.conf", "")
+            .fragment("etc/svc.d/.40-hidden.toml", "")
+            .fragment("run/svc.d/.50-hidden.toml", "")
+            .fragment("run/svc.d/10-a.toml", "");
+        let layers = [
+            Layer::new(tree.path("usr/lib")).allowed_extensions(&["toml"]),
+            Layer::new(tree.path("etc")).allowed_extensions(&["toml", "conf"]),
+            Layer::new(tree.path("run")).ignore_dotfiles(true),
+        ];
+        let fragments = scan_layers(layers.clone(), "svc.d");
+        assert_eq!(
+            fragments.keys().collect::<Vec<_>>(),
+            [".40-hidden.toml", "10-a.toml", "30-admin.conf"]
+                .map(OsStr::new)
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            fragments[OsStr::new("10-a.toml")],
+            tree.path("run/svc.d/10-a.toml")
+        );
+
+        // A non-overridable layer keeps its fragments.
+        let mut layers = layers.to_vec();
+        layers[0] = layers[0]
+            .clone()
+            .policy(LayerPolicy::new().overridable(false));
+        let fragments = scan_layers(layers, "svc.d");
+        assert_eq!(
+            fragments[OsStr::new("10-a.toml")],
+            tree.path("usr/lib/svc.d/10-a.toml")
+        );
+    }
+}
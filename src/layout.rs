@@ -0,0 +1,232 @@
+//! Human-readable rendering of the effective layering.
+
+use crate::{DirStatus, Entry, Fragment, LayerPolicy, Source};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The effective layout of all scanned directories, see [`Overdrop::layout`](crate::Overdrop::layout).
+///
+/// Its `Display` implementation renders an annotated tree, listing each scanned
+/// directory (in increasing priority order) with its entries, and showing which
+/// fragments were overridden or masked, and by what. This is meant for humans
+/// (e.g. behind a `--show-config-layout` flag):
+///
+/// ```text
+/// /usr/lib/my-crate/config.d
+/// ├── 10-a.conf → overridden by /etc/my-crate/config.d/10-a.conf
+/// ├── 20-b.conf → masked by /etc/my-crate/config.d/20-b.conf
+/// └── 30-c.conf
+/// /etc/my-crate/config.d
+/// ├── 10-a.conf
+/// └── 20-b.conf → mask
+/// /run/my-crate/config.d (missing)
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    dirs: Vec<LayoutDir>,
+    /// Synthetic fragments, in injection order.
+    synthetic: Vec<(OsString, Outcome)>,
+}
+
+/// A scanned directory, with its entries sorted by filename.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct LayoutDir {
+    status: DirStatus,
+    entries: Vec<(OsString, Outcome)>,
+}
+
+/// How an entry takes part in the effective configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Outcome {
+    /// The entry is the effective fragment.
+    Effective,
+    /// The fragment was overridden, by a path or by a synthetic fragment.
+    OverriddenBy(Option<PathBuf>),
+    /// An override which was ignored in favor of a lower-priority fragment.
+    IgnoredFor(Option<PathBuf>),
+    /// The fragment was masked.
+    MaskedBy(PathBuf),
+    /// The entry is the effective mask.
+    Mask,
+    /// A mask which did not apply.
+    IgnoredMask,
+    /// The fragment was rejected by the validator.
+    Rejected(String),
+}
+
+/// Entries of a scanned layer, as `(status, policy, entries)`.
+pub(crate) type LayerEntries = (DirStatus, LayerPolicy, Vec<(OsString, Entry<Fragment>)>);
+
+impl Layout {
+    /// Annotate the entries of all `layers` and `synthetic` fragments, given the winning `sources`.
+    pub(crate) fn new(
+        layers: Vec<LayerEntries>,
+        synthetic: &[(usize, OsString, Arc<[u8]>)],
+        sources: &BTreeMap<OsString, Source>,
+    ) -> Self {
+        let is_mask = |policy: &LayerPolicy, entry: &Entry<Fragment>| match entry {
+            Entry::Mask => policy.masks,
+            Entry::Empty(_) => policy.masks && policy.empty_file_masks,
+            Entry::Fragment(_) => false,
+        };
+        // Highest-priority applied mask, and layer of each winning fragment file.
+        let mut maskers = BTreeMap::new();
+        let mut winner_layers = BTreeMap::new();
+        for (idx, (status, policy, entries)) in layers.iter().enumerate() {
+            for (name, entry) in entries {
+                if is_mask(policy, entry) {
+                    maskers.insert(name, (idx, status.path().join(name)));
+                }
+                if let Entry::Fragment(f) | Entry::Empty(f) = entry {
+                    if sources.get(name).and_then(Source::fragment) == Some(f) {
+                        winner_layers.insert(name, idx);
+                    }
+                }
+            }
+        }
+        let winner_path = |name: &OsString| {
+            sources
+                .get(name)
+                .map(|source| source.fragment().map(|f| f.path().to_path_buf()))
+        };
+
+        let mut dirs = Vec::with_capacity(layers.len());
+        for (idx, (status, policy, entries)) in layers.iter().enumerate() {
+            let entries = entries
+                .iter()
+                .map(|(name, entry)| {
+                    let path = status.path().join(name);
+                    let rejection = status.rejected().iter().find(|r| r.path() == path);
+                    let outcome = if let Some(rejection) = rejection {
+                        Outcome::Rejected(rejection.reason().to_string())
+                    } else if let Some(winner) = winner_path(name) {
+                        // Synthetic winners have no layer, but cannot be overridden anyway.
+                        let above = winner_layers.get(name).map_or(true, |w| *w > idx);
+                        if winner.as_deref() == Some(path.as_path()) {
+                            Outcome::Effective
+                        } else if above {
+                            Outcome::OverriddenBy(winner)
+                        } else if let Entry::Fragment(_) | Entry::Empty(_) = entry {
+                            Outcome::IgnoredFor(winner)
+                        } else {
+                            Outcome::IgnoredMask
+                        }
+                    } else {
+                        match maskers.get(name) {
+                            Some((m, _)) if *m == idx && is_mask(policy, entry) => Outcome::Mask,
+                            Some((m, masker)) if *m > idx => Outcome::MaskedBy(masker.clone()),
+                            _ => Outcome::IgnoredMask,
+                        }
+                    };
+                    (name.clone(), outcome)
+                })
+                .collect();
+            dirs.push(LayoutDir {
+                status: status.clone(),
+                entries,
+            });
+        }
+
+        let synthetic = synthetic
+            .iter()
+            .map(|(_, name, contents)| {
+                let outcome = match sources.get(name) {
+                    Some(Source::Memory(winner)) if Arc::ptr_eq(winner, contents) => {
+                        Outcome::Effective
+                    }
+                    Some(source) => {
+                        Outcome::OverriddenBy(source.fragment().map(|f| f.path().to_path_buf()))
+                    }
+                    None => match maskers.get(name) {
+                        Some((_, masker)) => Outcome::MaskedBy(masker.clone()),
+                        None => Outcome::OverriddenBy(None),
+                    },
+                };
+                (name.clone(), outcome)
+            })
+            .collect();
+        Self { dirs, synthetic }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for dir in &self.dirs {
+            write!(f, "{}", dir.status.path().display())?;
+            match dir.status.error() {
+                None if dir.entries.is_empty() => writeln!(f, " (empty)")?,
+                None => writeln!(f)?,
+                Some(io::ErrorKind::NotFound) => writeln!(f, " (missing)")?,
+                Some(kind) => writeln!(f, " (unreadable: {})", kind)?,
+            }
+            write_entries(f, &dir.entries)?;
+        }
+        if !self.synthetic.is_empty() {
+            writeln!(f, "(synthetic)")?;
+            write_entries(f, &self.synthetic)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_entries(f: &mut fmt::Formatter<'_>, entries: &[(OsString, Outcome)]) -> fmt::Result {
+    let synthetic_or = |path: &Option<PathBuf>| match path {
+        Some(path) => path.display().to_string(),
+        None => "synthetic fragment".to_string(),
+    };
+    for (idx, (name, outcome)) in entries.iter().enumerate() {
+        let branch = if idx + 1 == entries.len() {
+            "└──"
+        } else {
+            "├──"
+        };
+        write!(f, "{} {}", branch, Path::new(name).display())?;
+        match outcome {
+            Outcome::Effective => writeln!(f)?,
+            Outcome::OverriddenBy(by) => writeln!(f, " → overridden by {}", synthetic_or(by))?,
+            Outcome::IgnoredFor(winner) => {
+                writeln!(f, " → ignored, {} wins", synthetic_or(winner))?
+            }
+            Outcome::MaskedBy(by) => writeln!(f, " → masked by {}", by.display())?,
+            Outcome::Mask => writeln!(f, " → mask")?,
+            Outcome::IgnoredMask => writeln!(f, " → mask (ignored)")?,
+            Outcome::Rejected(reason) => writeln!(f, " → rejected: {}", reason)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Overdrop;
+    use std::path::Path;
+
+    #[test]
+    fn render_layout() {
+        let treedir = Path::new("tests/fixtures/tree-mask");
+        let bases = ["usr/lib", "etc", "run", "missing"].map(|d| treedir.join(d));
+        let layout = Overdrop::new("liboverdrop.d")
+            .base_dirs(&bases)
+            .inject("50-e.conf", "runtime e", 4)
+            .layout();
+        let expected = "\
+tests/fixtures/tree-mask/usr/lib/liboverdrop.d
+├── 10-a.conf → overridden by tests/fixtures/tree-mask/run/liboverdrop.d/10-a.conf
+├── 20-b.conf → masked by tests/fixtures/tree-mask/etc/liboverdrop.d/20-b.conf
+└── 30-c.conf
+tests/fixtures/tree-mask/etc/liboverdrop.d
+└── 20-b.conf → mask
+tests/fixtures/tree-mask/run/liboverdrop.d
+├── 10-a.conf
+└── 40-d.conf → mask
+tests/fixtures/tree-mask/missing/liboverdrop.d (missing)
+(synthetic)
+└── 50-e.conf
+";
+        assert_eq!(layout.to_string(), expected);
+    }
+}
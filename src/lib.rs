@@ -10,7 +10,7 @@
 //!
 //!  * fragments are identified by unique filenames, lexicographically (e.g. `50-default-limits.conf`).
 //!  * in case of name duplication, last directory wins (e.g. `/etc/svc/custom.conf` can override `/usr/lib/svc/custom.conf`).
-//!  * a fragment symlinked to `/dev/null` is used to ignore any previous fragment with the same filename.
+//!  * a fragment symlinked to `/dev/null` (directly, or via a chain of symlinks) is used to ignore any previous fragment with the same filename.
 //!
 //! [reproducible]: http://0pointer.net/blog/projects/stateless.html
 //!
@@ -35,10 +35,23 @@
 //! }
 //! ```
 //!
+//! For further options, the [`Overdrop`] builder can be used instead.
+//!
+//! # Merging
+//!
+//! [`scan_and_merge`] processes all scanned fragments in order, merging them into a single value.
+//! [`scan_merge_validate`] additionally validates the final value, with per-key [`Provenance`].
+//!
+//! # Typed configuration
+//!
+//! For typed configuration, each fragment can be deserialized into a partial struct
+//! (generated by [`partial!`]) and then folded over the defaults via [`fold_partials`].
+//!
 //! # Migrating from liboverdrop 0.0.x
 //!
 //! The signature changed from
-//! ```rust,compile_fail
+#![cfg_attr(not(feature = "compat-0_0"), doc = "```rust,compile_fail")]
+#![cfg_attr(feature = "compat-0_0", doc = "```rust,no_run")]
 //! # use liboverdrop::FragmentScanner;
 //! let base_dirs: Vec<String> = vec![/**/];
 //! let shared_path: &str = "config.d";
@@ -69,25 +82,324 @@
 //!
 //! When updating, re-consider if you need to allocate any argument now,
 //! since they can all be literals or borrowed.
+//!
+//! To migrate call sites gradually, the `compat-0_0` feature restores a
+//! (deprecated) `FragmentScanner`, implemented on top of [`scan`].
 
-use log::trace;
+// The derive macro refers to `::liboverdrop`, also within this crate's own tests.
+#[cfg(all(test, feature = "derive"))]
+extern crate self as liboverdrop;
+
+use log::{trace, warn};
 use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+mod bases;
+pub use bases::{
+    BaseDirs, MULTILIB_CONVENTIONAL_BASES, OSTREE_CONVENTIONAL_BASES, SYSTEMD_BASES_WITHOUT_LOCAL,
+};
+
+mod bundle;
+pub use bundle::{Bundle, BundleEntry};
+
+mod cache;
+pub use cache::{CachedScanner, FragmentCache};
+
+mod diff;
+pub use diff::{diff, ScanDiff};
+
+mod digest;
+pub use digest::Digest;
+
+mod drift;
+pub use drift::{scan_drift, Drift};
+
+#[cfg(feature = "compat-0_0")]
+mod compat;
+#[cfg(feature = "compat-0_0")]
+#[allow(deprecated)]
+pub use compat::FragmentScanner;
+
+#[cfg(all(unix, feature = "dirfd"))]
+pub mod dirfd;
+
+#[cfg(all(target_os = "linux", feature = "getdents"))]
+mod fast;
+#[cfg(all(target_os = "linux", feature = "getdents"))]
+pub use fast::scan_fast;
+
+#[cfg(feature = "config")]
+mod config_source;
+#[cfg(feature = "config")]
+pub use config_source::ConfigSource;
+
+#[cfg(feature = "json")]
+mod deserialize;
+#[cfg(feature = "json")]
+pub use deserialize::{
+    scan_and_deserialize, scan_and_merge_value, ArrayMerge, DeserializeError, Format,
+};
+
+mod dropins;
+pub use dropins::{scan_main_and_dropins, scan_unit_dropins};
+
+mod error;
+pub use error::ScanError;
+
+#[cfg(feature = "figment")]
+mod figment_provider;
+#[cfg(feature = "figment")]
+pub use figment_provider::FigmentProvider;
+
+mod fragment;
+pub use fragment::{scan_detailed, Fragment, FragmentDetails, FragmentSet};
+
+mod intern;
+pub use intern::Interner;
+
+mod iter;
+pub use iter::scan_iter;
+
+mod layers;
+pub use layers::{
+    base_dirs_from_env, scan_layers, split_base_dirs, Layer, LayerPolicy, LayerSpec, LayerSpecError,
+};
+
+mod layout;
+pub use layout::Layout;
+
+mod lines;
+pub use lines::{first_field, scan_lines, Line, LineSet};
+
+mod merge;
+pub use merge::{
+    merge_key_values, scan_and_fold, scan_and_merge, scan_and_merge_by_extension,
+    scan_and_merge_bytes, scan_and_merge_contents, scan_and_merge_detailed, scan_and_merge_lenient,
+    scan_and_merge_with, scan_and_try_merge, scan_merge_validate, scan_values, FragmentInfo,
+    MergeTable, Provenance, Utf8Policy,
+};
+
+mod migrate;
+pub use migrate::{scan_and_merge_versioned, Migrations};
+
+mod naming;
+pub use naming::suggest_override_name;
+
+#[cfg(feature = "tokio")]
+mod nonblocking;
+#[cfg(feature = "stream")]
+pub use nonblocking::scan_stream;
+#[cfg(feature = "tokio")]
+pub use nonblocking::{scan_and_merge_async, scan_async};
+
+mod order;
+pub use order::{order_fragments, scan_and_merge_ordered, MergeOrder};
+
+mod overdrop;
+pub use overdrop::Overdrop;
+
+mod overlay;
+#[cfg(feature = "derive")]
+pub use liboverdrop_derive::Merge;
+pub use overlay::{scan_and_merge_into, Merge};
+
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "rayon")]
+pub use parallel::scan_and_merge_par;
+
+mod partial;
+pub use partial::{fold_partials, Partial};
+
+mod pattern;
+pub use pattern::{NamePattern, PatternError};
+
+mod report;
+pub use report::{MaskedName, ReportedFragment, ScanReport, SkipReason, SkippedEntry};
+
+mod resolve;
+pub use resolve::{Entry, Resolver};
+
+mod sort;
+pub use sort::version_cmp;
+
+mod source;
+pub use source::Source;
+
+mod symlink;
+use symlink::{check_dir_symlinks, classify_symlink};
+pub use symlink::{DirSymlinkPolicy, SymlinkPolicy};
+
+mod status;
+use status::DirScan;
+pub use status::{DirStatus, Rejection};
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+mod trust;
+pub use trust::TrustPolicy;
+
+mod vfs;
+pub use vfs::{scan_source, EntryKind, EntryNames, FragmentSource, MemoryTree, StdFs};
+
+mod watch;
+#[cfg(all(target_os = "linux", feature = "inotify"))]
+pub use watch::InotifyWatcher;
+pub use watch::{PollWatcher, WatchEvent};
+
+mod write;
+pub use write::write_override;
+
+#[cfg(feature = "xdg")]
+mod xdg;
+#[cfg(feature = "xdg")]
+pub use xdg::{xdg_base_dirs, xdg_base_dirs_with};
+
+#[cfg(feature = "jsonschema")]
+pub mod schema;
 
 /// The well-known path to the null device used for overrides.
 const DEVNULL: &str = "/dev/null";
 
+/// Whether a symlink pointing to `target` masks a fragment.
+///
+/// This is `/dev/null`, and on Windows also the `NUL` device (as `NUL` or `\\.\NUL`).
+fn is_mask_target(target: &Path) -> bool {
+    if target == Path::new(DEVNULL) {
+        return true;
+    }
+    cfg!(windows)
+        && target.to_str().map_or(false, |target| {
+            let device = target.strip_prefix(r"\\.\").unwrap_or(target);
+            device.eq_ignore_ascii_case("NUL")
+        })
+}
+
+/// Maximum number of symlinks followed when resolving a mask, as for path lookups on Linux.
+const MAX_MASK_HOPS: usize = 40;
+
+/// Whether the symlink at `link`, pointing to `target`, masks a fragment.
+///
+/// Like systemd, this follows chains of symlinks (e.g. to `../null-mask`, itself
+/// pointing to `/dev/null`), with relative targets resolved lexically against the
+/// directory of the link (so `../../dev/null` works too). Further links are read
+/// via `read_link`; chains longer than [`MAX_MASK_HOPS`] (e.g. loops), or ending in
+/// anything but the null device, do not mask.
+///
+/// With an alternate `root`, the chain is resolved as if `root` were `/`: absolute
+/// targets and `..` components stay under it, and a mask points to its `/dev/null`.
+fn is_mask_link<F>(link: &Path, target: &Path, root: Option<&Path>, mut read_link: F) -> bool
+where
+    F: FnMut(&Path) -> io::Result<PathBuf>,
+{
+    let mut link = match root {
+        Some(root) => Path::new("/").join(link.strip_prefix(root).unwrap_or(link)),
+        None => link.to_path_buf(),
+    };
+    let mut target = target.to_path_buf();
+    for _ in 0..MAX_MASK_HOPS {
+        if is_mask_target(&target) {
+            return true;
+        }
+        link = resolve_link_target(&link, &target);
+        if is_mask_target(&link) {
+            return true;
+        }
+        let next = match root {
+            Some(root) => read_link(&under_root(root, &link)),
+            None => read_link(&link),
+        };
+        target = match next {
+            Ok(target) => target,
+            Err(_) => return false,
+        };
+    }
+    false
+}
+
+/// Return the absolute `path` under `root`, e.g. `/etc` as `/sysroot/etc`.
+fn under_root(root: &Path, path: &Path) -> PathBuf {
+    root.join(
+        path.components()
+            .filter(|c| !matches!(c, Component::Prefix(_) | Component::RootDir))
+            .collect::<PathBuf>(),
+    )
+}
+
+/// Resolve `target` of the symlink at `link`, lexically normalizing `.` and `..` components.
+fn resolve_link_target(link: &Path, target: &Path) -> PathBuf {
+    let joined = match link.parent() {
+        Some(parent) => parent.join(target),
+        None => target.to_path_buf(),
+    };
+    let mut resolved = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match resolved.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    resolved.pop();
+                }
+                // `..` at the root stays at the root.
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => resolved.push(component),
+            },
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+/// Whether `meta` has a platform-specific hidden flag, i.e. the hidden attribute on Windows.
+///
+/// Such entries are ignored together with dotfiles.
+#[cfg(windows)]
+fn has_hidden_attribute(meta: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_meta: &fs::Metadata) -> bool {
+    false
+}
+
 /// The base search paths conventionally used by systemd and other projects.
 ///
 /// Here, files in `/run` override those in `/etc`, which in turn override
 /// those in `/usr/lib`.
 ///
 /// Note that some projects may want to omit `/usr/local`, which may be a distinct writable
-/// area from the OS image base.  To do so, one can explicitly filter it out from this set.
+/// area from the OS image base.  To do so, one can use [`SYSTEMD_BASES_WITHOUT_LOCAL`]
+/// instead, or adjust this set with [`BaseDirs`].
 pub const SYSTEMD_CONVENTIONAL_BASES: &[&str] = &["/usr/lib", "/usr/local/lib", "/etc", "/run"];
 
+/// Return the base search paths conventionally used on Windows, in increasing priority order.
+///
+/// These are `%ProgramData%` (machine-wide configuration) and `%APPDATA%` (per-user
+/// configuration), skipping unset variables. As there is no `/dev/null` there,
+/// fragments can be masked by symlinks to the `NUL` device or, more conveniently, by
+/// empty files with [`LayerPolicy::empty_file_masks`]; the hidden attribute is
+/// treated as a leading dot when ignoring dotfiles.
+pub fn windows_conventional_bases() -> Vec<PathBuf> {
+    windows_bases_with(|name| std::env::var_os(name))
+}
+
+fn windows_bases_with<F: Fn(&str) -> Option<OsString>>(lookup: F) -> Vec<PathBuf> {
+    ["ProgramData", "APPDATA"]
+        .into_iter()
+        .filter_map(|name| lookup(name).filter(|value| !value.is_empty()))
+        .map(PathBuf::from)
+        .collect()
+}
+
 #[allow(clippy::doc_overindented_list_items)]
 /// Scan unique configuration fragments from the configuration directories specified.
 ///
@@ -98,6 +410,8 @@ pub const SYSTEMD_CONVENTIONAL_BASES: &[&str] = &["/usr/lib", "/usr/local/lib",
 ///                   holding configuration fragments.
 /// * `allowed_extensions` - Only scan files that have an extension listed in `allowed_extensions`.
 ///                          If an empty slice is passed, then all extensions are allowed.
+///                          Composite extensions (e.g. `conf.toml`) match the full filename suffix.
+///                          A leading dot is optional (i.e. `.toml` is the same as `toml`).
 /// * `ignore_dotfiles` - Whether to ignore dotfiles (hidden files with name prefixed with '.').
 ///
 /// `shared_path` is joined onto each entry in `base_dirs` to form the directory paths to scan.
@@ -115,66 +429,798 @@ pub fn scan<BdS: AsRef<Path>, BdI: IntoIterator<Item = BdS>, Sp: AsRef<Path>, As
     ignore_dotfiles: bool,
 ) -> BTreeMap<OsString, PathBuf> {
     let shared_path = shared_path.as_ref();
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
 
-    let mut files_map = BTreeMap::new();
+    let mut resolver = Resolver::new();
     for dir in base_dirs {
         let dir = dir.as_ref().join(shared_path);
-        trace!("Scanning directory '{}'", dir.display());
+        // Missing or unreadable directories are simply skipped.
+        let _ = scan_dir_into(&mut resolver, &dir, &options, LayerPolicy::default());
+    }
 
-        let dir_iter = match fs::read_dir(dir) {
-            Ok(iter) => iter,
-            _ => continue,
-        };
-        for entry in dir_iter.flatten() {
-            let fpath = entry.path();
-            let fname = entry.file_name();
-
-            // If hidden files not allowed, ignore dotfiles.
-            // Rust RFC 900 &c.: there's no way to check if a Path/OsStr starts with a prefix;
-            // instead, we check via to_string_lossy(), which will only allocate if the basename wasn't UTF-8,
-            // and the lossiness doesn't bother us; https://github.com/rust-lang/rfcs/issues/900
-            if ignore_dotfiles && fname.to_string_lossy().starts_with('.') {
-                continue;
+    FragmentSet::from_map(resolver.into_map()).into_paths()
+}
+
+/// Scan the paths of unique configuration fragments, in processing order.
+///
+/// This works like [`scan`], returning only the paths, in filename order.
+/// [`Overdrop::effective_paths`] also supports other processing orders.
+///
+/// ```rust,no_run
+/// let paths = liboverdrop::effective_paths(
+///     liboverdrop::SYSTEMD_CONVENTIONAL_BASES,
+///     "my-crate/config.d",
+///     &["conf"],
+///     true,
+/// );
+/// for path in &paths {
+///     println!("{}", path.display());
+/// }
+/// ```
+pub fn effective_paths<BdS, BdI, Sp, As>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> Vec<PathBuf>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    scan(base_dirs, shared_path, allowed_extensions, ignore_dotfiles)
+        .into_values()
+        .collect()
+}
+
+/// Scan unique configuration fragments under several shared paths at once.
+///
+/// This works like [`scan`], with each of `shared_paths` joined onto each entry in
+/// `base_dirs`, producing a single override map. Base directories take precedence:
+/// e.g. a fragment under `/etc` overrides one under `/usr/lib`, regardless of their
+/// shared paths. Within the same base directory, later shared paths override earlier
+/// ones, so a legacy path should come first.
+///
+/// ```rust,no_run
+/// let fragments = liboverdrop::scan_shared_paths(
+///     ["/usr/lib", "/etc"],
+///     &["myapp/conf.d", "myapp/config.d"],
+///     &["conf"],
+///     true,
+/// );
+/// ```
+pub fn scan_shared_paths<BdS, BdI, Sp, As>(
+    base_dirs: BdI,
+    shared_paths: &[Sp],
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> BTreeMap<OsString, PathBuf>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let dirs = base_dirs.into_iter().flat_map(|base| {
+        shared_paths
+            .iter()
+            .map(move |shared_path| base.as_ref().join(shared_path))
+    });
+    scan(dirs, "", allowed_extensions, ignore_dotfiles)
+}
+
+/// Scan unique configuration fragments, also returning the masked ones.
+///
+/// This works like [`scan`], but additionally returns a map of the filenames which
+/// are masked (via a symlink to `/dev/null`) with no higher-priority fragment
+/// overriding the mask, holding the path of the effective mask. This allows showing
+/// e.g. "config X masked by /etc/…/X -> /dev/null", instead of the name just vanishing.
+pub fn scan_with_masks<BdS, BdI, Sp, As>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> (BTreeMap<OsString, PathBuf>, BTreeMap<OsString, PathBuf>)
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let shared_path = shared_path.as_ref();
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+
+    let mut resolver = Resolver::new();
+    let mut masks = BTreeMap::new();
+    for dir in base_dirs {
+        let dir = dir.as_ref().join(shared_path);
+        // Missing or unreadable directories are simply skipped.
+        let _ = read_dir_entries(&StdFs, &dir, &options, |fname, entry| {
+            if let Entry::Mask = entry {
+                masks.insert(fname.clone(), dir.join(&fname));
             }
+            resolver.apply(LayerPolicy::default(), fname, entry);
+        });
+    }
 
-            // If extensions are specified, proceed only if filename has one of the allowed
-            // extensions.
-            if !allowed_extensions.is_empty() {
-                if let Some(extension) = fpath.extension() {
-                    if !allowed_extensions.iter().any(|ae| ae.as_ref() == extension) {
-                        continue;
-                    }
-                } else {
-                    continue;
-                }
+    let fragments = FragmentSet::from_map(resolver.into_map()).into_paths();
+    masks.retain(|name, _| !fragments.contains_key(name));
+    (fragments, masks)
+}
+
+/// Scan unique configuration fragments into `fragments`, from a previous scan.
+///
+/// This works like [`scan`], with the directories scanned here taking precedence over
+/// those of the scans which produced `fragments`: their fragments override existing
+/// ones with the same filename, and their masks remove them. This allows layering
+/// several scans, e.g. with different shared paths or extensions, into one map:
+///
+/// ```rust,no_run
+/// let bases = liboverdrop::SYSTEMD_CONVENTIONAL_BASES;
+/// let mut fragments = liboverdrop::scan(bases, "legacy-name.d", &["conf"], true);
+/// liboverdrop::scan_into(&mut fragments, bases, "my-crate/config.d", &["conf", "toml"], true);
+/// ```
+pub fn scan_into<BdS, BdI, Sp, As>(
+    fragments: &mut BTreeMap<OsString, PathBuf>,
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let shared_path = shared_path.as_ref();
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+
+    for dir in base_dirs {
+        let dir = dir.as_ref().join(shared_path);
+        // Missing or unreadable directories are simply skipped.
+        let _ = read_dir_entries(&StdFs, &dir, &options, |fname, entry| match entry {
+            Entry::Fragment(fragment) | Entry::Empty(fragment) => {
+                fragments.insert(fname, fragment.into_path());
+            }
+            Entry::Mask => {
+                fragments.remove(&fname);
             }
+        });
+    }
+}
 
-            // Check filetype, ignore non-file.
-            let meta = match entry.metadata() {
-                Ok(m) => m,
-                _ => continue,
+/// Scan unique configuration fragments, failing on I/O errors instead of skipping them.
+///
+/// This works like [`scan`], but only missing directories are skipped: any other
+/// error while reading a directory or its entries (e.g. permission denied) is returned
+/// as [`ScanError::Io`], so that callers can tell an absent directory apart from an
+/// unreadable one, instead of silently falling back to lower-priority fragments.
+pub fn try_scan<BdS, BdI, Sp, As>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> Result<BTreeMap<OsString, PathBuf>, ScanError>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let shared_path = shared_path.as_ref();
+    let options = DirOptions {
+        strict: true,
+        ..DirOptions::new(allowed_extensions, ignore_dotfiles)
+    };
+
+    let mut resolver = Resolver::new();
+    for dir in base_dirs {
+        let dir = dir.as_ref().join(shared_path);
+        match scan_dir_into(&mut resolver, &dir, &options, LayerPolicy::default()) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                trace!("Skipping missing directory '{}'", dir.display());
+            }
+            Err(source) => return Err(ScanError::Io { path: dir, source }),
+        }
+    }
+
+    Ok(FragmentSet::from_map(resolver.into_map()).into_paths())
+}
+
+/// Return the directories which [`scan`] would look into, without touching the filesystem.
+///
+/// Directories are returned in increasing priority order (i.e. fragments in later
+/// directories override those in earlier ones), with `shared_path` joined onto each
+/// entry in `base_dirs`. This is useful e.g. for listing exactly where a service
+/// looks for configuration in `--help` output or generated documentation.
+pub fn effective_search_dirs<BdS, BdI, Sp>(base_dirs: BdI, shared_path: Sp) -> Vec<PathBuf>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+{
+    let shared_path = shared_path.as_ref();
+    base_dirs
+        .into_iter()
+        .map(|dir| dir.as_ref().join(shared_path))
+        .collect()
+}
+
+/// Scan unique configuration fragments, with different allowed extensions for each directory.
+///
+/// This works like [`scan`], but each entry of `base_dirs` is paired with its own
+/// `allowed_extensions` (with the same semantics as in [`scan`]). This is useful e.g. for
+/// staged format migrations, where vendor directories only ship `.toml` fragments but
+/// admin directories still accept legacy `.conf` ones.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// let base_dirs: [(&str, &[&str]); 2] = [
+///     ("/usr/lib", &["toml"]),
+///     ("/etc", &["toml", "conf"]),
+/// ];
+/// let fragments = liboverdrop::scan_per_dir(base_dirs, "my-crate/config.d", false);
+/// ```
+pub fn scan_per_dir<BdS, Ae, As, BdI, Sp>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    ignore_dotfiles: bool,
+) -> BTreeMap<OsString, PathBuf>
+where
+    BdS: AsRef<Path>,
+    Ae: AsRef<[As]>,
+    As: AsRef<OsStr>,
+    BdI: IntoIterator<Item = (BdS, Ae)>,
+    Sp: AsRef<Path>,
+{
+    let shared_path = shared_path.as_ref();
+
+    let mut resolver = Resolver::new();
+    for (dir, allowed_extensions) in base_dirs {
+        let dir = dir.as_ref().join(shared_path);
+        let options = DirOptions::new(allowed_extensions.as_ref(), ignore_dotfiles);
+        // Missing or unreadable directories are simply skipped.
+        let _ = scan_dir_into(&mut resolver, &dir, &options, LayerPolicy::default());
+    }
+
+    FragmentSet::from_map(resolver.into_map()).into_paths()
+}
+
+/// A shareable predicate over fragment filenames.
+type NamePredicate = Arc<dyn Fn(&OsStr) -> bool + Send + Sync>;
+
+/// A shareable predicate over candidate entries, by path and (non-followed) metadata.
+type EntryPredicate = Arc<dyn Fn(&Path, &fs::Metadata) -> bool + Send + Sync>;
+
+/// A shareable callback receiving skipped entries.
+type SkipFn = Arc<dyn Fn(&SkippedEntry) + Send + Sync>;
+
+/// A shareable function deriving override keys from fragment names.
+type KeyFn = Arc<dyn Fn(&OsStr) -> OsString + Send + Sync>;
+
+/// Filtering options applied to the entries of each scanned directory.
+#[derive(Clone, Default)]
+struct DirOptions {
+    /// Normalized allowed extensions; empty means all are allowed.
+    allowed_extensions: Vec<String>,
+    ignore_dotfiles: bool,
+    /// Additional caller-supplied policy for hidden files.
+    hidden: Option<NamePredicate>,
+    /// Whether to report files ignored because of their extension.
+    report_unmatched: bool,
+    /// Whether to fail on errors reading entries, instead of skipping them.
+    strict: bool,
+    /// How many levels of subdirectories to descend into.
+    max_depth: usize,
+    /// Filename patterns, one of which must match; empty means all are allowed.
+    patterns: Vec<NamePattern>,
+    /// Filename patterns which always exclude an entry.
+    excluded: Vec<NamePattern>,
+    /// Filename prefix which must match; empty means all are allowed.
+    name_prefix: String,
+    /// Handling of symlinks other than masks.
+    symlinks: SymlinkPolicy,
+    /// Whether broken symlinks mask fragments.
+    broken_symlinks_mask: bool,
+    /// Ownership and permission requirements, if any.
+    trust: Option<TrustPolicy>,
+    /// Alternate root that mask symlinks are resolved against, if any.
+    root: Option<PathBuf>,
+    /// Additional caller-supplied filter for candidate entries.
+    filter: Option<EntryPredicate>,
+    /// Caller-supplied callback for skipped entries.
+    on_skipped: Option<SkipFn>,
+    /// Caller-supplied override keys; by default, the fragment name is the key.
+    key: Option<KeyFn>,
+    /// Normalized extensions, in decreasing priority order for same-stem fragments.
+    extension_priority: Vec<String>,
+}
+
+impl fmt::Debug for DirOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirOptions")
+            .field("allowed_extensions", &self.allowed_extensions)
+            .field("ignore_dotfiles", &self.ignore_dotfiles)
+            .field("hidden", &self.hidden.as_ref().map(|_| ".."))
+            .field("report_unmatched", &self.report_unmatched)
+            .field("strict", &self.strict)
+            .field("max_depth", &self.max_depth)
+            .field("patterns", &self.patterns)
+            .field("excluded", &self.excluded)
+            .field("name_prefix", &self.name_prefix)
+            .field("symlinks", &self.symlinks)
+            .field("broken_symlinks_mask", &self.broken_symlinks_mask)
+            .field("trust", &self.trust)
+            .field("root", &self.root)
+            .field("filter", &self.filter.as_ref().map(|_| ".."))
+            .field("on_skipped", &self.on_skipped.as_ref().map(|_| ".."))
+            .field("key", &self.key.as_ref().map(|_| ".."))
+            .field("extension_priority", &self.extension_priority)
+            .finish()
+    }
+}
+
+impl DirOptions {
+    fn new<As: AsRef<OsStr>>(allowed_extensions: &[As], ignore_dotfiles: bool) -> Self {
+        Self {
+            allowed_extensions: normalize_extensions(allowed_extensions),
+            ignore_dotfiles,
+            hidden: None,
+            report_unmatched: false,
+            strict: false,
+            max_depth: 0,
+            patterns: Vec::new(),
+            excluded: Vec::new(),
+            name_prefix: String::new(),
+            symlinks: SymlinkPolicy::default(),
+            broken_symlinks_mask: false,
+            trust: None,
+            root: None,
+            filter: None,
+            on_skipped: None,
+            key: None,
+            extension_priority: Vec::new(),
+        }
+    }
+
+    /// Pass the entry at `path`, skipped for `reason`, to the caller-supplied callback (if any).
+    fn skipped(&self, path: &Path, reason: impl FnOnce() -> SkipReason) {
+        if let Some(on_skipped) = &self.on_skipped {
+            on_skipped(&SkippedEntry::new(path.to_path_buf(), reason()));
+        }
+    }
+
+    /// Whether `fname` is a hidden file, to be ignored.
+    fn is_hidden(&self, fname: &OsStr) -> bool {
+        // Rust RFC 900 &c.: there's no way to check if a Path/OsStr starts with a prefix;
+        // instead, we check via to_string_lossy(), which will only allocate if the basename wasn't UTF-8,
+        // and the lossiness doesn't bother us; https://github.com/rust-lang/rfcs/issues/900
+        if self.ignore_dotfiles && fname.to_string_lossy().starts_with('.') {
+            return true;
+        }
+        self.hidden.as_ref().map_or(false, |policy| policy(fname))
+    }
+
+    /// Whether an entry named `fname` should be considered at all, based on its name only.
+    fn accepts_name(&self, fname: &OsStr) -> bool {
+        // If hidden files not allowed, ignore dotfiles (and whatever else the policy hides).
+        if self.is_hidden(fname) {
+            return false;
+        }
+
+        self.matches_extension(fname) && self.matches_patterns(fname)
+    }
+
+    /// Whether entry names are mapped to different override keys.
+    fn is_keyed(&self) -> bool {
+        self.key.is_some() || !self.extension_priority.is_empty()
+    }
+
+    /// Return the override key for `fname`.
+    ///
+    /// Without a caller-supplied key function, names with a prioritized extension
+    /// are keyed by their stem.
+    fn key_of(&self, fname: &OsStr) -> OsString {
+        if let Some(key) = &self.key {
+            return key(fname);
+        }
+        let stem = fname.to_str().and_then(|name| {
+            self.extension_priority.iter().find_map(|ext| {
+                name.strip_suffix(ext.as_str())
+                    .and_then(|stem| stem.strip_suffix('.'))
+                    .filter(|stem| !stem.is_empty())
+            })
+        });
+        stem.map_or_else(|| fname.to_os_string(), OsString::from)
+    }
+
+    /// Precedence of `fname` among entries of the same directory sharing its key (higher wins).
+    fn extension_precedence(&self, fname: &OsStr) -> usize {
+        let exts = &self.extension_priority;
+        exts.iter()
+            .position(|ext| has_allowed_extension(fname, &[ext]))
+            .map_or(0, |idx| exts.len() - idx)
+    }
+
+    /// Whether `fname` has the prefix and matches one of the patterns (if any is specified), and none of the excluded ones.
+    fn matches_patterns(&self, fname: &OsStr) -> bool {
+        // Same as for dotfiles, the lossy conversion only allocates for non-UTF-8 names.
+        if !fname
+            .to_string_lossy()
+            .starts_with(self.name_prefix.as_str())
+        {
+            return false;
+        }
+        (self.patterns.is_empty() || self.patterns.iter().any(|p| p.matches(fname)))
+            && !self.excluded.iter().any(|p| p.matches(fname))
+    }
+
+    /// Whether `fname` has one of the allowed extensions (if any is specified).
+    fn matches_extension(&self, fname: &OsStr) -> bool {
+        // If extensions are specified, proceed only if filename has one of the allowed
+        // extensions.
+        self.allowed_extensions.is_empty() || has_allowed_extension(fname, &self.allowed_extensions)
+    }
+}
+
+/// Return the kind of the entry at `path` in `source`, with its (non-followed) metadata if available.
+fn entry_info<S>(source: &S, path: &Path) -> io::Result<(EntryKind, Option<fs::Metadata>)>
+where
+    S: FragmentSource + ?Sized,
+{
+    match source.metadata(path, false) {
+        Some(meta) => {
+            let meta = meta?;
+            Ok((EntryKind::of(&meta), Some(meta)))
+        }
+        None => Ok((source.entry_kind(path)?, None)),
+    }
+}
+
+/// Classify the entry at `fpath` in `source`, given its kind and (non-followed) metadata, if any.
+///
+/// Mask symlinks are resolved under `root`, if any, see [`is_mask_link`].
+fn classify_entry<S>(
+    source: &S,
+    fpath: PathBuf,
+    kind: EntryKind,
+    meta: Option<&fs::Metadata>,
+    root: Option<&Path>,
+) -> Option<Entry<Fragment>>
+where
+    S: FragmentSource + ?Sized,
+{
+    match kind {
+        EntryKind::File { len } => {
+            let fragment = match meta {
+                Some(meta) => Fragment::new(fpath, meta),
+                None => Fragment::without_metadata(fpath, len),
             };
-            if !meta.file_type().is_file() {
-                if let Ok(target) = fs::read_link(&fpath) {
-                    // A devnull symlink is a special case to ignore previous file-names.
-                    if target == Path::new(DEVNULL) {
-                        trace!("Nulled config file '{}'", fpath.display());
-                        files_map.remove(&fname);
-                    }
+            return Some(if fragment.is_empty() {
+                Entry::Empty(fragment)
+            } else {
+                Entry::Fragment(fragment)
+            });
+        }
+        EntryKind::Symlink => {}
+        _ => return None,
+    }
+
+    // A devnull symlink is a special case to ignore previous file-names.
+    match source.read_link(&fpath) {
+        Ok(target) if is_mask_link(&fpath, &target, root, |link| source.read_link(link)) => {
+            trace!("Nulled config file '{}'", fpath.display());
+            #[cfg(feature = "tracing")]
+            tracing::trace!(path = %fpath.display(), "found mask");
+            Some(Entry::Mask)
+        }
+        _ => None,
+    }
+}
+
+/// Read the entries of a single directory which match `options`, passing them to `found`.
+///
+/// Entries in subdirectories (up to the maximum depth) are named by their relative path,
+/// and names are mapped to keys if requested (in extension priority order, then filename
+/// order, so that the last one wins among the ones sharing a key). Returns the matching (and, if requested, unmatched) entries, or an error
+/// if the directory itself could not be read (or, if strict, any of its entries).
+fn read_dir_entries<S, F>(
+    source: &S,
+    dir: &Path,
+    options: &DirOptions,
+    mut found: F,
+) -> io::Result<DirScan>
+where
+    S: FragmentSource + ?Sized,
+    F: FnMut(OsString, Entry<Fragment>),
+{
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("scan_dir", dir = %dir.display()).entered();
+    let mut scan = DirScan::default();
+    let (prefix, depth) = (Path::new(""), options.max_depth);
+    if options.is_keyed() {
+        let mut entries = Vec::new();
+        let mut push = |name, entry| entries.push((name, entry));
+        read_dir_level(source, dir, prefix, depth, options, &mut push, &mut scan)?;
+        entries.sort_by_cached_key(|(name, _)| (options.extension_precedence(name), name.clone()));
+        for (name, entry) in entries {
+            found(options.key_of(&name), entry);
+        }
+    } else {
+        read_dir_level(source, dir, prefix, depth, options, &mut found, &mut scan)?;
+    }
+    Ok(scan)
+}
+
+/// Read the entries of `dir`, at `prefix` relative to the scanned directory, see `read_dir_entries`.
+fn read_dir_level<S, F>(
+    source: &S,
+    dir: &Path,
+    prefix: &Path,
+    depth: usize,
+    options: &DirOptions,
+    found: &mut F,
+    scan: &mut DirScan,
+) -> io::Result<()>
+where
+    S: FragmentSource + ?Sized,
+    F: FnMut(OsString, Entry<Fragment>),
+{
+    trace!("Scanning directory '{}'", dir.display());
+
+    let names = source.list(dir).map_err(|e| {
+        if e.kind() != io::ErrorKind::NotFound {
+            options.skipped(dir, || SkipReason::Unreadable(e.kind()));
+        }
+        e
+    })?;
+    for fname in names {
+        let fname = match fname {
+            Ok(fname) => fname,
+            Err(e) => {
+                trace!("Skipping unreadable entry in '{}': {}", dir.display(), e);
+                options.skipped(dir, || SkipReason::Unreadable(e.kind()));
+                if options.strict {
+                    return Err(e);
                 }
                 continue;
             }
+        };
+        let fpath = dir.join(&fname);
+        let is_kind =
+            |is: fn(&EntryKind) -> bool| source.entry_kind(&fpath).map_or(false, |k| is(&k));
 
-            trace!(
-                "Found config file '{}' at '{}'",
-                Path::new(&fname).display(),
-                fpath.display()
-            );
-            files_map.insert(fname, fpath);
+        // If hidden files not allowed, ignore dotfiles (and whatever else the policy hides).
+        if options.is_hidden(&fname) {
+            options.skipped(&fpath, || SkipReason::Hidden);
+            continue;
+        }
+        if depth > 0 && is_kind(|k| *k == EntryKind::Dir) {
+            let subdir = prefix.join(&fname);
+            match read_dir_level(source, &fpath, &subdir, depth - 1, options, found, scan) {
+                // Subdirectories removed in the meanwhile are fine, even if strict.
+                Err(e) if options.strict && e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => continue,
+            }
+        }
+        if !options.matches_extension(&fname) {
+            // Likely a typo'd fragment (e.g. `50-custom.tomll`), worth a warning.
+            if options.report_unmatched && is_kind(|k| matches!(k, EntryKind::File { .. })) {
+                warn!(
+                    "Ignoring file '{}', not matching allowed extensions",
+                    fpath.display()
+                );
+                scan.unmatched.push(prefix.join(fname).into_os_string());
+            }
+            options.skipped(&fpath, || SkipReason::UnmatchedExtension);
+            continue;
+        }
+        if !options.matches_patterns(&fname) {
+            options.skipped(&fpath, || SkipReason::UnmatchedPattern);
+            continue;
+        }
+
+        let entry = classify_candidate(source, fpath, options, scan)?;
+        if let Some(entry) = entry {
+            let name = prefix.join(fname).into_os_string();
+            if let Entry::Fragment(fragment) | Entry::Empty(fragment) = &entry {
+                trace!(
+                    "Found config file '{}' at '{}'",
+                    Path::new(&name).display(),
+                    fragment.path().display()
+                );
+                #[cfg(feature = "tracing")]
+                tracing::trace!(
+                    name = %Path::new(&name).display(),
+                    path = %fragment.path().display(),
+                    "found fragment"
+                );
+            }
+            found(name, entry);
+            scan.matched += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Classify the entry at `fpath` in `source`, whose name already matches `options`.
+///
+/// This applies the checks of `options` needing metadata (hidden attribute, filter and
+/// trust policy), then classifies the entry. Skipped entries are reported to the
+/// callback of `options`; errors are only returned if strict.
+fn classify_candidate<S>(
+    source: &S,
+    fpath: PathBuf,
+    options: &DirOptions,
+    scan: &mut DirScan,
+) -> io::Result<Option<Entry<Fragment>>>
+where
+    S: FragmentSource + ?Sized,
+{
+    // Check filetype, ignore non-file.
+    let (kind, meta) = match entry_info(source, &fpath) {
+        Ok(info) => info,
+        // Entries removed in the meanwhile are fine, even if strict.
+        Err(e) if options.strict && e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(e) => {
+            options.skipped(&fpath, || SkipReason::Metadata(e.kind()));
+            return Ok(None);
+        }
+    };
+    if options.ignore_dotfiles && meta.as_ref().map_or(false, has_hidden_attribute) {
+        options.skipped(&fpath, || SkipReason::Hidden);
+        return Ok(None);
+    }
+    // Filters and trust policies need metadata: sources without it pass neither.
+    if let Some(filter) = &options.filter {
+        if !meta.as_ref().map_or(false, |meta| filter(&fpath, meta)) {
+            trace!("Filtered out '{}'", fpath.display());
+            options.skipped(&fpath, || SkipReason::Filtered);
+            return Ok(None);
         }
     }
+    let trusted = options.trust.map(|trust| match &meta {
+        Some(meta) => trust.check(meta),
+        None => Err("no metadata to check ownership".to_string()),
+    });
+    if let Some(Err(reason)) = trusted {
+        warn!("Rejected config file '{}': {}", fpath.display(), reason);
+        options.skipped(&fpath, || SkipReason::Rejected(reason.clone()));
+        scan.rejected.push(Rejection::new(fpath, reason));
+        return Ok(None);
+    }
+    // Only keep the path around for the callback if needed.
+    let skipped_path = options.on_skipped.as_ref().map(|_| fpath.clone());
+    let entry = if kind == EntryKind::Symlink {
+        classify_symlink(source, fpath, options, scan)
+    } else {
+        classify_entry(source, fpath, kind, meta.as_ref(), options.root.as_deref())
+    };
+    if let (None, Some(path)) = (&entry, skipped_path) {
+        options.skipped(&path, || SkipReason::NotAFile);
+    }
+    Ok(entry)
+}
+
+/// Scan a single directory as the next layer of `resolver`.
+fn scan_dir_into(
+    resolver: &mut Resolver<Fragment>,
+    dir: &Path,
+    options: &DirOptions,
+    policy: LayerPolicy,
+) -> io::Result<DirScan> {
+    read_dir_entries(&StdFs, dir, options, |fname, entry| {
+        resolver.apply(policy, fname, entry)
+    })
+}
 
-    files_map
+/// Scan unique configuration fragments as `scan` does, as a list sorted by filename.
+fn scan_streaming<BdS, BdI, Sp>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    options: &DirOptions,
+) -> Vec<(OsString, PathBuf)>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+{
+    scan_streaming_layers(base_dirs, shared_path, options)
+        .into_iter()
+        .map(|(fname, fpath, _)| (fname, fpath))
+        .collect()
+}
+
+/// As for [`scan_streaming`], also returning the index of the base directory of each fragment.
+fn scan_streaming_layers<BdS, BdI, Sp>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    options: &DirOptions,
+) -> Vec<(OsString, PathBuf, usize)>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+{
+    let mut resolver = Resolver::new();
+    for (layer, dir) in effective_search_dirs(base_dirs, shared_path)
+        .iter()
+        .enumerate()
+    {
+        // Missing or unreadable directories are simply skipped.
+        let _ = read_dir_entries(&StdFs, dir, options, |fname, entry| {
+            let entry = entry.map(|fragment| fragment.in_search_layer(layer));
+            resolver.apply(LayerPolicy::default(), fname, entry)
+        });
+    }
+    resolver
+        .into_map()
+        .into_iter()
+        .map(|(fname, fragment)| {
+            let layer = fragment.search_layer();
+            (fname, fragment.into_path(), layer)
+        })
+        .collect()
+}
+
+/// Resolve the winning fragment for a single `fname` across `layers` (in increasing priority order).
+///
+/// This follows the same rules as `scan_dir_into` (including the symlink and trust
+/// policies of `options`), without reading whole directories.
+fn resolve_name(
+    layers: &[(PathBuf, LayerPolicy)],
+    fname: &OsStr,
+    options: &DirOptions,
+) -> Option<Fragment> {
+    let mut resolver = Resolver::new();
+    let mut scan = DirScan::default();
+    for (dir, policy) in layers {
+        let fpath = dir.join(fname);
+        let entry = classify_candidate(&StdFs, fpath, options, &mut scan);
+        if let Ok(Some(entry)) = entry {
+            resolver.apply(*policy, fname.to_os_string(), entry);
+        }
+    }
+    resolver.into_map().into_values().next()
+}
+
+/// Normalize allowed extensions, stripping any leading dot (i.e. `.toml` is the same as `toml`).
+///
+/// As in `scan`, non-UTF-8 extensions are lossily converted; the lossiness doesn't
+/// bother us, as filenames are converted the same way when matching.
+fn normalize_extensions<As: AsRef<OsStr>>(allowed_extensions: &[As]) -> Vec<String> {
+    allowed_extensions
+        .iter()
+        .map(|ae| {
+            let ae = ae.as_ref().to_string_lossy();
+            ae.strip_prefix('.').unwrap_or(&ae).to_string()
+        })
+        .collect()
+}
+
+/// Check whether `fname` ends with one of the (normalized) `allowed_extensions`.
+///
+/// Simple extensions (e.g. `toml`) are compared against the last extension component,
+/// while composite ones (e.g. `conf.toml`) are matched as a full filename suffix.
+/// In both cases, the extension must be preceded by a non-empty stem.
+fn has_allowed_extension<As: AsRef<str>>(fname: &OsStr, allowed_extensions: &[As]) -> bool {
+    let extension = Path::new(fname).extension().map(OsStr::to_string_lossy);
+    allowed_extensions.iter().any(|ae| {
+        let ae = ae.as_ref();
+        if ae.contains('.') {
+            fname
+                .to_string_lossy()
+                .strip_suffix(ae)
+                .and_then(|stem| stem.strip_suffix('.'))
+                .map_or(false, |stem| !stem.is_empty())
+        } else {
+            extension.as_deref() == Some(ae)
+        }
+    })
 }
 
 #[cfg(test)]
@@ -283,6 +1329,111 @@ mod tests {
         assert_eq!(fragments_keys, expected_keys);
     }
 
+    #[test]
+    fn multiple_shared_paths() {
+        let treedir = Path::new("tests/fixtures/tree-shared");
+        let dirs = ["usr/lib", "etc"].map(|d| treedir.join(d));
+        let fragments =
+            scan_shared_paths(&dirs, &["myapp/conf.d", "myapp/config.d"], &["conf"], false);
+        let fragments: Vec<_> = fragments
+            .values()
+            .map(|path| path.strip_prefix(treedir).unwrap())
+            .collect();
+        assert_eq!(
+            fragments,
+            [
+                "etc/myapp/conf.d/10-a.conf",
+                "usr/lib/myapp/conf.d/20-b.conf",
+                "usr/lib/myapp/config.d/30-c.conf",
+            ]
+            .map(Path::new)
+        );
+    }
+
+    #[test]
+    fn masked_names() {
+        let treedir = Path::new("tests/fixtures/tree-mask");
+        let dirs = ["usr/lib", "etc", "run"].map(|d| treedir.join(d));
+        let (fragments, masked) =
+            scan_with_masks::<_, _, _, &str>(&dirs, "liboverdrop.d", &[], false);
+        assert_eq!(
+            fragments,
+            scan::<_, _, _, &str>(&dirs, "liboverdrop.d", &[], false)
+        );
+        let expected: BTreeMap<OsString, PathBuf> = [
+            ("20-b.conf".into(), dirs[1].join("liboverdrop.d/20-b.conf")),
+            ("40-d.conf".into(), dirs[2].join("liboverdrop.d/40-d.conf")),
+        ]
+        .into();
+        assert_eq!(masked, expected);
+
+        // Masks overridden by a higher-priority fragment are not reported.
+        let dirs = ["etc", "usr/lib"].map(|d| treedir.join(d));
+        let (fragments, masked) =
+            scan_with_masks::<_, _, _, &str>(&dirs, "liboverdrop.d", &[], false);
+        assert_eq!(fragments.len(), 3);
+        assert!(masked.is_empty());
+    }
+
+    #[test]
+    fn composed_scans() {
+        let tree = testing::TreeBuilder::new()
+            .fragment("usr/lib/old.d/10-a.conf", "legacy")
+            .fragment("usr/lib/old.d/20-b.conf", "legacy")
+            .fragment("etc/old.d/30-c.conf", "legacy")
+            .fragment("usr/lib/new.d/10-a.toml", "vendor")
+            .fragment("usr/lib/new.d/20-b.conf", "vendor")
+            .mask("etc/new.d/30-c.conf");
+        let dirs = tree.base_dirs(["usr/lib", "etc"]);
+        let mut fragments = scan(&dirs, "old.d", &["conf"], false);
+        scan_into(&mut fragments, &dirs, "new.d", &["conf", "toml"], false);
+        let expected: BTreeMap<OsString, PathBuf> = [
+            ("10-a.conf".into(), tree.path("usr/lib/old.d/10-a.conf")),
+            ("10-a.toml".into(), tree.path("usr/lib/new.d/10-a.toml")),
+            ("20-b.conf".into(), tree.path("usr/lib/new.d/20-b.conf")),
+        ]
+        .into();
+        assert_eq!(fragments, expected);
+
+        let mut fragments = BTreeMap::new();
+        scan_into(&mut fragments, &dirs, "old.d", &["conf"], false);
+        assert_eq!(fragments, scan(&dirs, "old.d", &["conf"], false));
+    }
+
+    #[test]
+    fn try_scan_errors() {
+        let treedir = Path::new("tests/fixtures/tree-basic");
+        let dirs = ["usr/lib", "run", "etc", "missing"].map(|d| treedir.join(d));
+        let fragments = try_scan(&dirs, "liboverdrop.d", &["toml"], false).unwrap();
+        assert_eq!(fragments, scan(&dirs, "liboverdrop.d", &["toml"], false));
+
+        // Reading a file as a directory fails, and is not the same as it being missing.
+        let file = treedir.join("etc/liboverdrop.d/config.conf");
+        match try_scan::<_, _, _, &str>([&file], "", &[], false) {
+            Err(ScanError::Io { path, source }) => {
+                assert_eq!(path, file.join(""));
+                assert_ne!(source.kind(), io::ErrorKind::NotFound);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(scan::<_, _, _, &str>([&file], "", &[], false).is_empty());
+    }
+
+    #[test]
+    fn search_dirs() {
+        let dirs = effective_search_dirs(SYSTEMD_CONVENTIONAL_BASES, "my-crate/config.d");
+        let expected = [
+            "/usr/lib/my-crate/config.d",
+            "/usr/local/lib/my-crate/config.d",
+            "/etc/my-crate/config.d",
+            "/run/my-crate/config.d",
+        ];
+        assert_eq!(dirs, expected.map(PathBuf::from));
+
+        let dirs = effective_search_dirs(["/nonexistent"], "");
+        assert_eq!(dirs, [PathBuf::from("/nonexistent")]);
+    }
+
     #[test]
     fn basic_override_restrict_extensions() {
         let treedir = "tests/fixtures/tree-basic";
@@ -307,6 +1458,209 @@ mod tests {
         assert_fragments_hit(&fragments, "noextension");
     }
 
+    #[test]
+    fn composite_extensions() {
+        let dirs = ["tests/fixtures/tree-extensions"];
+
+        let fragments = scan(dirs, "liboverdrop.d", &["conf.toml", "ign.json"], false);
+        let keys: Vec<_> = fragments.into_keys().collect();
+        assert_eq!(keys, ["10-a.conf.toml", "30-c.ign.json"]);
+
+        let fragments = scan(dirs, "liboverdrop.d", &["conf.toml", "json"], false);
+        let keys: Vec<_> = fragments.into_keys().collect();
+        assert_eq!(keys, ["10-a.conf.toml", "30-c.ign.json", "40-d.json"]);
+
+        let fragments = scan(dirs, "liboverdrop.d", &["toml"], false);
+        let keys: Vec<_> = fragments.into_keys().collect();
+        assert_eq!(keys, ["10-a.conf.toml", "20-b.toml"]);
+
+        assert!(!has_allowed_extension(
+            OsStr::new(".conf.toml"),
+            &["conf.toml"]
+        ));
+        assert!(!has_allowed_extension(
+            OsStr::new("fooconf.toml"),
+            &["conf.toml"]
+        ));
+    }
+
+    #[test]
+    fn leading_dot_extensions() {
+        let dirs = ["tests/fixtures/tree-extensions"];
+
+        let fragments = scan(dirs, "liboverdrop.d", &[".toml", "conf"], false);
+        let keys: Vec<_> = fragments.into_keys().collect();
+        assert_eq!(keys, ["10-a.conf.toml", "20-b.toml", "50-e.conf"]);
+
+        let fragments = scan(dirs, "liboverdrop.d", &[".ign.json"], false);
+        let keys: Vec<_> = fragments.into_keys().collect();
+        assert_eq!(keys, ["30-c.ign.json"]);
+    }
+
+    #[test]
+    fn per_dir_extensions() {
+        let treedir = "tests/fixtures/tree-basic";
+        let dirs: [(String, &[&str]); 2] = [
+            (format!("{}/{}", treedir, "usr/lib"), &["toml"]),
+            (format!("{}/{}", treedir, "etc"), &["toml", "conf"]),
+        ];
+
+        let fragments = scan_per_dir(dirs, "liboverdrop.d", true);
+
+        assert_fragments_hit(&fragments, "04-config-d.toml");
+        assert_fragments_hit(&fragments, "01-config-a.toml");
+        assert_fragments_hit(&fragments, "config.conf");
+        assert_fragments_miss(&fragments, "noextension");
+        assert_fragments_miss(&fragments, ".hidden.conf");
+
+        let dirs: [(String, &[&str]); 1] = [(format!("{}/{}", treedir, "etc"), &[])];
+        let fragments = scan_per_dir(dirs, "liboverdrop.d", false);
+        assert_fragments_hit(&fragments, "noextension");
+    }
+
+    #[test]
+    fn streaming_matches_scan() {
+        for treedir in ["tests/fixtures/tree-basic", "tests/fixtures/tree-mask"] {
+            let dirs = ["usr/lib", "etc", "run"].map(|d| Path::new(treedir).join(d));
+            for (exts, ignore_dotfiles) in
+                [(&[][..], false), (&[][..], true), (&["toml"][..], false)]
+            {
+                let options = DirOptions::new(exts, ignore_dotfiles);
+                let streamed = scan_streaming(&dirs, "liboverdrop.d", &options);
+                let expected = scan(&dirs, "liboverdrop.d", exts, ignore_dotfiles);
+                assert_eq!(streamed, expected.into_iter().collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apis_agree() {
+        let tree = testing::TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .fragment("usr/lib/svc.d/20-b.conf", "vendor")
+            .fragment("usr/lib/svc.d/30-c.conf", "vendor")
+            .fragment("usr/lib/svc.d/40-d.conf", "vendor")
+            .fragment("usr/lib/svc.d/50-empty.conf", "")
+            .fragment("usr/lib/svc.d/.60-hidden.conf", "vendor")
+            .fragment("etc/svc.d/10-a.conf", "admin")
+            .mask("etc/svc.d/20-b.conf")
+            .symlink("etc/svc.d/30-c.conf", "../null-mask")
+            .symlink("etc/null-mask", "../etc/chained-mask")
+            .mask("etc/chained-mask")
+            .symlink("etc/svc.d/40-d.conf", "../../usr/lib/svc.d/40-d.conf")
+            .mask("etc/svc.d/.60-hidden.conf")
+            .fragment("run/svc.d/70-g.conf", "runtime")
+            .base_dir("run/svc.d/80-dir.conf");
+        let bases = ["usr/lib", "etc", "run"];
+        let dirs = tree.base_dirs(bases);
+        let exts = ["conf"];
+
+        for ignore_dotfiles in [false, true] {
+            let expected = scan(&dirs, "svc.d", &exts, ignore_dotfiles);
+            let names: Vec<_> = expected.keys().map(|n| n.to_str().unwrap()).collect();
+            assert_eq!(
+                names,
+                ["10-a.conf", "40-d.conf", "50-empty.conf", "70-g.conf"]
+            );
+            let as_vec = || expected.clone().into_iter().collect::<Vec<_>>();
+
+            let options = DirOptions::new(&exts, ignore_dotfiles);
+            assert_eq!(scan_streaming(&dirs, "svc.d", &options), as_vec());
+            let iterated: Vec<_> = scan_iter(&dirs, "svc.d", &exts, ignore_dotfiles).collect();
+            assert_eq!(iterated, as_vec());
+            assert_eq!(
+                scan_source(&StdFs, &dirs, "svc.d", &exts, ignore_dotfiles),
+                expected
+            );
+            let scanner = Overdrop::new("svc.d")
+                .base_dirs(&dirs)
+                .allowed_extensions(&exts)
+                .ignore_dotfiles(ignore_dotfiles);
+            assert_eq!(scanner.scan().unwrap(), expected);
+
+            #[cfg(all(target_os = "linux", feature = "getdents"))]
+            assert_eq!(scan_fast(&dirs, "svc.d", &exts, ignore_dotfiles), expected);
+
+            #[cfg(feature = "dirfd")]
+            {
+                use std::os::fd::AsFd;
+                let root = fs::File::open(tree.root()).unwrap();
+                let fragments =
+                    dirfd::scan_at(root.as_fd(), bases, "svc.d", &exts, ignore_dotfiles);
+                let fragments: BTreeMap<_, _> = fragments
+                    .into_iter()
+                    .map(|(name, path)| (name, tree.path(path)))
+                    .collect();
+                assert_eq!(fragments, expected);
+            }
+
+            #[cfg(feature = "tokio")]
+            {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                let fragments = scan_async(&dirs, "svc.d", &exts, ignore_dotfiles);
+                assert_eq!(runtime.block_on(fragments), expected);
+                #[cfg(feature = "stream")]
+                {
+                    use futures_util::StreamExt;
+                    let stream = scan_stream(&dirs, "svc.d", &exts, ignore_dotfiles);
+                    assert_eq!(runtime.block_on(stream.collect::<Vec<_>>()), as_vec());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn windows_bases_and_masks() {
+        let bases = windows_bases_with(|name| match name {
+            "ProgramData" => Some(r"C:\ProgramData".into()),
+            "APPDATA" => Some("".into()),
+            _ => None,
+        });
+        assert_eq!(bases, [PathBuf::from(r"C:\ProgramData")]);
+
+        assert!(is_mask_target(Path::new("/dev/null")));
+        assert_eq!(is_mask_target(Path::new("NUL")), cfg!(windows));
+        assert_eq!(is_mask_target(Path::new(r"\\.\nul")), cfg!(windows));
+        assert!(!is_mask_target(Path::new("/dev/zero")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn chained_masks() {
+        let to_root = "../".repeat(64);
+        let tree = testing::TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-chained.conf", "vendor")
+            .fragment("usr/lib/svc.d/20-relative.conf", "vendor")
+            .fragment("usr/lib/svc.d/30-loop.conf", "vendor")
+            .fragment("usr/lib/svc.d/40-file.conf", "vendor")
+            .mask("etc/null-mask")
+            .symlink("etc/svc.d/10-chained.conf", "../null-mask")
+            .symlink(
+                "etc/svc.d/20-relative.conf",
+                format!("{}dev/./null", to_root),
+            )
+            .symlink("etc/svc.d/30-loop.conf", "30-loop.conf")
+            .fragment("etc/other.conf", "admin")
+            .symlink("etc/svc.d/40-file.conf", "../other.conf");
+        let dirs = tree.base_dirs(["usr/lib", "etc"]);
+        let fragments = scan(&dirs, "svc.d", &["conf"], false);
+        assert_eq!(
+            fragments.keys().collect::<Vec<_>>(),
+            ["30-loop.conf", "40-file.conf"]
+        );
+        assert_eq!(
+            fragments[OsStr::new("30-loop.conf")],
+            tree.path("usr/lib/svc.d/30-loop.conf")
+        );
+
+        let options = DirOptions::new(&["conf"], false);
+        let streamed = scan_streaming(&dirs, "svc.d", &options);
+        assert_eq!(streamed, fragments.into_iter().collect::<Vec<_>>());
+    }
+
     #[test]
     fn basic_override_ignore_hidden() {
         let treedir = "tests/fixtures/tree-basic";
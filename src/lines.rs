@@ -0,0 +1,182 @@
+//! Line-oriented merging, for formats where fragments override individual lines.
+
+use crate::scan;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// A single line of a line-oriented configuration, with its provenance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    text: String,
+    source: PathBuf,
+    number: usize,
+}
+
+impl Line {
+    /// Text of the line, without the trailing newline.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Path of the fragment holding the line.
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// Line number in the fragment, starting from 1.
+    pub fn number(&self) -> usize {
+        self.number
+    }
+}
+
+/// The merged set of lines resulting from [`scan_lines`], by key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineSet {
+    lines: BTreeMap<String, Line>,
+}
+
+impl LineSet {
+    /// Return the effective line for `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&Line> {
+        self.lines.get(key)
+    }
+
+    /// Number of effective lines.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Whether there is no effective line.
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Iterate over effective lines, in order by key.
+    pub fn iter(&self) -> btree_map::Iter<'_, String, Line> {
+        self.lines.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a LineSet {
+    type Item = (&'a String, &'a Line);
+    type IntoIter = btree_map::Iter<'a, String, Line>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Return the first whitespace-separated field of `line`, as used by [`scan_lines`] keys.
+pub fn first_field(line: &str) -> Option<String> {
+    line.split_whitespace().next().map(String::from)
+}
+
+/// Scan configuration fragments and merge them line by line.
+///
+/// This is meant for `tmpfiles.d`/`sysusers.d`-style formats, where each line is
+/// identified by a field (e.g. a path or a user name). Arguments are the same as for
+/// [`scan`]; the unique fragments are then read in the same order as returned by
+/// [`scan`], and each line replaces any line with the same key from a previous
+/// fragment (or earlier in the same fragment).
+///
+/// Empty lines and comments (lines starting with `#`) are skipped. `key` returns the
+/// key of any other line, or `None` to skip it.
+pub fn scan_lines<BdS, BdI, Sp, As, K>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    mut key: K,
+) -> io::Result<LineSet>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    K: FnMut(&str) -> Option<String>,
+{
+    let fragments = scan(base_dirs, shared_path, allowed_extensions, ignore_dotfiles);
+    let mut lines = BTreeMap::new();
+    for path in fragments.into_values() {
+        let reader = BufReader::new(File::open(&path)?);
+        for (idx, text) in reader.lines().enumerate() {
+            let text = text?;
+            let trimmed = text.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            if let Some(k) = key(trimmed) {
+                let line = Line {
+                    text,
+                    source: path.clone(),
+                    number: idx + 1,
+                };
+                lines.insert(k, line);
+            }
+        }
+    }
+    Ok(LineSet { lines })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREEDIR: &str = "tests/fixtures/tree-lines";
+
+    fn dirs() -> [PathBuf; 3] {
+        ["usr/lib", "etc", "run"].map(|d| Path::new(TREEDIR).join(d))
+    }
+
+    #[test]
+    fn per_line_overrides() {
+        // tmpfiles.d-style: the path is the second field.
+        let set = scan_lines(dirs(), "liboverdrop.d", &["conf"], false, |line| {
+            line.split_whitespace().nth(1).map(String::from)
+        })
+        .unwrap();
+        assert_eq!(set.len(), 3);
+
+        // Whole fragment overridden by filename.
+        let line = set.get("/var/lib/foo").unwrap();
+        assert_eq!(line.text(), "d /var/lib/foo 0750 root root -");
+        assert_eq!(
+            line.source(),
+            Path::new(TREEDIR).join("run/liboverdrop.d/10-base.conf")
+        );
+        assert_eq!(line.number(), 1);
+
+        // Single line overridden by a later fragment.
+        let line = set.get("/var/cache/foo").unwrap();
+        assert_eq!(line.text(), "d /var/cache/foo 0700 foo foo 1d");
+        assert_eq!(
+            line.source(),
+            Path::new(TREEDIR).join("etc/liboverdrop.d/20-site.conf")
+        );
+
+        assert!(set.get("/srv/foo").is_some());
+        assert!(set.get("/var/lib/foo/state").is_none());
+    }
+
+    #[test]
+    fn first_field_keys() {
+        let set = scan_lines(
+            [Path::new(TREEDIR).join("usr/lib")],
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            first_field,
+        )
+        .unwrap();
+        // Lines are keyed by their type field, so the last one of each type wins.
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.get("d").unwrap().number(), 3);
+        assert_eq!(set.get("f").unwrap().number(), 5);
+        let keys: Vec<_> = set.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["d", "f"]);
+    }
+}
@@ -0,0 +1,1005 @@
+//! Merging of configuration fragments into a single value.
+
+use crate::{
+    has_allowed_extension, normalize_extensions, scan, scan_streaming, scan_streaming_layers,
+    DirOptions,
+};
+use log::warn;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+/// Scan configuration fragments and merge them into a single value.
+///
+/// Arguments are the same as for [`scan`]. Each unique configuration fragment
+/// is opened and passed to `merge`, together with its filename and the value
+/// accumulated so far (starting from `T::default()`), in the same order as
+/// returned by [`scan`]: a single global order by filename, whichever directory
+/// each fragment comes from. See [`Overdrop::merge_order`](crate::Overdrop::merge_order)
+/// for processing fragments layer by layer instead.
+///
+/// The first error returned by `merge` (or encountered while opening a fragment)
+/// stops processing and is returned. The error type only needs to be convertible
+/// from `io::Error`, so e.g. `anyhow::Error` can be used directly.
+///
+/// Directories are scanned as for [`scan`], and only the winning fragments are
+/// opened, in a single pass: fragments shadowed by a higher-priority one are
+/// never read.
+pub fn scan_and_merge<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    merge: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    T: Default,
+    E: From<io::Error>,
+    F: FnMut(T, &OsStr, &mut BufReader<File>) -> Result<T, E>,
+{
+    scan_and_fold(
+        base_dirs,
+        shared_path,
+        allowed_extensions,
+        ignore_dotfiles,
+        T::default(),
+        merge,
+    )
+}
+
+/// Scan configuration fragments and fold them into a caller-supplied initial value.
+///
+/// This works like [`scan_and_merge`], but the accumulated value starts from `init`
+/// (so `T` does not need to implement `Default`), as for [`Iterator::fold`]. See
+/// [`scan_and_merge_with`] for a variant updating the value in place.
+pub fn scan_and_fold<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    init: T,
+    mut fold: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    E: From<io::Error>,
+    F: FnMut(T, &OsStr, &mut BufReader<File>) -> Result<T, E>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let mut value = init;
+    for (name, path) in scan_streaming(base_dirs, shared_path, &options) {
+        let mut reader = BufReader::new(File::open(&path)?);
+        value = fold(value, &name, &mut reader)?;
+    }
+    Ok(value)
+}
+
+/// Scan configuration fragments and merge them into a caller-supplied initial value.
+///
+/// This works like [`scan_and_merge`], but the accumulated value starts from `initial`
+/// (so `T` does not need to implement `Default`), and `merge` updates it in place
+/// instead of moving it through every call.
+pub fn scan_and_merge_with<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    initial: T,
+    mut merge: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    E: From<io::Error>,
+    F: FnMut(&mut T, &OsStr, &mut BufReader<File>) -> Result<(), E>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let fragments = scan_streaming(base_dirs, shared_path, &options);
+    merge_fragments(fragments, initial, |value, name, _, reader| {
+        merge(value, name, reader)?;
+        Ok(ControlFlow::Continue(()))
+    })
+}
+
+/// Where a fragment passed to a [`scan_and_merge_detailed`] callback comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FragmentInfo<'a> {
+    name: &'a OsStr,
+    path: &'a Path,
+    layer: usize,
+}
+
+impl<'a> FragmentInfo<'a> {
+    /// Filename of the fragment.
+    pub fn name(&self) -> &'a OsStr {
+        self.name
+    }
+
+    /// Full path of the fragment, e.g. for error messages.
+    pub fn path(&self) -> &'a Path {
+        self.path
+    }
+
+    /// Index of the base directory the fragment comes from, in the order given
+    /// (i.e. in increasing priority order).
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+}
+
+/// Scan configuration fragments and merge them, with details about each fragment.
+///
+/// This works like [`scan_and_merge`], but `merge` receives a [`FragmentInfo`]
+/// instead of the bare filename, so that errors can point at the actual file, and
+/// fragments from different layers (e.g. vendor and admin ones) can be treated
+/// differently.
+pub fn scan_and_merge_detailed<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    mut merge: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    T: Default,
+    E: From<io::Error>,
+    F: FnMut(T, &FragmentInfo<'_>, &mut BufReader<File>) -> Result<T, E>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let mut value = T::default();
+    for (name, path, layer) in scan_streaming_layers(base_dirs, shared_path, &options) {
+        let mut reader = BufReader::new(File::open(&path)?);
+        let info = FragmentInfo {
+            name: &name,
+            path: &path,
+            layer,
+        };
+        value = merge(value, &info, &mut reader)?;
+    }
+    Ok(value)
+}
+
+/// Scan and merge configuration fragments, going on past failing ones.
+///
+/// This works like [`scan_and_merge_with`] (starting from `T::default()`), but an
+/// error returned by `merge` (or encountered while opening a fragment) does not stop
+/// processing: it is collected with the path of the failing fragment, and the
+/// remaining fragments are merged anyway. The merged value is returned together
+/// with all failures, in processing order, so that e.g. all malformed fragments can
+/// be reported at once.
+///
+/// `merge` updates the value in place, so whatever it changed before failing is
+/// kept; callbacks which need all-or-nothing semantics should parse each fragment
+/// before applying it.
+pub fn scan_and_merge_lenient<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    mut merge: F,
+) -> (T, Vec<(PathBuf, E)>)
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    T: Default,
+    E: From<io::Error>,
+    F: FnMut(&mut T, &OsStr, &mut BufReader<File>) -> Result<(), E>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let mut value = T::default();
+    let mut failures = Vec::new();
+    for (name, path) in scan_streaming(base_dirs, shared_path, &options) {
+        let res = File::open(&path)
+            .map_err(E::from)
+            .and_then(|file| merge(&mut value, &name, &mut BufReader::new(file)));
+        if let Err(e) = res {
+            failures.push((path, e));
+        }
+    }
+    (value, failures)
+}
+
+/// Scan and merge configuration fragments, with early exit.
+///
+/// This works like [`scan_and_merge`], but `merge` returns a [`ControlFlow`]:
+/// on `ControlFlow::Continue(value)` processing goes on with the next fragment,
+/// while `ControlFlow::Break(value)` stops processing (without opening the
+/// remaining fragments) and returns `value`. This is useful e.g. once a decisive
+/// setting is found, without abusing the error channel.
+pub fn scan_and_try_merge<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    mut merge: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    T: Default,
+    E: From<io::Error>,
+    F: FnMut(T, &OsStr, &mut BufReader<File>) -> Result<ControlFlow<T, T>, E>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let fragments = scan_streaming(base_dirs, shared_path, &options);
+    merge_fragments(fragments, T::default(), |value, name, _, reader| {
+        Ok(match merge(mem::take(value), name, reader)? {
+            ControlFlow::Continue(v) => {
+                *value = v;
+                ControlFlow::Continue(())
+            }
+            ControlFlow::Break(v) => {
+                *value = v;
+                ControlFlow::Break(())
+            }
+        })
+    })
+}
+
+/// How [`scan_and_merge_contents`] handles fragments which are not valid UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Utf8Policy {
+    /// Fail with an `InvalidData` error.
+    #[default]
+    Strict,
+    /// Replace invalid sequences with `U+FFFD REPLACEMENT CHARACTER`.
+    Lossy,
+    /// Skip the fragment, with a warning.
+    Skip,
+}
+
+/// Scan configuration fragments and merge their contents, as strings.
+///
+/// This works like [`scan_and_merge`], but each fragment is read entirely into
+/// memory and passed to `merge` as a `&str`. Fragments which are not valid UTF-8 are
+/// handled according to `non_utf8`.
+pub fn scan_and_merge_contents<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    non_utf8: Utf8Policy,
+    mut merge: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    T: Default,
+    E: From<io::Error>,
+    F: FnMut(T, &OsStr, &str) -> Result<T, E>,
+{
+    scan_and_merge_bytes(
+        base_dirs,
+        shared_path,
+        allowed_extensions,
+        ignore_dotfiles,
+        |value, name, contents| match (std::str::from_utf8(contents), non_utf8) {
+            (Ok(contents), _) => merge(value, name, contents),
+            (Err(_), Utf8Policy::Lossy) => merge(value, name, &String::from_utf8_lossy(contents)),
+            (Err(_), Utf8Policy::Skip) => {
+                warn!(
+                    "Skipping non-UTF-8 fragment '{}'",
+                    Path::new(name).display()
+                );
+                Ok(value)
+            }
+            (Err(e), Utf8Policy::Strict) => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, e).into())
+            }
+        },
+    )
+}
+
+/// Scan configuration fragments and merge their contents, as bytes.
+///
+/// This works like [`scan_and_merge`], but each fragment is read entirely into
+/// memory and passed to `merge` as a byte slice.
+pub fn scan_and_merge_bytes<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    mut merge: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    T: Default,
+    E: From<io::Error>,
+    F: FnMut(T, &OsStr, &[u8]) -> Result<T, E>,
+{
+    let mut contents = Vec::new();
+    scan_and_merge(
+        base_dirs,
+        shared_path,
+        allowed_extensions,
+        ignore_dotfiles,
+        |value, name, reader| {
+            contents.clear();
+            reader.read_to_end(&mut contents)?;
+            merge(value, name, &contents)
+        },
+    )
+}
+
+/// Scan configuration fragments and parse each of them into its own value, without merging.
+///
+/// Arguments are the same as for [`scan`]. Each unique configuration fragment is
+/// opened and passed to `parse`, and the results are returned by fragment filename.
+/// This is meant for services where each fragment describes an independent object
+/// (e.g. one peer or rule per file), rather than contributing to a single configuration.
+///
+/// Fragments with non-UTF-8 filenames are skipped. The first error returned by
+/// `parse` (or encountered while opening a fragment) stops processing and is returned.
+pub fn scan_values<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    mut parse: F,
+) -> Result<BTreeMap<String, T>, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    E: From<io::Error>,
+    F: FnMut(&OsStr, &mut BufReader<File>) -> Result<T, E>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let fragments = scan_streaming(base_dirs, shared_path, &options)
+        .into_iter()
+        .filter(|(name, _)| name.to_str().is_some());
+    merge_fragments(fragments, BTreeMap::new(), |values, name, _, reader| {
+        let value = parse(name, reader)?;
+        values.insert(name.to_string_lossy().into_owned(), value);
+        Ok(ControlFlow::Continue(()))
+    })
+}
+
+/// Merge callback for fragments with a given extension, as used by [`MergeTable`].
+type ExtensionMerge<'a, T, E> =
+    Box<dyn FnMut(&mut T, &OsStr, &mut BufReader<File>) -> Result<(), E> + 'a>;
+
+/// A table mapping fragment extensions to distinct merge callbacks.
+///
+/// This allows processing different kinds of fragments sharing the same directory
+/// (e.g. `.toml` settings and `.rules` files) in a single pass, via
+/// [`scan_and_merge_by_extension`]. Extensions follow the same rules as in
+/// [`scan`](crate::scan) (i.e. the leading dot is optional, and composite extensions
+/// are supported); if several match, the longest one wins.
+pub struct MergeTable<'a, T, E> {
+    handlers: Vec<(String, ExtensionMerge<'a, T, E>)>,
+}
+
+impl<T, E> std::fmt::Debug for MergeTable<'_, T, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.handlers.iter().map(|(ext, _)| ext))
+            .finish()
+    }
+}
+
+impl<T, E> Default for MergeTable<'_, T, E> {
+    fn default() -> Self {
+        Self {
+            handlers: Vec::new(),
+        }
+    }
+}
+
+impl<'a, T, E> MergeTable<'a, T, E> {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merge fragments with the given `extension` via `merge`.
+    ///
+    /// Registering the same extension twice replaces the previous callback.
+    #[must_use]
+    pub fn on<F>(mut self, extension: impl AsRef<OsStr>, merge: F) -> Self
+    where
+        F: FnMut(&mut T, &OsStr, &mut BufReader<File>) -> Result<(), E> + 'a,
+    {
+        let extension = normalize_extensions(&[extension]).remove(0);
+        self.handlers.retain(|(ext, _)| *ext != extension);
+        self.handlers.push((extension, Box::new(merge)));
+        self
+    }
+
+    fn handler_for(&mut self, name: &OsStr) -> Option<&mut ExtensionMerge<'a, T, E>> {
+        self.handlers
+            .iter_mut()
+            .filter(|(ext, _)| has_allowed_extension(name, &[ext]))
+            .max_by_key(|(ext, _)| ext.len())
+            .map(|(_, merge)| merge)
+    }
+}
+
+/// Scan configuration fragments and merge them, dispatching on their extension.
+///
+/// Only fragments with one of the extensions registered in `table` are scanned;
+/// other arguments are the same as for [`scan`](crate::scan). Each fragment is passed
+/// to the callback registered for its extension, which updates the accumulated value
+/// in place (starting from `initial`), in the same order as returned by [`scan`].
+pub fn scan_and_merge_by_extension<BdS, BdI, Sp, T, E>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    ignore_dotfiles: bool,
+    initial: T,
+    table: &mut MergeTable<'_, T, E>,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    E: From<io::Error>,
+{
+    if table.handlers.is_empty() {
+        return Ok(initial);
+    }
+
+    let extensions: Vec<&str> = table.handlers.iter().map(|(ext, _)| ext.as_str()).collect();
+    let fragments = scan(base_dirs, shared_path, &extensions, ignore_dotfiles);
+    merge_fragments(fragments, initial, |value, name, _, reader| {
+        if let Some(merge) = table.handler_for(name) {
+            merge(value, name, reader)?;
+        }
+        Ok(ControlFlow::Continue(()))
+    })
+}
+
+/// Merge `key=value` lines from `reader` into `target`, as a built-in merger.
+///
+/// Empty lines and comments (lines starting with `#`) are skipped, and whitespace
+/// around keys and values is trimmed; keys set again override previous values. If
+/// `strict_unset` is set, a key with an empty value (i.e. `key=`) explicitly unsets
+/// it instead, removing any value set by a lower-priority fragment; otherwise it is
+/// set to an empty string.
+///
+/// This is meant to be used as a callback for [`scan_and_merge_with`]:
+///
+/// ```rust,no_run
+/// # use std::collections::BTreeMap;
+/// let config = liboverdrop::scan_and_merge_with(
+///     ["/usr/lib", "/etc"],
+///     "my-crate/config.d",
+///     &["conf"],
+///     false,
+///     BTreeMap::new(),
+///     |config, _, reader| liboverdrop::merge_key_values(config, reader, true),
+/// )?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Lines without a `=` separator are rejected with an `InvalidData` error.
+pub fn merge_key_values<R: BufRead>(
+    target: &mut BTreeMap<String, String>,
+    reader: R,
+    strict_unset: bool,
+) -> io::Result<()> {
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| {
+            let msg = format!("line {}: missing '=' separator", idx + 1);
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })?;
+        let (key, value) = (key.trim(), value.trim());
+        if strict_unset && value.is_empty() {
+            target.remove(key);
+        } else {
+            target.insert(key.to_string(), value.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Open and merge fragments in order, until `merge` fails or breaks.
+fn merge_fragments<T, E, F, I>(fragments: I, initial: T, mut merge: F) -> Result<T, E>
+where
+    E: From<io::Error>,
+    F: FnMut(&mut T, &OsStr, &Path, &mut BufReader<File>) -> Result<ControlFlow<()>, E>,
+    I: IntoIterator<Item = (OsString, PathBuf)>,
+{
+    let mut value = initial;
+    for (name, path) in fragments {
+        let mut reader = BufReader::new(File::open(&path)?);
+        if merge(&mut value, &name, &path, &mut reader)?.is_break() {
+            break;
+        }
+    }
+    Ok(value)
+}
+
+/// Per-key provenance of a merged value.
+///
+/// While merging, keys can be recorded as set by the fragment currently being
+/// processed, so that validation errors can point at the file responsible for
+/// a given setting.
+#[derive(Clone, Debug, Default)]
+pub struct Provenance {
+    current: Option<PathBuf>,
+    keys: BTreeMap<String, PathBuf>,
+}
+
+impl Provenance {
+    /// Record `key` as set by the fragment currently being merged.
+    ///
+    /// A key recorded by a later fragment replaces previous records.
+    pub fn record(&mut self, key: impl Into<String>) {
+        if let Some(current) = &self.current {
+            self.keys.insert(key.into(), current.clone());
+        }
+    }
+
+    /// Return the path of the fragment which last set `key`, if any.
+    pub fn source_of(&self, key: &str) -> Option<&Path> {
+        self.keys.get(key).map(PathBuf::as_path)
+    }
+
+    /// Iterate over all recorded keys, with the path of the fragment which last set them.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.keys.iter().map(|(k, v)| (k.as_str(), v.as_path()))
+    }
+}
+
+/// Scan and merge configuration fragments, then validate the merged value.
+///
+/// This works like [`scan_and_merge`], but `merge` additionally receives a
+/// [`Provenance`] where it can record which keys the current fragment sets.
+/// Once all fragments are merged, `validate` is run on the final value
+/// together with the collected provenance; its error is returned as-is.
+///
+/// This allows catching invalid combinations of settings across fragments,
+/// with errors that reference the file where each offending setting came from.
+pub fn scan_merge_validate<BdS, BdI, Sp, As, T, E, F, V>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    mut merge: F,
+    validate: V,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    T: Default,
+    E: From<io::Error>,
+    F: FnMut(T, &OsStr, &mut BufReader<File>, &mut Provenance) -> Result<T, E>,
+    V: FnOnce(&T, &Provenance) -> Result<(), E>,
+{
+    let fragments = scan(base_dirs, shared_path, allowed_extensions, ignore_dotfiles);
+
+    let mut provenance = Provenance::default();
+    let value =
+        merge_fragments::<_, E, _, _>(fragments, T::default(), |value, name, path, reader| {
+            provenance.current = Some(path.to_path_buf());
+            *value = merge(mem::take(value), name, reader, &mut provenance)?;
+            Ok(ControlFlow::Continue(()))
+        })?;
+    provenance.current = None;
+
+    validate(&value, &provenance)?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+
+    const TREEDIR: &str = "tests/fixtures/tree-merge";
+
+    fn dirs() -> Vec<String> {
+        ["usr/lib", "etc", "run"]
+            .iter()
+            .map(|d| format!("{}/{}", TREEDIR, d))
+            .collect()
+    }
+
+    /// Parse `key=value` lines, overriding previously set keys.
+    fn parse_lines(
+        mut map: BTreeMap<String, String>,
+        reader: &mut BufReader<File>,
+        mut on_key: impl FnMut(&str),
+    ) -> io::Result<BTreeMap<String, String>> {
+        for line in reader.lines() {
+            let line = line?;
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, line.clone()))?;
+            on_key(key);
+            map.insert(key.to_string(), value.to_string());
+        }
+        Ok(map)
+    }
+
+    #[test]
+    fn values_per_fragment() {
+        let values = scan_values(dirs(), "liboverdrop.d", &["conf"], false, |_, reader| {
+            parse_lines(BTreeMap::new(), reader, |_| {})
+        })
+        .unwrap();
+        let names: Vec<_> = values.keys().map(String::as_str).collect();
+        assert_eq!(
+            names,
+            [
+                "10-base.conf",
+                "20-extra.conf",
+                "30-local.conf",
+                "40-runtime.conf"
+            ]
+        );
+        assert_eq!(values["20-extra.conf"]["verbose"], "true");
+        assert_eq!(values["10-base.conf"].len(), 2);
+    }
+
+    #[test]
+    fn key_values_unset() {
+        let dirs = ["usr/lib", "etc"].map(|d| Path::new("tests/fixtures/tree-unset").join(d));
+        let merge = |strict_unset| {
+            scan_and_merge_with(
+                &dirs,
+                "liboverdrop.d",
+                &["conf"],
+                false,
+                BTreeMap::new(),
+                |config, _, reader| merge_key_values(config, reader, strict_unset),
+            )
+            .unwrap()
+        };
+
+        let config = merge(true);
+        assert_eq!(config.len(), 2);
+        assert_eq!(config["name"], "vendor");
+        assert_eq!(config["verbose"], "true");
+        assert!(!config.contains_key("retries"));
+
+        let config = merge(false);
+        assert_eq!(config["retries"], "");
+
+        let mut config = BTreeMap::new();
+        let err = merge_key_values(&mut config, &b"a=1\nbroken\n"[..], true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn merge_basic() {
+        let mut seen = Vec::new();
+        let merged = scan_and_merge(dirs(), "liboverdrop.d", &["conf"], false, |acc, name, r| {
+            seen.push(name.to_os_string());
+            parse_lines(acc, r, |_| {})
+        })
+        .unwrap();
+
+        assert_eq!(
+            seen,
+            [
+                "10-base.conf",
+                "20-extra.conf",
+                "30-local.conf",
+                "40-runtime.conf"
+            ]
+        );
+        assert_eq!(merged["name"], "runtime");
+        assert_eq!(merged["retries"], "5");
+        assert_eq!(merged["verbose"], "true");
+    }
+
+    #[test]
+    fn merge_detailed() {
+        let mut seen = Vec::new();
+        let merged =
+            scan_and_merge_detailed(dirs(), "liboverdrop.d", &["conf"], false, |acc, info, r| {
+                seen.push((
+                    info.name().to_os_string(),
+                    info.path().to_path_buf(),
+                    info.layer(),
+                ));
+                parse_lines(acc, r, |_| {})
+            })
+            .unwrap();
+
+        let fragment = |dir: &str, name: &str, layer| {
+            let path = Path::new(TREEDIR)
+                .join(dir)
+                .join("liboverdrop.d")
+                .join(name);
+            (name.into(), path, layer)
+        };
+        assert_eq!(
+            seen,
+            [
+                fragment("usr/lib", "10-base.conf", 0),
+                fragment("etc", "20-extra.conf", 1),
+                fragment("etc", "30-local.conf", 1),
+                fragment("run", "40-runtime.conf", 2),
+            ]
+        );
+        assert_eq!(merged["name"], "runtime");
+    }
+
+    #[test]
+    fn merge_contents() {
+        let tree = crate::testing::TreeBuilder::new()
+            .fragment("etc/svc.d/10-a.conf", "a\n")
+            .fragment("etc/svc.d/20-b.conf", b"b\xff\n")
+            .fragment("etc/svc.d/30-c.conf", "c\n");
+        let dirs = tree.base_dirs(["etc"]);
+        let merge = |non_utf8| {
+            scan_and_merge_contents(
+                &dirs,
+                "svc.d",
+                &["conf"],
+                false,
+                non_utf8,
+                |acc: String, _, s| io::Result::Ok(acc + s),
+            )
+        };
+        assert_eq!(merge(Utf8Policy::Lossy).unwrap(), "a\nb\u{fffd}\nc\n");
+        assert_eq!(merge(Utf8Policy::Skip).unwrap(), "a\nc\n");
+        let err = merge(Utf8Policy::Strict).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let bytes = scan_and_merge_bytes(
+            &dirs,
+            "svc.d",
+            &["conf"],
+            false,
+            |mut acc: Vec<u8>, _, b| {
+                acc.extend_from_slice(b);
+                io::Result::Ok(acc)
+            },
+        );
+        assert_eq!(bytes.unwrap(), b"a\nb\xff\nc\n");
+    }
+
+    #[test]
+    fn merge_non_std_error() {
+        // An error type which doesn't implement `std::error::Error`, like `anyhow::Error`.
+        #[derive(Debug)]
+        struct Failure(String);
+
+        impl From<io::Error> for Failure {
+            fn from(e: io::Error) -> Self {
+                Failure(e.to_string())
+            }
+        }
+
+        let err = scan_and_merge(dirs(), "liboverdrop.d", &["conf"], false, |acc, name, r| {
+            let acc = parse_lines(acc, r, |_| {})?;
+            if name == "30-local.conf" {
+                return Err(Failure(format!("rejected {}", name.to_string_lossy())));
+            }
+            Ok(acc)
+        })
+        .unwrap_err();
+        assert_eq!(err.0, "rejected 30-local.conf");
+    }
+
+    #[test]
+    fn merge_by_extension() {
+        let tree = ["tests/fixtures/tree-extensions"];
+        let mut toml = Vec::new();
+        let mut composite = Vec::new();
+        let mut conf = Vec::new();
+        let mut table = MergeTable::new()
+            .on("toml", |acc: &mut Vec<String>, name, _| {
+                acc.push(name.to_string_lossy().into_owned());
+                toml.push(name.to_os_string());
+                Ok::<_, io::Error>(())
+            })
+            .on(".conf.toml", |acc, name, _| {
+                acc.push(name.to_string_lossy().into_owned());
+                composite.push(name.to_os_string());
+                Ok(())
+            })
+            .on("conf", |acc, name, _| {
+                acc.push(name.to_string_lossy().into_owned());
+                conf.push(name.to_os_string());
+                Ok(())
+            });
+
+        let merged =
+            scan_and_merge_by_extension(tree, "liboverdrop.d", false, vec![], &mut table).unwrap();
+        drop(table);
+
+        assert_eq!(merged, ["10-a.conf.toml", "20-b.toml", "50-e.conf"]);
+        assert_eq!(toml, ["20-b.toml"]);
+        assert_eq!(composite, ["10-a.conf.toml"]);
+        assert_eq!(conf, ["50-e.conf"]);
+
+        let mut empty = MergeTable::<Vec<String>, io::Error>::new();
+        let merged =
+            scan_and_merge_by_extension(tree, "liboverdrop.d", false, vec![], &mut empty).unwrap();
+        assert!(merged.is_empty());
+    }
+
+    #[test]
+    fn merge_with_initial() {
+        struct Settings {
+            seen: Vec<String>,
+            values: BTreeMap<String, String>,
+        }
+
+        let initial = Settings {
+            seen: vec!["builtin".to_string()],
+            values: BTreeMap::new(),
+        };
+        let merged = scan_and_merge_with(
+            dirs(),
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            initial,
+            |acc, name, r| {
+                acc.seen.push(name.to_string_lossy().into_owned());
+                acc.values = parse_lines(mem::take(&mut acc.values), r, |_| {})?;
+                Ok::<_, io::Error>(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(merged.seen.len(), 5);
+        assert_eq!(merged.seen[0], "builtin");
+        assert_eq!(merged.values["name"], "runtime");
+    }
+
+    #[test]
+    fn fold_from_initial() {
+        /// A configuration type without a meaningful default.
+        struct Settings {
+            origin: &'static str,
+            values: BTreeMap<String, String>,
+        }
+
+        let init = Settings {
+            origin: "builtin",
+            values: BTreeMap::from([("name".to_string(), "builtin".to_string())]),
+        };
+        let folded = scan_and_fold(
+            dirs(),
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            init,
+            |acc, _, r| {
+                let values = parse_lines(acc.values, r, |_| {})?;
+                Ok::<_, io::Error>(Settings { values, ..acc })
+            },
+        )
+        .unwrap();
+        assert_eq!(folded.origin, "builtin");
+        assert_eq!(folded.values["name"], "runtime");
+    }
+
+    #[test]
+    fn merge_lenient() {
+        let tree = crate::testing::TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "a=1\n")
+            .fragment("usr/lib/svc.d/20-b.conf", "broken\n")
+            .fragment("etc/svc.d/30-c.conf", "c=3\nbroken\n")
+            .fragment("etc/svc.d/40-d.conf", "d=4\n");
+        let dirs = tree.base_dirs(["usr/lib", "etc"]);
+        let (merged, failures) =
+            scan_and_merge_lenient(&dirs, "svc.d", &["conf"], false, |acc, _, r| {
+                merge_key_values(acc, r, false)
+            });
+
+        let keys: Vec<_> = merged.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["a", "c", "d"]);
+        let failed: Vec<_> = failures.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(
+            failed,
+            [
+                tree.path("usr/lib/svc.d/20-b.conf"),
+                tree.path("etc/svc.d/30-c.conf")
+            ]
+        );
+        assert!(failures
+            .iter()
+            .all(|(_, e)| e.kind() == io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn merge_early_exit() {
+        let mut seen = Vec::new();
+        let merged =
+            scan_and_try_merge(dirs(), "liboverdrop.d", &["conf"], false, |acc, name, r| {
+                seen.push(name.to_os_string());
+                let acc = parse_lines(acc, r, |_| {})?;
+                if acc.get("verbose").map(String::as_str) == Some("true") {
+                    return Ok::<_, io::Error>(ControlFlow::Break(acc));
+                }
+                Ok(ControlFlow::Continue(acc))
+            })
+            .unwrap();
+
+        assert_eq!(seen, ["10-base.conf", "20-extra.conf"]);
+        assert_eq!(merged["name"], "vendor");
+        assert_eq!(merged["verbose"], "true");
+        assert_eq!(merged["retries"], "3");
+
+        let merged = scan_and_try_merge(dirs(), "liboverdrop.d", &["conf"], false, |acc, _, r| {
+            Ok::<_, io::Error>(ControlFlow::Continue(parse_lines(acc, r, |_| {})?))
+        })
+        .unwrap();
+        assert_eq!(merged["name"], "runtime");
+    }
+
+    #[test]
+    fn merge_validate_provenance() {
+        let merged = scan_merge_validate(
+            dirs(),
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            |acc, _, r, prov| parse_lines(acc, r, |k| prov.record(k)),
+            |_, prov| {
+                let expected = Path::new(TREEDIR).join("etc/liboverdrop.d/30-local.conf");
+                assert_eq!(prov.source_of("retries"), Some(expected.as_path()));
+                assert!(prov.source_of("missing").is_none());
+                assert_eq!(prov.iter().count(), 3);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(merged["retries"], "5");
+
+        let err = scan_merge_validate(
+            dirs(),
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            |acc, _, r, prov| parse_lines(acc, r, |k| prov.record(k)),
+            |merged, prov| {
+                if merged["name"] == "runtime" {
+                    let source = prov.source_of("name").unwrap();
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid name set in '{}'", source.display()),
+                    ));
+                }
+                Ok(())
+            },
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("run/liboverdrop.d/40-runtime.conf"));
+    }
+}
@@ -0,0 +1,199 @@
+//! Versioned fragments, with migrations to the current format.
+//!
+//! Fragments may opt into a version header as their first line, e.g. `# Version: 2`.
+//! Before merging, each fragment is routed through caller-registered migration
+//! functions, one version at a time, up to the current version. This allows services
+//! to evolve their drop-in format without breaking existing admin files.
+
+use crate::{scan_streaming, DirOptions};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Migration from one fragment version to the next one.
+type Migration<'a, E> = Box<dyn FnMut(String) -> Result<String, E> + 'a>;
+
+/// A set of migration functions between fragment versions.
+pub struct Migrations<'a, E> {
+    current: u32,
+    unversioned: u32,
+    steps: BTreeMap<u32, Migration<'a, E>>,
+}
+
+impl<E> fmt::Debug for Migrations<'_, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Migrations")
+            .field("current", &self.current)
+            .field("unversioned", &self.unversioned)
+            .field("steps", &self.steps.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<'a, E: From<io::Error>> Migrations<'a, E> {
+    /// Create an empty set of migrations, for fragments in the `current` version.
+    ///
+    /// Fragments without a version header are assumed to be in version 1.
+    pub fn new(current: u32) -> Self {
+        Self {
+            current,
+            unversioned: 1,
+            steps: BTreeMap::new(),
+        }
+    }
+
+    /// Set the version assumed for fragments without a version header.
+    #[must_use]
+    pub fn unversioned(mut self, version: u32) -> Self {
+        self.unversioned = version;
+        self
+    }
+
+    /// Register `migrate`, converting fragment contents from version `from` to `from + 1`.
+    ///
+    /// Registering the same version twice replaces the previous function.
+    #[must_use]
+    pub fn on<F>(mut self, from: u32, migrate: F) -> Self
+    where
+        F: FnMut(String) -> Result<String, E> + 'a,
+    {
+        self.steps.insert(from, Box::new(migrate));
+        self
+    }
+
+    /// Migrate the contents of a fragment (including its version header, if any)
+    /// to the current version, returning them without the header.
+    pub fn migrate(&mut self, contents: &str) -> Result<String, E> {
+        let (version, body) = match split_version(contents) {
+            Some((version, body)) => (version, body),
+            None => (self.unversioned, contents),
+        };
+        if version > self.current {
+            let msg = format!(
+                "fragment version {} is newer than supported version {}",
+                version, self.current
+            );
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg).into());
+        }
+
+        let mut body = body.to_string();
+        for from in version..self.current {
+            let step = self.steps.get_mut(&from).ok_or_else(|| {
+                let msg = format!("no migration from fragment version {}", from);
+                io::Error::new(io::ErrorKind::InvalidData, msg)
+            })?;
+            body = step(body)?;
+        }
+        Ok(body)
+    }
+}
+
+/// Split a leading `# Version: N` header line from `contents`.
+fn split_version(contents: &str) -> Option<(u32, &str)> {
+    let (first, rest) = contents.split_once('\n').unwrap_or((contents, ""));
+    let version = first
+        .trim()
+        .strip_prefix('#')?
+        .trim_start()
+        .strip_prefix("Version:")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some((version, rest))
+}
+
+/// Scan configuration fragments, migrate them to the current version and merge them.
+///
+/// This works like [`scan_and_merge_with`](crate::scan_and_merge_with), but each
+/// fragment is read in full and routed through `migrations` first; `merge` then
+/// receives the migrated contents, without the version header. Fragments must be
+/// valid UTF-8.
+pub fn scan_and_merge_versioned<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    initial: T,
+    migrations: &mut Migrations<'_, E>,
+    mut merge: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    E: From<io::Error>,
+    F: FnMut(&mut T, &OsStr, &str) -> Result<(), E>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let mut value = initial;
+    for (name, path) in scan_streaming(base_dirs, shared_path, &options) {
+        let contents = fs::read_to_string(&path)?;
+        let migrated = migrations.migrate(&contents)?;
+        merge(&mut value, &name, &migrated)?;
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge_key_values;
+
+    fn dirs() -> [std::path::PathBuf; 2] {
+        ["usr/lib", "etc"].map(|d| Path::new("tests/fixtures/tree-versioned").join(d))
+    }
+
+    /// Version 1 had `timeout` in seconds, version 2 has `timeout_ms`.
+    fn v1_to_v2(contents: String) -> io::Result<String> {
+        let lines: Vec<_> = contents
+            .lines()
+            .map(|line| match line.strip_prefix("timeout=") {
+                Some(secs) => {
+                    let secs: u64 = secs.parse().map_err(|_| io::ErrorKind::InvalidData)?;
+                    Ok(format!("timeout_ms={}", secs * 1000))
+                }
+                None => Ok(line.to_string()),
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(lines.join("\n"))
+    }
+
+    #[test]
+    fn migrate_and_merge() {
+        let mut migrations = Migrations::new(2).on(1, v1_to_v2);
+        let config = scan_and_merge_versioned(
+            dirs(),
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            BTreeMap::new(),
+            &mut migrations,
+            |config, _, contents| merge_key_values(config, contents.as_bytes(), false),
+        )
+        .unwrap();
+        assert_eq!(config.len(), 2);
+        assert_eq!(config["timeout_ms"], "3000");
+        assert_eq!(config["name"], "legacy");
+
+        let migrated = migrations.migrate("timeout=7\n").unwrap();
+        assert_eq!(migrated, "timeout_ms=7000");
+        let migrated = migrations.migrate("# Version: 2\ntimeout_ms=1\n").unwrap();
+        assert_eq!(migrated, "timeout_ms=1\n");
+    }
+
+    #[test]
+    fn migrate_errors() {
+        let mut migrations = Migrations::<io::Error>::new(3).on(1, Ok);
+        let err = migrations.migrate("# Version: 4\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = migrations.migrate("# Version: 1\n").unwrap_err();
+        assert!(err.to_string().contains("from fragment version 2"));
+
+        let mut migrations = Migrations::<io::Error>::new(3).unversioned(3);
+        assert_eq!(migrations.migrate("a=b\n").unwrap(), "a=b\n");
+    }
+}
@@ -0,0 +1,101 @@
+//! Naming of new drop-in fragments.
+
+use std::ffi::{OsStr, OsString};
+use std::ops::RangeInclusive;
+
+/// Suggest a filename for a new drop-in within a priority band.
+///
+/// `existing` lists the names of the current effective fragments (e.g. the keys
+/// returned by [`scan`](crate::scan)). Fragment names conventionally start with a
+/// numeric priority prefix, followed by a dash; the suggestion uses the first number
+/// in `band` above all existing prefixes in that band, so that the new fragment
+/// sorts (and thus applies) after them. Numbers are zero-padded to the width of the
+/// band end, e.g. `suggest_override_name(names, 50..=59, "sitename", "conf")` may
+/// return `51-sitename.conf`.
+///
+/// Returns `None` if the band is exhausted, or if `stem` or `extension` would not
+/// make a plain filename.
+pub fn suggest_override_name<N: AsRef<OsStr>, I: IntoIterator<Item = N>>(
+    existing: I,
+    band: RangeInclusive<u32>,
+    stem: &str,
+    extension: &str,
+) -> Option<OsString> {
+    let extension = extension.strip_prefix('.').unwrap_or(extension);
+    if stem.is_empty() || stem.contains('/') || extension.contains('/') {
+        return None;
+    }
+
+    let highest = existing
+        .into_iter()
+        .filter_map(|name| priority_of(name.as_ref()))
+        .filter(|prio| band.contains(prio))
+        .max();
+    let prio = match highest {
+        Some(p) => p.checked_add(1).filter(|p| band.contains(p))?,
+        None if band.is_empty() => return None,
+        None => *band.start(),
+    };
+
+    let width = band.end().to_string().len();
+    let mut name = format!("{:0width$}-{}", prio, stem, width = width);
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(extension);
+    }
+    Some(name.into())
+}
+
+/// Parse the numeric priority prefix of a fragment name, if any.
+fn priority_of(name: &OsStr) -> Option<u32> {
+    let name = name.to_str()?;
+    let (prefix, _) = name.split_once('-')?;
+    if prefix.is_empty() || !prefix.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    prefix.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn suggest_names() {
+        let existing = [
+            "10-base.conf",
+            "50-vendor.conf",
+            "52-extra.conf",
+            "60-late.conf",
+            "local.conf",
+        ];
+        let name = suggest_override_name(existing, 50..=59, "sitename", "conf");
+        assert_eq!(name.unwrap(), "53-sitename.conf");
+
+        let name = suggest_override_name(existing, 20..=29, "sitename", ".conf");
+        assert_eq!(name.unwrap(), "20-sitename.conf");
+
+        let name = suggest_override_name(existing, 0..=99, "sitename", "");
+        assert_eq!(name.unwrap(), "61-sitename");
+
+        let name = suggest_override_name(existing, 0..=999, "sitename", "toml");
+        assert_eq!(name.unwrap(), "061-sitename.toml");
+
+        let name = suggest_override_name(existing, 0..=9, "sitename", "toml");
+        assert_eq!(name.unwrap(), "0-sitename.toml");
+
+        assert!(suggest_override_name(existing, 60..=60, "sitename", "conf").is_none());
+        assert!(suggest_override_name(existing, 50..=59, "", "conf").is_none());
+        assert!(suggest_override_name(existing, 50..=59, "../x", "conf").is_none());
+    }
+
+    #[test]
+    fn suggest_from_scan() {
+        let dirs =
+            ["usr/lib", "etc", "run"].map(|d| Path::new("tests/fixtures/tree-merge").join(d));
+        let fragments = crate::scan::<_, _, _, &str>(dirs, "liboverdrop.d", &[], false);
+        let name = suggest_override_name(fragments.keys(), 30..=39, "site", "conf");
+        assert_eq!(name.unwrap(), "31-site.conf");
+    }
+}
@@ -0,0 +1,273 @@
+//! Asynchronous scanning and merging of configuration fragments.
+
+use crate::{read_dir_entries, DirOptions, Fragment, FragmentSource, LayerPolicy, Resolver, StdFs};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "stream")]
+use {
+    crate::iter::ScanIter,
+    futures_core::Stream,
+    futures_util::{stream, FutureExt},
+};
+
+/// Scan unique configuration fragments asynchronously.
+///
+/// Arguments and results are the same as for [`scan`](crate::scan), with the scan
+/// run on the blocking thread pool of `tokio`, so that it can be awaited from async
+/// code without blocking it. Dropping the future stops waiting for the scan, but
+/// does not interrupt it.
+///
+/// This is available with the `tokio` feature.
+pub async fn scan_async<BdS, BdI, Sp, As>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> BTreeMap<OsString, PathBuf>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let dirs = crate::effective_search_dirs(base_dirs, shared_path);
+    scan_source_async(StdFs, dirs, options).await
+}
+
+/// Scan `dirs` (in increasing priority order) in `source` asynchronously, see [`scan_async`].
+///
+/// Entries which cannot be read are skipped, as for [`scan`](crate::scan).
+async fn scan_source_async<S>(
+    source: S,
+    dirs: Vec<PathBuf>,
+    options: DirOptions,
+) -> BTreeMap<OsString, PathBuf>
+where
+    S: FragmentSource + Send + 'static,
+{
+    blocking(move || {
+        let mut resolver = Resolver::new();
+        for dir in dirs {
+            // Missing or unreadable directories are simply skipped.
+            let _ = read_dir_entries(&source, &dir, &options, |fname, entry| {
+                resolver.apply(
+                    LayerPolicy::default(),
+                    fname,
+                    entry.map(Fragment::into_path),
+                )
+            });
+        }
+        resolver.into_map()
+    })
+    .await
+}
+
+/// Run `f` on the blocking thread pool, propagating its panics.
+async fn blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// Scan configuration fragments asynchronously and merge them into a single value.
+///
+/// This works like [`scan_and_merge`](crate::scan_and_merge), with the scan done by
+/// [`scan_async`] and each fragment read asynchronously into memory before being
+/// passed to `merge` (which is synchronous, as parsing doesn't block). As for
+/// [`scan_and_merge`](crate::scan_and_merge), the first error stops processing.
+///
+/// This is available with the `tokio` feature.
+pub async fn scan_and_merge_async<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    mut merge: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    T: Default,
+    E: From<io::Error>,
+    F: FnMut(T, &OsStr, &mut dyn BufRead) -> Result<T, E>,
+{
+    let fragments = scan_async(base_dirs, shared_path, allowed_extensions, ignore_dotfiles).await;
+    let mut value = T::default();
+    for (name, path) in fragments {
+        let contents = tokio::fs::read(&path).await?;
+        value = merge(value, &name, &mut &contents[..])?;
+    }
+    Ok(value)
+}
+
+/// Stream unique configuration fragments, in the same order as [`scan`](crate::scan).
+///
+/// Arguments are the same as for [`scan`](crate::scan). As for
+/// [`scan_iter`](crate::scan_iter), directories are listed when the stream is first
+/// polled, and then each fragment is resolved only when the stream reaches it, so
+/// that consumers can already process (e.g. open and parse) earlier fragments
+/// meanwhile. Both run on the blocking thread pool of `tokio`.
+///
+/// This is available with the `stream` feature.
+#[cfg(feature = "stream")]
+pub fn scan_stream<BdS, BdI, Sp, As>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> impl Stream<Item = (OsString, PathBuf)> + Send
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let dirs = crate::effective_search_dirs(base_dirs, shared_path);
+
+    let listing = async move {
+        let fragments = blocking(move || ScanIter::new(dirs, options)).await;
+        stream::unfold(fragments, |mut fragments| {
+            blocking(move || fragments.next().map(|fragment| (fragment, fragments)))
+        })
+    };
+    listing.flatten_stream()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TreeBuilder;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn async_matches_scan() {
+        for (treedir, bases) in [
+            ("tests/fixtures/tree-basic", ["usr/lib", "run", "etc"]),
+            ("tests/fixtures/tree-mask", ["usr/lib", "etc", "run"]),
+        ] {
+            let dirs = bases.map(|d| Path::new(treedir).join(d));
+            for (extensions, ignore_dotfiles) in [(&[][..], false), (&["toml"][..], true)] {
+                let expected = crate::scan(&dirs, "liboverdrop.d", extensions, ignore_dotfiles);
+                let fragments = block_on(scan_async(
+                    &dirs,
+                    "liboverdrop.d",
+                    extensions,
+                    ignore_dotfiles,
+                ));
+                assert_eq!(fragments, expected);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "stream")]
+    fn stream_matches_scan() {
+        use futures_util::StreamExt;
+
+        for (treedir, bases) in [
+            ("tests/fixtures/tree-basic", ["usr/lib", "run", "etc"]),
+            ("tests/fixtures/tree-mask", ["usr/lib", "etc", "run"]),
+        ] {
+            let dirs = bases.map(|d| Path::new(treedir).join(d));
+            for (extensions, ignore_dotfiles) in [(&[][..], false), (&["toml"][..], true)] {
+                let expected = crate::scan(&dirs, "liboverdrop.d", extensions, ignore_dotfiles);
+                let stream = scan_stream(&dirs, "liboverdrop.d", extensions, ignore_dotfiles);
+                let fragments: Vec<_> = block_on(stream.collect());
+                assert_eq!(fragments, expected.into_iter().collect::<Vec<_>>());
+            }
+        }
+    }
+
+    /// A [`MemoryTree`](crate::MemoryTree) failing to read one entry of each directory.
+    struct Unreadable(crate::MemoryTree);
+
+    impl FragmentSource for Unreadable {
+        fn list(&self, dir: &Path) -> io::Result<crate::EntryNames<'_>> {
+            let error = io::Error::new(io::ErrorKind::Other, "unreadable entry");
+            Ok(Box::new(
+                std::iter::once(Err(error)).chain(self.0.list(dir)?),
+            ))
+        }
+
+        fn entry_kind(&self, path: &Path) -> io::Result<crate::EntryKind> {
+            self.0.entry_kind(path)
+        }
+
+        fn open(&self, path: &Path) -> io::Result<Box<dyn io::Read + '_>> {
+            self.0.open(path)
+        }
+
+        fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+            self.0.read_link(path)
+        }
+    }
+
+    #[test]
+    fn async_skips_unreadable_entries() {
+        let tree = crate::MemoryTree::new()
+            .file("usr/lib/svc.d/10-a.conf", "vendor a")
+            .file("usr/lib/svc.d/20-b.conf", "vendor b")
+            .file("etc/svc.d/10-a.conf", "admin a");
+        let dirs = vec!["usr/lib/svc.d".into(), "etc/svc.d".into()];
+        let options = DirOptions::new(&["conf"], false);
+
+        // The rest of each layer is still scanned.
+        let fragments = block_on(scan_source_async(Unreadable(tree), dirs, options));
+        assert_eq!(
+            fragments,
+            BTreeMap::from([
+                ("10-a.conf".into(), "etc/svc.d/10-a.conf".into()),
+                ("20-b.conf".into(), "usr/lib/svc.d/20-b.conf".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn async_merge() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor a\n")
+            .fragment("usr/lib/svc.d/20-b.conf", "vendor b\n")
+            .fragment("etc/svc.d/10-a.conf", "admin a\n");
+        let dirs = tree.base_dirs(["usr/lib", "etc"]);
+        let merged = block_on(scan_and_merge_async(
+            &dirs,
+            "svc.d",
+            &["conf"],
+            false,
+            |mut merged: String, _, reader| -> io::Result<_> {
+                reader.read_to_string(&mut merged)?;
+                Ok(merged)
+            },
+        ));
+        let expected: String = crate::scan_and_merge(
+            &dirs,
+            "svc.d",
+            &["conf"],
+            false,
+            |mut merged: String, _, reader| -> io::Result<_> {
+                io::Read::read_to_string(reader, &mut merged)?;
+                Ok(merged)
+            },
+        )
+        .unwrap();
+        assert_eq!(expected, "admin a\nvendor b\n");
+        assert_eq!(merged.unwrap(), expected);
+    }
+}
@@ -0,0 +1,192 @@
+//! Ordering directives between fragments.
+//!
+//! Fragments are normally merged in alphanumeric order by filename. For configs
+//! where correctness depends on application order and renaming files is not
+//! possible, fragments may declare ordering constraints in their leading comment
+//! block, listing other fragments by filename:
+//!
+//! ```text
+//! # After: 20-base.conf
+//! # Before: 90-final.conf 95-late.conf
+//! ```
+
+use crate::{scan_streaming, DirOptions};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// The order in which fragments from different layers are processed, see [`Overdrop::merge_order`](crate::Overdrop::merge_order).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MergeOrder {
+    /// All effective fragments are processed in a single global order by filename,
+    /// whichever directory they come from, as systemd does for drop-ins.
+    #[default]
+    Filename,
+    /// Effective fragments are processed layer by layer, in increasing priority order
+    /// of the directories they come from, and by filename within each layer.
+    Layer,
+}
+
+/// Order fragments according to their `# After:` / `# Before:` directives.
+///
+/// `fragments` are in their default order (e.g. as returned by [`scan`](crate::scan)),
+/// which is kept for fragments not constrained by any directive. Directives referring
+/// to fragments which are not part of `fragments` are ignored.
+///
+/// Ordering cycles are rejected with an `InvalidData` error, naming the fragments
+/// involved.
+pub fn order_fragments<I>(fragments: I) -> io::Result<Vec<(OsString, PathBuf)>>
+where
+    I: IntoIterator<Item = (OsString, PathBuf)>,
+{
+    let fragments: Vec<(OsString, PathBuf)> = fragments.into_iter().collect();
+    let index: BTreeMap<&OsStr, usize> = fragments
+        .iter()
+        .enumerate()
+        .map(|(idx, (name, _))| (name.as_os_str(), idx))
+        .collect();
+
+    // Edges go from each fragment to the ones which must come after it.
+    let mut successors = vec![BTreeSet::new(); fragments.len()];
+    for (idx, (_, path)) in fragments.iter().enumerate() {
+        let (after, before) = read_directives(path)?;
+        for name in after {
+            if let Some(&other) = index.get(OsStr::new(&name)) {
+                successors[other].insert(idx);
+            }
+        }
+        for name in before {
+            if let Some(&other) = index.get(OsStr::new(&name)) {
+                successors[idx].insert(other);
+            }
+        }
+    }
+
+    let mut indegree = vec![0; fragments.len()];
+    for &succ in successors.iter().flatten() {
+        indegree[succ] += 1;
+    }
+    // Among the fragments ready to be merged, keep the default order.
+    let mut ready: BTreeSet<usize> = (0..fragments.len()).filter(|&i| indegree[i] == 0).collect();
+    let mut order = Vec::with_capacity(fragments.len());
+    while let Some(idx) = ready.pop_first() {
+        order.push(idx);
+        for &succ in &successors[idx] {
+            indegree[succ] -= 1;
+            if indegree[succ] == 0 {
+                ready.insert(succ);
+            }
+        }
+    }
+
+    if order.len() < fragments.len() {
+        let cycle: Vec<_> = (0..fragments.len())
+            .filter(|&i| indegree[i] > 0)
+            .map(|i| fragments[i].0.to_string_lossy().into_owned())
+            .collect();
+        let msg = format!("ordering cycle between fragments: {}", cycle.join(", "));
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+
+    let mut fragments: Vec<_> = fragments.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .filter_map(|idx| fragments[idx].take())
+        .collect())
+}
+
+/// Read the `After` and `Before` directives from the leading comment block of a fragment.
+fn read_directives(path: &Path) -> io::Result<(Vec<String>, Vec<String>)> {
+    let (mut after, mut before) = (Vec::new(), Vec::new());
+    let reader = BufReader::new(File::open(path)?);
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let comment = match line.strip_prefix('#') {
+            Some(comment) => comment.trim_start(),
+            None => break,
+        };
+        if let Some(names) = comment.strip_prefix("After:") {
+            after.extend(names.split_whitespace().map(String::from));
+        } else if let Some(names) = comment.strip_prefix("Before:") {
+            before.extend(names.split_whitespace().map(String::from));
+        }
+    }
+    Ok((after, before))
+}
+
+/// Scan configuration fragments and merge them, honoring ordering directives.
+///
+/// This works like [`scan_and_merge_with`](crate::scan_and_merge_with), with fragments
+/// merged in the order computed by [`order_fragments`].
+pub fn scan_and_merge_ordered<BdS, BdI, Sp, As, T, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    initial: T,
+    mut merge: F,
+) -> Result<T, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    E: From<io::Error>,
+    F: FnMut(&mut T, &OsStr, &mut BufReader<File>) -> Result<(), E>,
+{
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+    let fragments = order_fragments(scan_streaming(base_dirs, shared_path, &options))?;
+    let mut value = initial;
+    for (name, path) in fragments {
+        let mut reader = BufReader::new(File::open(&path)?);
+        merge(&mut value, &name, &mut reader)?;
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREEDIR: &str = "tests/fixtures/tree-order";
+
+    #[test]
+    fn ordered_merge() {
+        let dirs = ["usr/lib", "etc"].map(|d| Path::new(TREEDIR).join(d));
+        let order = scan_and_merge_ordered(
+            &dirs,
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            Vec::new(),
+            |order, name, _| -> io::Result<()> {
+                order.push(name.to_string_lossy().into_owned());
+                Ok(())
+            },
+        )
+        .unwrap();
+        // Directives after the leading comment block (as in `40-d.conf`) are ignored.
+        assert_eq!(order, ["30-c.conf", "10-a.conf", "20-b.conf", "40-d.conf"]);
+
+        let unordered = crate::scan(&dirs, "liboverdrop.d", &["conf"], false);
+        let ordered = order_fragments(unordered.clone()).unwrap();
+        assert_eq!(ordered.len(), unordered.len());
+        assert_eq!(ordered[0].1, unordered[OsStr::new("30-c.conf")]);
+    }
+
+    #[test]
+    fn ordering_cycle() {
+        let dirs = [Path::new(TREEDIR).join("cycle")];
+        let fragments = crate::scan::<_, _, _, &str>(&dirs, "liboverdrop.d", &[], false);
+        let err = order_fragments(fragments).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("10-x.conf, 20-y.conf"));
+    }
+}
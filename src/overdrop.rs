@@ -0,0 +1,2311 @@
+//! Builder-style scanner configuration.
+
+use crate::digest::digest_sources;
+use crate::layout::LayerEntries;
+use crate::source::{LimitedReader, SizeLimit};
+use crate::{
+    check_dir_symlinks, normalize_extensions, read_dir_entries, resolve_name, under_root,
+    version_cmp, Digest, DirOptions, DirScan, DirStatus, DirSymlinkPolicy, Entry, Fragment,
+    FragmentSet, Interner, LayerPolicy, LayerSpec, Layout, MergeOrder, NamePattern, Rejection,
+    Resolver, ScanError, ScanReport, SkipReason, SkippedEntry, Source, StdFs, SymlinkPolicy,
+    TrustPolicy, SYSTEMD_CONVENTIONAL_BASES,
+};
+use log::warn;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Size limits for merges, see [`Overdrop::max_fragment_size`].
+#[derive(Clone, Copy, Debug, Default)]
+struct SizeLimits {
+    fragment: Option<u64>,
+    total: Option<u64>,
+}
+
+impl SizeLimits {
+    /// The tightest limit for the next fragment, after `used` bytes were already merged.
+    fn next(&self, used: u64) -> Option<SizeLimit> {
+        let total = self
+            .total
+            .map(|limit| SizeLimit::Total(limit.saturating_sub(used), limit));
+        match (self.fragment.map(SizeLimit::Fragment), total) {
+            (Some(fragment), Some(total)) if total.bytes() < fragment.bytes() => Some(total),
+            (Some(fragment), _) => Some(fragment),
+            (None, total) => total,
+        }
+    }
+}
+
+/// Builder for configuration fragment scans.
+///
+/// This collects the same settings as the positional arguments of [`scan`](crate::scan),
+/// plus further options, and then performs the scan.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use liboverdrop::Overdrop;
+/// let fragments = Overdrop::new("my-crate/config.d")
+///     .base_dirs(["/usr/lib", "/run", "/etc"])
+///     .allowed_extensions(&["toml"])
+///     .require_nonempty(true)
+///     .scan()?;
+/// # Ok::<(), liboverdrop::ScanError>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Overdrop {
+    /// Shared paths, in increasing priority order within each base directory.
+    shared_paths: Vec<PathBuf>,
+    base_dirs: Vec<PathBuf>,
+    options: DirOptions,
+    require_all_dirs_exist: bool,
+    /// Individual base directories which are required to exist.
+    required_dirs: Vec<PathBuf>,
+    /// Masking and override policies, by base directory.
+    policies: Vec<(PathBuf, LayerPolicy)>,
+    /// Policy of layers without an explicit one.
+    default_policy: LayerPolicy,
+    /// Shared paths of other services, scanned as lower-priority layers.
+    imports: Vec<PathBuf>,
+    /// Synthetic fragments, as `(priority, name, contents)`.
+    synthetic: Vec<(usize, OsString, Arc<[u8]>)>,
+    validator: Option<Validator>,
+    fail_on_rejection: bool,
+    /// Processing order of fragments, if not by filename.
+    order: Option<NameOrder>,
+    merge_order: MergeOrder,
+    dir_symlinks: DirSymlinkPolicy,
+    dedupe_files: bool,
+    require_nonempty: bool,
+    /// Maximum size of each fragment, and of all of them, for merges.
+    limits: SizeLimits,
+}
+
+impl Overdrop {
+    /// Create a new scanner for fragments under `shared_path`.
+    ///
+    /// Base directories default to [`SYSTEMD_CONVENTIONAL_BASES`], all extensions
+    /// are allowed and dotfiles are not ignored.
+    pub fn new(shared_path: impl AsRef<Path>) -> Self {
+        Self {
+            shared_paths: vec![shared_path.as_ref().to_path_buf()],
+            base_dirs: SYSTEMD_CONVENTIONAL_BASES
+                .iter()
+                .map(PathBuf::from)
+                .collect(),
+            options: DirOptions::default(),
+            require_all_dirs_exist: false,
+            required_dirs: Vec::new(),
+            policies: Vec::new(),
+            default_policy: LayerPolicy::default(),
+            imports: Vec::new(),
+            synthetic: Vec::new(),
+            validator: None,
+            fail_on_rejection: false,
+            require_nonempty: false,
+            order: None,
+            limits: SizeLimits::default(),
+            merge_order: MergeOrder::default(),
+            dir_symlinks: DirSymlinkPolicy::default(),
+            dedupe_files: false,
+        }
+    }
+
+    /// Set the base directories to scan, in increasing priority order.
+    #[must_use]
+    pub fn base_dirs<BdS: AsRef<Path>, BdI: IntoIterator<Item = BdS>>(
+        mut self,
+        base_dirs: BdI,
+    ) -> Self {
+        self.base_dirs = base_dirs
+            .into_iter()
+            .map(|d| d.as_ref().to_path_buf())
+            .collect();
+        self
+    }
+
+    /// Scan base directories under an alternate `root`, e.g. a mounted target at `/sysroot`.
+    ///
+    /// The root is prefixed onto all base directories (including the default
+    /// [`SYSTEMD_CONVENTIONAL_BASES`]), so that e.g. `/etc` is scanned as `/sysroot/etc`.
+    /// Returned paths include the root. Base directories passed to other settings
+    /// (e.g. [`layer_policy`](Self::layer_policy)) are still given without it.
+    ///
+    /// Mask symlinks are resolved against the alternate root, as they would be after
+    /// switching to it: a link to `/dev/null` (or `../../dev/null`) masks fragments,
+    /// and absolute links in a chain are followed under the root rather than on the host.
+    #[must_use]
+    pub fn root(mut self, root: impl AsRef<Path>) -> Self {
+        self.options.root = Some(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the base directories to scan from a [`LayerSpec`].
+    ///
+    /// Scanned directories under layers marked as required must exist, otherwise
+    /// the scan fails with [`ScanError::MissingDirs`].
+    #[must_use]
+    pub fn layers(mut self, spec: &LayerSpec) -> Self {
+        self = self.base_dirs(spec.base_dirs());
+        self.required_dirs = spec.required_dirs().map(Path::to_path_buf).collect();
+        self
+    }
+
+    /// Set the masking and override policy for the layer at `base_dir`.
+    ///
+    /// Layers without an explicit policy use [`LayerPolicy::default()`] (adjusted by
+    /// [`empty_file_masks`](Self::empty_file_masks)). Setting a policy for the same
+    /// base directory twice replaces the previous one.
+    #[must_use]
+    pub fn layer_policy(mut self, base_dir: impl AsRef<Path>, policy: LayerPolicy) -> Self {
+        let base_dir = base_dir.as_ref();
+        self.policies.retain(|(dir, _)| dir != base_dir);
+        self.policies.push((base_dir.to_path_buf(), policy));
+        self
+    }
+
+    /// Whether empty files mask fragments from lower layers, like `/dev/null` symlinks (by default, they do not).
+    ///
+    /// With this, a zero-length regular file removes its name from the results instead
+    /// of overriding it with empty contents, as several projects and parts of systemd
+    /// do. This applies to all layers without an explicit
+    /// [`layer_policy`](Self::layer_policy); see [`LayerPolicy::empty_file_masks`].
+    #[must_use]
+    pub fn empty_file_masks(mut self, empty_file_masks: bool) -> Self {
+        self.default_policy = self.default_policy.empty_file_masks(empty_file_masks);
+        self
+    }
+
+    /// Set the shared paths to scan under each base directory, replacing the one given to [`new`](Self::new).
+    ///
+    /// This allows e.g. a legacy `myapp/conf.d` to be scanned together with `myapp/config.d`,
+    /// with the same precedence as in [`scan_shared_paths`](crate::scan_shared_paths).
+    #[must_use]
+    pub fn shared_paths<Sp: AsRef<Path>, SpI: IntoIterator<Item = Sp>>(
+        mut self,
+        shared_paths: SpI,
+    ) -> Self {
+        self.shared_paths = shared_paths
+            .into_iter()
+            .map(|p| p.as_ref().to_path_buf())
+            .collect();
+        self
+    }
+
+    /// Also scan fragments under another service's `shared_path`, as lower-priority layers.
+    ///
+    /// This allows site defaults shared by several services (e.g. `fleet-defaults/config.d`)
+    /// to participate without being symlinked into every service's tree. The imported
+    /// path is scanned under all base directories, with lower priority than all of the
+    /// service's own directories; further imports have higher priority than earlier ones.
+    /// Imported directories are never required to exist.
+    #[must_use]
+    pub fn import(mut self, shared_path: impl AsRef<Path>) -> Self {
+        self.imports.push(shared_path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Inject a synthetic fragment named `name`, held in memory.
+    ///
+    /// This allows values computed at runtime (or received over an API) to take part
+    /// in the normal override and masking rules. The fragment is placed in its own
+    /// layer right above the first `priority` base directories: `0` ranks it below
+    /// all of them, while `base_dirs.len()` (or more) ranks it above all of them.
+    /// Synthetic fragments with the same name and priority override earlier ones.
+    ///
+    /// Synthetic fragments are not subject to filename filters nor to the
+    /// [`validator`](Self::validator), and they only appear in the results of
+    /// [`scan_sources`](Self::scan_sources): path-based results only list fragment files,
+    /// hence a filename won by a synthetic fragment is missing from them.
+    #[must_use]
+    pub fn inject(
+        mut self,
+        name: impl AsRef<OsStr>,
+        contents: impl Into<Vec<u8>>,
+        priority: usize,
+    ) -> Self {
+        let contents = Arc::from(contents.into());
+        self.synthetic
+            .push((priority, name.as_ref().to_os_string(), contents));
+        self
+    }
+
+    /// Only scan files with one of these extensions (by default, all are allowed).
+    #[must_use]
+    pub fn allowed_extensions<As: AsRef<OsStr>>(mut self, allowed_extensions: &[As]) -> Self {
+        self.options.allowed_extensions = normalize_extensions(allowed_extensions);
+        self
+    }
+
+    /// Only scan files whose name matches one of these patterns (by default, all are allowed).
+    ///
+    /// This applies in addition to [`allowed_extensions`](Self::allowed_extensions), e.g.
+    /// to only consider fragments matching `[0-9][0-9]-*.toml`. When scanning
+    /// subdirectories, patterns only apply to fragment filenames.
+    #[must_use]
+    pub fn name_patterns<I: IntoIterator<Item = NamePattern>>(mut self, patterns: I) -> Self {
+        self.options.patterns = patterns.into_iter().collect();
+        self
+    }
+
+    /// Only scan files whose name starts with `prefix` (by default, all are allowed).
+    ///
+    /// This is meant for directories shared by several components, each owning a
+    /// prefix namespace (e.g. `10-networkd-`). It applies in addition to
+    /// [`name_patterns`](Self::name_patterns), and entries without the prefix are
+    /// ignored as if they did not exist, including masks.
+    #[must_use]
+    pub fn name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.options.name_prefix = prefix.into();
+        self
+    }
+
+    /// Never scan files whose name matches one of these patterns.
+    ///
+    /// This is meant for files which do not belong in configuration directories,
+    /// like `README`, `placeholder.conf` or package-manager leftovers (`*.rpmsave`).
+    /// Excluded entries are ignored as if they did not exist: unlike filtering the
+    /// results afterwards, they neither override nor mask fragments from lower layers.
+    #[must_use]
+    pub fn exclude_patterns<I: IntoIterator<Item = NamePattern>>(mut self, patterns: I) -> Self {
+        self.options.excluded = patterns.into_iter().collect();
+        self
+    }
+
+    /// Whether to ignore dotfiles (hidden files with name prefixed with '.').
+    ///
+    /// This is a convenience for the most common [`hidden_files`](Self::hidden_files) policy.
+    #[must_use]
+    pub fn ignore_dotfiles(mut self, ignore_dotfiles: bool) -> Self {
+        self.options.ignore_dotfiles = ignore_dotfiles;
+        self
+    }
+
+    /// Set a policy deciding which files are hidden, and thus ignored, by filename.
+    ///
+    /// This applies in addition to [`ignore_dotfiles`](Self::ignore_dotfiles), e.g. to
+    /// also hide files like `README` or `LICENSE` that vendors drop into configuration
+    /// directories.
+    #[must_use]
+    pub fn hidden_files<F>(mut self, policy: F) -> Self
+    where
+        F: Fn(&OsStr) -> bool + Send + Sync + 'static,
+    {
+        self.options.hidden = Some(Arc::new(policy));
+        self
+    }
+
+    /// Set a function deriving the override key of each fragment from its name.
+    ///
+    /// By default, fragments override each other by full filename. With e.g. the file
+    /// stem as key, `custom.toml` in `/etc` overrides `custom.yaml` in `/usr/lib`.
+    /// If several fragments in the same directory share a key, the one with the
+    /// greatest filename wins. Results are indexed by key.
+    #[must_use]
+    pub fn key_fn<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&OsStr) -> OsString + Send + Sync + 'static,
+    {
+        self.options.key = Some(Arc::new(key));
+        self
+    }
+
+    /// Prioritize extensions, for fragments with the same stem in the same directory.
+    ///
+    /// Fragments with one of these extensions are keyed by their stem (e.g. `10-foo`
+    /// for both `10-foo.toml` and `10-foo.json`), and if a directory holds several
+    /// fragments with the same stem, the one whose extension comes first wins. Across
+    /// directories, higher-priority directories still win regardless of extensions.
+    /// With a [`key_fn`](Self::key_fn), that one decides keys instead.
+    #[must_use]
+    pub fn extension_priority<As: AsRef<OsStr>>(mut self, extensions: &[As]) -> Self {
+        self.options.extension_priority = normalize_extensions(extensions);
+        self
+    }
+
+    /// Set a filter, deciding whether each candidate entry is considered at all.
+    ///
+    /// The filter receives the path and (non-followed) metadata of each entry which
+    /// passed the filename filters, e.g. to skip files above a size threshold.
+    /// Filtered out entries are ignored, as if they did not exist; this also applies
+    /// to masks.
+    #[must_use]
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Path, &fs::Metadata) -> bool + Send + Sync + 'static,
+    {
+        self.options.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Set a callback, receiving each entry which is skipped while scanning, and why.
+    ///
+    /// This covers all entries which are not considered at all (e.g. because of their
+    /// extension, as dotfiles, or as they are not regular files), rejected fragments, and
+    /// directories which exist but cannot be read, e.g. to answer "why isn't my fragment
+    /// picked up" in production logs. Entries which are considered, but then shadowed
+    /// or masked by higher-priority ones, are not skipped; see [`layout`](Self::layout)
+    /// for those.
+    #[must_use]
+    pub fn on_skipped<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&SkippedEntry) + Send + Sync + 'static,
+    {
+        self.options.on_skipped = Some(Arc::new(callback));
+        self
+    }
+
+    /// Set a validator, checking the contents of each winning fragment before accepting it.
+    ///
+    /// The validator receives the path and contents of each fragment (e.g. to run a
+    /// `visudo -c`-like check), and returns the reason for rejecting it, if any.
+    /// Rejected fragments are skipped, as if they did not exist (so a lower-priority
+    /// fragment with the same filename may take over, and is validated in turn), and
+    /// are listed in [`DirStatus::rejected`]; see also
+    /// [`fail_on_rejection`](Self::fail_on_rejection). Shadowed and masked fragments
+    /// are never read; fragments are opened with [`Fragment::open`], and reading them
+    /// is subject to the [size limits](Self::max_fragment_size), failing validation
+    /// if they are exceeded.
+    #[must_use]
+    pub fn validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&Path, &[u8]) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.validator = Some(Validator(Arc::new(validator)));
+        self
+    }
+
+    /// Only accept entries satisfying ownership and permission requirements.
+    ///
+    /// See [`TrustPolicy`]. Untrusted fragments and masks are rejected, as if they did
+    /// not exist, before the [validator](Self::validator) runs; see also
+    /// [`fail_on_rejection`](Self::fail_on_rejection).
+    #[must_use]
+    pub fn trust(mut self, policy: TrustPolicy) -> Self {
+        self.options.trust = Some(policy);
+        self
+    }
+
+    /// Whether to fail with [`ScanError::Rejected`] if the validator (or the [trust policy](Self::trust)) rejects any fragment.
+    #[must_use]
+    pub fn fail_on_rejection(mut self, fail: bool) -> Self {
+        self.fail_on_rejection = fail;
+        self
+    }
+
+    /// Set how symlinks other than masks are handled (by default, they are ignored).
+    ///
+    /// See [`SymlinkPolicy`]. With [`SymlinkPolicy::Warn`] and [`SymlinkPolicy::Error`],
+    /// skipped symlinks are listed in [`DirStatus::symlinks`].
+    #[must_use]
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.options.symlinks = policy;
+        self
+    }
+
+    /// Whether broken symlinks mask fragments from lower layers, like `/dev/null` symlinks (by default, they do not).
+    ///
+    /// This matches systemd, where a dangling symlink hides the unit or drop-in it names.
+    /// Masking is still subject to the [`layer_policy`](Self::layer_policy) of each layer.
+    #[must_use]
+    pub fn broken_symlinks_mask(mut self, masks: bool) -> Self {
+        self.options.broken_symlinks_mask = masks;
+        self
+    }
+
+    /// Set whether scanned directories may be symlinks (by default, they are followed).
+    ///
+    /// This applies to the shared path within each base directory, e.g. to a
+    /// `/etc/foo/config.d` symlinked to a shared location. Directories which may not be
+    /// followed are skipped, and reported as unreadable in their [`DirStatus`].
+    #[must_use]
+    pub fn dir_symlinks(mut self, policy: DirSymlinkPolicy) -> Self {
+        self.dir_symlinks = policy;
+        self
+    }
+
+    /// Descend into subdirectories of the scanned directories, up to `max_depth` levels.
+    ///
+    /// By default (`0`), only fragments directly inside each scanned directory are
+    /// considered. Fragments found in subdirectories are keyed by their path relative
+    /// to the scanned directory (e.g. `network/10-dhcp.toml`), and override or mask
+    /// each other by that relative path. Filename filters apply to each component.
+    #[must_use]
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.options.max_depth = max_depth;
+        self
+    }
+
+    /// Whether to report files which are ignored because they do not match the allowed extensions.
+    ///
+    /// Such files (e.g. `50-custom.tomll` or `config.toml.txt`) are most likely typos, so
+    /// they are logged as warnings and listed in [`DirStatus::unmatched`]. Hidden files
+    /// are never reported.
+    #[must_use]
+    pub fn report_unmatched(mut self, report: bool) -> Self {
+        self.options.report_unmatched = report;
+        self
+    }
+
+    /// Whether to process fragments in version order, as systemd does, instead of by filename.
+    ///
+    /// This affects [`scan_sorted`](Self::scan_sorted) and [`scan_and_merge`](Self::scan_and_merge),
+    /// which then follow [`version_cmp`](crate::version_cmp) (so that e.g. `2-foo.conf`
+    /// comes before `10-foo.conf`).
+    #[must_use]
+    pub fn version_sort(mut self, version_sort: bool) -> Self {
+        self.order = version_sort.then(|| NameOrder(Arc::new(version_cmp)));
+        self
+    }
+
+    /// Set the order in which fragments are processed, by comparing their names.
+    ///
+    /// This affects [`scan_sorted`](Self::scan_sorted) and [`scan_and_merge`](Self::scan_and_merge),
+    /// replacing any previously set order (e.g. [`version_sort`](Self::version_sort)).
+    /// Fragments comparing equal keep their order by filename.
+    #[must_use]
+    pub fn sort_by<F>(mut self, cmp: F) -> Self
+    where
+        F: Fn(&OsStr, &OsStr) -> Ordering + Send + Sync + 'static,
+    {
+        self.order = Some(NameOrder(Arc::new(cmp)));
+        self
+    }
+
+    /// Set the order in which fragments are processed, by a key extracted from their names.
+    ///
+    /// See [`sort_by`](Self::sort_by); e.g. with priorities encoded as a trailing
+    /// suffix (`foo.priority-90.conf`):
+    ///
+    /// ```rust,no_run
+    /// # use liboverdrop::Overdrop;
+    /// let fragments = Overdrop::new("my-crate/config.d")
+    ///     .sort_by_key(|name| {
+    ///         let name = name.to_string_lossy();
+    ///         let priority = name.rsplit_once(".priority-").and_then(|(_, p)| {
+    ///             p.split('.').next().and_then(|p| p.parse::<u32>().ok())
+    ///         });
+    ///         priority.unwrap_or(50)
+    ///     })
+    ///     .scan_sorted()?;
+    /// # Ok::<(), liboverdrop::ScanError>(())
+    /// ```
+    #[must_use]
+    pub fn sort_by_key<K, F>(self, key: F) -> Self
+    where
+        K: Ord,
+        F: Fn(&OsStr) -> K + Send + Sync + 'static,
+    {
+        self.sort_by(move |a, b| key(a).cmp(&key(b)))
+    }
+
+    /// Set the order in which fragments from different layers are processed.
+    ///
+    /// This affects [`scan_sorted`](Self::scan_sorted) and [`scan_and_merge`](Self::scan_and_merge).
+    /// By default ([`MergeOrder::Filename`]), all fragments are ordered together by
+    /// filename; with [`MergeOrder::Layer`], they are first grouped by directory, in
+    /// increasing priority order, with [synthetic](Self::inject) fragments right before
+    /// the directories of the base directory they were injected at. Within the same
+    /// layer, fragments follow the order configured e.g. via [`sort_by`](Self::sort_by).
+    #[must_use]
+    pub fn merge_order(mut self, merge_order: MergeOrder) -> Self {
+        self.merge_order = merge_order;
+        self
+    }
+
+    /// Whether to keep only one name for fragments which are the same physical file (by default, all are kept).
+    ///
+    /// With bind mounts, hard links or followed [symlinks](Self::symlinks), the same
+    /// file (by device and inode number) can win under several names, and would then
+    /// be read and merged several times, e.g. double-applying append-style settings.
+    /// With this, only the first of these names in filename order is kept. This has
+    /// no effect on platforms without stable file identifiers.
+    #[must_use]
+    pub fn dedupe_files(mut self, dedupe: bool) -> Self {
+        self.dedupe_files = dedupe;
+        self
+    }
+
+    /// Whether to fail on directories which exist but cannot be read, instead of treating them as empty.
+    ///
+    /// Missing directories are still skipped (see
+    /// [`require_all_dirs_exist`](Self::require_all_dirs_exist)), but any other error
+    /// while reading a directory or its entries (e.g. permission denied, a symlink
+    /// loop, or a file in place of the directory) fails the scan with
+    /// [`ScanError::Io`], as [`try_scan`](crate::try_scan) does.
+    #[must_use]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Whether to fail with [`ScanError::MissingDirs`] if any scanned directory does not exist.
+    #[must_use]
+    pub fn require_all_dirs_exist(mut self, require: bool) -> Self {
+        self.require_all_dirs_exist = require;
+        self
+    }
+
+    /// Whether to fail with [`ScanError::NoFragments`] if no fragment is found.
+    ///
+    /// This catches e.g. a typo'd shared path silently yielding an empty configuration.
+    #[must_use]
+    pub fn require_nonempty(mut self, require: bool) -> Self {
+        self.require_nonempty = require;
+        self
+    }
+
+    /// Set the maximum size of each fragment read by [`scan_and_merge`](Self::scan_and_merge), in bytes.
+    ///
+    /// Larger fragments (e.g. a log file accidentally dropped into the directory) fail
+    /// the merge with [`ScanError::FragmentTooLarge`], without being read; they also fail
+    /// the [validator](Self::validator), if any.
+    #[must_use]
+    pub fn max_fragment_size(mut self, limit: u64) -> Self {
+        self.limits.fragment = Some(limit);
+        self
+    }
+
+    /// Set the maximum size of all fragments read by [`scan_and_merge`](Self::scan_and_merge), in bytes.
+    ///
+    /// Going over it fails the merge with [`ScanError::TotalTooLarge`], with the first
+    /// fragment that does not fit.
+    #[must_use]
+    pub fn max_total_size(mut self, limit: u64) -> Self {
+        self.limits.total = Some(limit);
+        self
+    }
+
+    /// Return the directories which would be scanned, in increasing priority order.
+    ///
+    /// See [`effective_search_dirs`](crate::effective_search_dirs); directories of
+    /// [imported](Self::import) shared paths come first.
+    pub fn search_dirs(&self) -> Vec<PathBuf> {
+        self.search_layers()
+            .into_iter()
+            .map(|layer| layer.dir)
+            .collect()
+    }
+
+    /// Return `base` under the [alternate root](Self::root), if any.
+    fn rooted(&self, base: &Path) -> PathBuf {
+        match &self.options.root {
+            Some(root) => under_root(root, base),
+            None => base.to_path_buf(),
+        }
+    }
+
+    /// Return the scanned layers, in increasing priority order.
+    fn search_layers(&self) -> Vec<SearchLayer<'_>> {
+        let imported = self.imports.iter().flat_map(|shared_path| {
+            self.base_dirs.iter().map(move |base| SearchLayer {
+                base,
+                dir: self.rooted(base).join(shared_path),
+                imported: true,
+            })
+        });
+        let own = self.base_dirs.iter().flat_map(|base| {
+            self.shared_paths
+                .iter()
+                .map(move |shared_path| SearchLayer {
+                    base,
+                    dir: self.rooted(base).join(shared_path),
+                    imported: false,
+                })
+        });
+        imported.chain(own).collect()
+    }
+
+    /// Scan unique configuration fragments, as [`scan`](crate::scan) does.
+    pub fn scan(&self) -> Result<BTreeMap<OsString, PathBuf>, ScanError> {
+        self.scan_fragments().map(FragmentSet::into_paths)
+    }
+
+    /// Scan unique configuration fragments, interning names and paths in `pool`.
+    ///
+    /// Results from repeated scans sharing the same pool reuse the same allocations
+    /// for identical fragment names and paths.
+    pub fn scan_interned(
+        &self,
+        pool: &mut Interner,
+    ) -> Result<BTreeMap<Arc<OsStr>, Arc<Path>>, ScanError> {
+        let fragments = self.scan_fragments()?;
+        Ok(fragments
+            .iter()
+            .map(|(name, fragment)| (pool.name(name), pool.path(fragment.path())))
+            .collect())
+    }
+
+    /// Update `fragments`, from a previous scan with the same settings, after changes to `changed` paths.
+    ///
+    /// This is meant to be driven by filesystem watch events: each changed path is
+    /// expected to be the location of a fragment (or mask) inside one of the scanned
+    /// directories, and only the winners for the affected filenames are recomputed,
+    /// instead of re-walking all directories. If one of the scanned directories itself
+    /// (or any of its ancestors) changed, this falls back to a full rescan. Other paths
+    /// are ignored.
+    ///
+    /// Returns the filenames whose fragment was added, removed or replaced. Strictness
+    /// checks (e.g. [`require_nonempty`](Self::require_nonempty)) are not applied.
+    pub fn rescan_changed<P: AsRef<Path>, I: IntoIterator<Item = P>>(
+        &self,
+        fragments: &mut FragmentSet,
+        changed: I,
+    ) -> Vec<OsString> {
+        let layers: Vec<_> = self
+            .search_layers()
+            .into_iter()
+            .map(|layer| (layer.dir, self.policy_for(layer.base)))
+            .collect();
+
+        // Single-name resolution doesn't filter, key, dedupe nor validate fragments, nor know
+        // of synthetic ones or symlinked directories, and changes are only mapped to names
+        // directly inside the scanned directories.
+        if self.options.filter.is_some()
+            || self.options.is_keyed()
+            || self.validator.is_some()
+            || !self.synthetic.is_empty()
+            || self.options.max_depth > 0
+            || self.dir_symlinks != DirSymlinkPolicy::Follow
+            || self.dedupe_files
+        {
+            return self.rescan_all(fragments);
+        }
+
+        let mut names = BTreeSet::new();
+        for path in changed {
+            let path = path.as_ref();
+            if layers.iter().any(|(dir, _)| dir.starts_with(path)) {
+                return self.rescan_all(fragments);
+            }
+            if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+                if layers.iter().any(|(dir, _)| dir == parent) && self.options.accepts_name(name) {
+                    names.insert(name.to_os_string());
+                }
+            }
+        }
+
+        names
+            .into_iter()
+            .filter(|name| {
+                fragments.update(name.clone(), resolve_name(&layers, name, &self.options))
+            })
+            .collect()
+    }
+
+    fn rescan_all(&self, fragments: &mut FragmentSet) -> Vec<OsString> {
+        let (sources, _) = self.scan_layers();
+        let fresh = FragmentSet::from_map(files_only(sources));
+
+        let changed = fragments.changed_names(&fresh);
+        *fragments = fresh;
+        changed
+    }
+
+    /// Scan unique configuration fragments, with their details.
+    pub fn scan_fragments(&self) -> Result<FragmentSet, ScanError> {
+        self.scan_with_status().map(|(fragments, _)| fragments)
+    }
+
+    pub(crate) fn options(&self) -> &DirOptions {
+        &self.options
+    }
+
+    fn policy_for(&self, base_dir: &Path) -> LayerPolicy {
+        self.policies
+            .iter()
+            .find(|(dir, _)| dir == base_dir)
+            .map_or(self.default_policy, |(_, policy)| *policy)
+    }
+
+    /// Scan all layers, applying their policies.
+    fn scan_layers(&self) -> (BTreeMap<OsString, Source>, Vec<DirStatus>) {
+        self.scan_layers_with(|dir, found| read_dir_entries(&StdFs, dir, &self.options, found))
+    }
+
+    /// Scan all layers as [`scan_layers`](Self::scan_layers) does, with directories read by `read`.
+    pub(crate) fn scan_layers_with<R>(
+        &self,
+        mut read: R,
+    ) -> (BTreeMap<OsString, Source>, Vec<DirStatus>)
+    where
+        R: FnMut(&Path, &mut dyn FnMut(OsString, Entry<Fragment>)) -> io::Result<DirScan>,
+    {
+        // Entries are kept by layer, as fragments rejected by the validator are resolved again.
+        let mut layers = Vec::new();
+        let mut statuses = Vec::with_capacity(self.base_dirs.len());
+        // Synthetic fragments are ranked by base directory, not by scanned directory.
+        let mut own_bases = 0;
+        let mut last_base = None;
+        for (
+            index,
+            SearchLayer {
+                base,
+                dir,
+                imported,
+            },
+        ) in self.search_layers().into_iter().enumerate()
+        {
+            if !imported && last_base != Some(base) {
+                layers.push(self.synthetic_layer(|priority| priority == own_bases));
+                own_bases += 1;
+                last_base = Some(base);
+            }
+            let mut entries = Vec::new();
+            let checked =
+                check_dir_symlinks(self.dir_symlinks, &self.rooted(base), &dir).map_err(|e| {
+                    self.options
+                        .skipped(&dir, || SkipReason::Unreadable(e.kind()));
+                    e
+                });
+            let res = checked.and_then(|()| {
+                read(&dir, &mut |fname, entry| {
+                    let entry = entry.map(|f| Source::File(f.in_search_layer(index)));
+                    entries.push((fname, entry));
+                })
+            });
+            layers.push((self.policy_for(base), entries));
+            statuses.push(DirStatus::new(dir, res));
+        }
+        layers.push(self.synthetic_layer(|priority| priority >= own_bases));
+        let mut sources = self.resolve_validated(&layers, &mut statuses);
+        if self.dedupe_files {
+            let mut seen = HashSet::new();
+            sources.retain(|_, source| match source {
+                Source::File(fragment) => fragment.file_id().map_or(true, |id| seen.insert(id)),
+                Source::Memory(_) => true,
+            });
+        }
+        (sources, statuses)
+    }
+
+    /// Return the synthetic fragments whose priority matches `at`, as a layer of their own.
+    fn synthetic_layer(&self, at: impl Fn(usize) -> bool) -> (LayerPolicy, Vec<SourceEntry>) {
+        let entries = self.synthetic.iter().filter(|(p, ..)| at(*p));
+        let entries = entries.map(|(_, name, contents)| {
+            let entry = Entry::Fragment(Source::Memory(Arc::clone(contents)));
+            (name.clone(), entry)
+        });
+        (LayerPolicy::default(), entries.collect())
+    }
+
+    /// Resolve the winning sources of `layers`, skipping fragments rejected by the validator.
+    ///
+    /// Only winners are validated: if one is rejected, the names are resolved again
+    /// without it, so that a lower-priority fragment may take over (and is then
+    /// validated in turn). Rejections are recorded in the status of their directory.
+    fn resolve_validated(
+        &self,
+        layers: &[(LayerPolicy, Vec<SourceEntry>)],
+        statuses: &mut [DirStatus],
+    ) -> BTreeMap<OsString, Source> {
+        let mut rejected = HashSet::new();
+        let mut validated = HashSet::new();
+        loop {
+            let mut resolver = Resolver::new();
+            for (policy, entries) in layers {
+                for (name, entry) in entries {
+                    let is_rejected = match entry {
+                        Entry::Fragment(Source::File(f)) | Entry::Empty(Source::File(f)) => {
+                            rejected.contains(f.path())
+                        }
+                        _ => false,
+                    };
+                    if !is_rejected {
+                        resolver.apply(*policy, name.clone(), entry.clone());
+                    }
+                }
+            }
+            let sources = resolver.into_map();
+            let validator = match &self.validator {
+                Some(validator) => validator,
+                None => return sources,
+            };
+
+            let mut done = true;
+            for (name, source) in &sources {
+                let fragment = match source {
+                    Source::File(fragment) if validated.insert(fragment.path().to_path_buf()) => {
+                        fragment
+                    }
+                    _ => continue,
+                };
+                if let Some(rejection) = validator.check(name, fragment, self.limits.next(0)) {
+                    let reason = || SkipReason::Rejected(rejection.reason().to_string());
+                    self.options.skipped(rejection.path(), reason);
+                    rejected.insert(fragment.path().to_path_buf());
+                    if let Some(status) = statuses.get_mut(fragment.search_layer()) {
+                        status.reject(rejection);
+                    }
+                    done = false;
+                }
+            }
+            if done {
+                return sources;
+            }
+        }
+    }
+
+    /// Scan unique configuration fragments, also reporting the status of each directory.
+    ///
+    /// Statuses are returned in the same order as the scanned directories. This allows
+    /// support tooling to show e.g. that a directory exists but is unreadable by the
+    /// service user.
+    pub fn scan_with_status(&self) -> Result<(FragmentSet, Vec<DirStatus>), ScanError> {
+        let (sources, statuses) = self.scan_sources_with_status()?;
+        Ok((FragmentSet::from_map(files_only(sources)), statuses))
+    }
+
+    /// Scan unique configuration fragments, including [synthetic](Self::inject) ones.
+    ///
+    /// Synthetic fragments also count for [`require_nonempty`](Self::require_nonempty).
+    pub fn scan_sources(&self) -> Result<BTreeMap<OsString, Source>, ScanError> {
+        self.scan_sources_with_status().map(|(sources, _)| sources)
+    }
+
+    /// Scan unique configuration fragments, and open them.
+    ///
+    /// Each fragment is opened with [`Fragment::open`] right after scanning, so that
+    /// fragments replaced in the meanwhile (e.g. in a world-writable `/run`) are not
+    /// read instead of the scanned ones; such a fragment fails the scan with
+    /// [`ScanError::Io`]. Returns the path and an open file for each fragment.
+    pub fn scan_open(&self) -> Result<BTreeMap<OsString, (PathBuf, File)>, ScanError> {
+        self.scan_fragments()?
+            .iter()
+            .map(|(name, fragment)| match fragment.open() {
+                Ok(file) => Ok((name.clone(), (fragment.path().to_path_buf(), file))),
+                Err(source) => Err(ScanError::Io {
+                    path: fragment.path().to_path_buf(),
+                    source,
+                }),
+            })
+            .collect()
+    }
+
+    /// Scan unique configuration fragments and compute their [`Digest`].
+    ///
+    /// This also covers [synthetic](Self::inject) fragments, and fails with
+    /// [`ScanError::Io`] if any fragment cannot be read.
+    pub fn digest(&self) -> Result<Digest, ScanError> {
+        let sources = self.scan_sources()?;
+        digest_sources(&sources).map_err(|(path, source)| ScanError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Scan unique configuration fragments, in processing order.
+    ///
+    /// Fragments are sorted by filename, unless another order is configured, e.g.
+    /// via [`version_sort`](Self::version_sort).
+    pub fn scan_sorted(&self) -> Result<Vec<(OsString, PathBuf)>, ScanError> {
+        let mut fragments: Vec<_> = self.scan_fragments()?.into_iter().collect();
+        let ranks = self.layer_ranks();
+        self.sort(&mut fragments, |_, fragment| ranks.of_fragment(fragment));
+        Ok(fragments
+            .into_iter()
+            .map(|(name, fragment)| (name, fragment.into_path()))
+            .collect())
+    }
+
+    /// Scan the paths of unique configuration fragments, in processing order.
+    ///
+    /// This is [`scan_sorted`](Self::scan_sorted) without the filenames, e.g. to pass
+    /// the fragments to an external parser.
+    pub fn effective_paths(&self) -> Result<Vec<PathBuf>, ScanError> {
+        let fragments = self.scan_sorted()?;
+        Ok(fragments.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Sort `fragments` (initially by filename) in processing order, given the layer `rank` of each.
+    fn sort<T>(&self, fragments: &mut [(OsString, T)], rank: impl Fn(&OsStr, &T) -> usize) {
+        let by_layer = self.merge_order == MergeOrder::Layer;
+        if !by_layer && self.order.is_none() {
+            return;
+        }
+        fragments.sort_by(|(a, x), (b, y)| {
+            let layers = if by_layer {
+                rank(a, x).cmp(&rank(b, y))
+            } else {
+                Ordering::Equal
+            };
+            layers.then_with(|| {
+                self.order
+                    .as_ref()
+                    .map_or(Ordering::Equal, |NameOrder(cmp)| cmp(a, b))
+            })
+        });
+    }
+
+    /// Compute the processing rank of each layer, for [`MergeOrder::Layer`].
+    fn layer_ranks(&self) -> LayerRanks {
+        let mut ranks = LayerRanks::default();
+        let mut last_base = None;
+        // Synthetic fragments come right before the layers of their base directory.
+        for layer in self.search_layers() {
+            if !layer.imported && last_base != Some(layer.base) {
+                ranks.synthetic.push(ranks.end);
+                ranks.end += 1;
+                last_base = Some(layer.base);
+            }
+            ranks.dirs.push(ranks.end);
+            ranks.end += 1;
+        }
+        ranks
+    }
+
+    /// Scan configuration fragments and merge them into a single value.
+    ///
+    /// This works like [`scan_and_merge`](crate::scan_and_merge), with all the builder
+    /// settings applied, and also merges [synthetic](Self::inject) fragments, in
+    /// processing order (see [`scan_sorted`](Self::scan_sorted)). The error type must
+    /// be convertible from both `io::Error` and [`ScanError`].
+    pub fn scan_and_merge<T, E, F>(&self, mut merge: F) -> Result<T, E>
+    where
+        T: Default,
+        E: From<io::Error> + From<ScanError>,
+        F: FnMut(T, &OsStr, &mut dyn BufRead) -> Result<T, E>,
+    {
+        let mut sources: Vec<_> = self.scan_sources()?.into_iter().collect();
+        let ranks = self.layer_ranks();
+        self.sort(&mut sources, |name, source| match source {
+            Source::File(fragment) => ranks.of_fragment(fragment),
+            Source::Memory(contents) => self
+                .synthetic
+                .iter()
+                .find(|(_, n, c)| n == name && Arc::ptr_eq(c, contents))
+                .and_then(|(priority, ..)| ranks.synthetic.get(*priority).copied())
+                .unwrap_or(ranks.end),
+        });
+        let mut value = T::default();
+        let mut used = 0;
+        for (name, source) in sources {
+            match self.limits.next(used) {
+                Some(limit) if source.len() > limit.bytes() => {
+                    return Err(limit.error(name).into());
+                }
+                Some(limit) => {
+                    let mut reader = LimitedReader::new(source.open()?, limit, &name);
+                    value = merge(value, &name, &mut reader)?;
+                }
+                None => value = merge(value, &name, &mut source.open()?)?,
+            }
+            used += source.len();
+        }
+        Ok(value)
+    }
+
+    /// Scan all layers, returning their effective layout.
+    ///
+    /// Unlike fallible scans, this never fails: strictness checks are not applied, and
+    /// missing or unreadable directories are shown as such in the [`Layout`].
+    pub fn layout(&self) -> Layout {
+        let (sources, layers) = self.layer_entries();
+        Layout::new(layers, &self.synthetic, &sources)
+    }
+
+    /// Scan all layers, returning a structured [`ScanReport`].
+    ///
+    /// As for [`layout`](Self::layout), strictness checks are not applied.
+    pub fn report(&self) -> ScanReport {
+        let (sources, layers) = self.layer_entries();
+        ScanReport::new(layers, &sources)
+    }
+
+    /// Scan all layers, returning the winning sources and the entries of each layer.
+    fn layer_entries(&self) -> (BTreeMap<OsString, Source>, Vec<LayerEntries>) {
+        let (sources, statuses) = self.scan_layers();
+        // Skipped entries were already passed to the callback while scanning.
+        let options = DirOptions {
+            on_skipped: None,
+            ..self.options.clone()
+        };
+        let layers = self
+            .search_layers()
+            .into_iter()
+            .zip(statuses)
+            .map(|(layer, status)| {
+                let mut entries = Vec::new();
+                // Errors are already reported in the status.
+                if status.readable() {
+                    let _ = read_dir_entries(&StdFs, &layer.dir, &options, |name, entry| {
+                        entries.push((name, entry))
+                    });
+                }
+                entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                (status, self.policy_for(layer.base), entries)
+            })
+            .collect();
+        (sources, layers)
+    }
+
+    fn scan_sources_with_status(
+        &self,
+    ) -> Result<(BTreeMap<OsString, Source>, Vec<DirStatus>), ScanError> {
+        let (sources, statuses) = self.scan_layers();
+        self.check_scan(sources, statuses)
+    }
+
+    /// Apply strictness checks to the results of [`scan_layers`](Self::scan_layers).
+    pub(crate) fn check_scan(
+        &self,
+        sources: BTreeMap<OsString, Source>,
+        statuses: Vec<DirStatus>,
+    ) -> Result<(BTreeMap<OsString, Source>, Vec<DirStatus>), ScanError> {
+        let missing: Vec<_> = statuses
+            .iter()
+            .zip(self.search_layers())
+            .filter(|(status, layer)| {
+                let required = self.require_all_dirs_exist
+                    || self.required_dirs.iter().any(|d| d == layer.base);
+                !status.exists() && !layer.imported && required
+            })
+            .map(|(status, _)| status.path().to_path_buf())
+            .collect();
+        if !missing.is_empty() {
+            return Err(ScanError::MissingDirs(missing));
+        }
+        if self.options.strict {
+            let unreadable = statuses.iter().find_map(|status| match status.error() {
+                Some(kind) if status.exists() => Some((status.path(), kind)),
+                _ => None,
+            });
+            if let Some((path, kind)) = unreadable {
+                return Err(ScanError::Io {
+                    path: path.to_path_buf(),
+                    source: io::Error::from(kind),
+                });
+            }
+        }
+        if self.fail_on_rejection {
+            if let Some(rejection) = statuses.iter().flat_map(DirStatus::rejected).next() {
+                return Err(ScanError::Rejected(rejection.clone()));
+            }
+        }
+        if self.options.symlinks == SymlinkPolicy::Error {
+            if let Some(path) = statuses.iter().flat_map(DirStatus::symlinks).next() {
+                return Err(ScanError::Symlink(path.clone()));
+            }
+        }
+        if self.require_nonempty && sources.is_empty() {
+            return Err(ScanError::NoFragments);
+        }
+        Ok((sources, statuses))
+    }
+}
+
+/// Keep only the fragment files among `sources`.
+pub(crate) fn files_only(sources: BTreeMap<OsString, Source>) -> BTreeMap<OsString, Fragment> {
+    sources
+        .into_iter()
+        .filter_map(|(name, source)| match source {
+            Source::File(fragment) => Some((name, fragment)),
+            Source::Memory(_) => None,
+        })
+        .collect()
+}
+
+/// A shareable check over fragment contents, returning the reason for rejecting them.
+type ContentCheck = dyn Fn(&Path, &[u8]) -> Result<(), String> + Send + Sync;
+
+/// An entry of a scanned layer, by name.
+type SourceEntry = (OsString, Entry<Source>);
+
+/// A fragment validator, see [`Overdrop::validator`].
+#[derive(Clone)]
+struct Validator(Arc<ContentCheck>);
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Validator(..)")
+    }
+}
+
+impl Validator {
+    /// Validate the fragment `name`, reading at most `limit`, returning its rejection if any.
+    fn check(
+        &self,
+        name: &OsStr,
+        fragment: &Fragment,
+        limit: Option<SizeLimit>,
+    ) -> Option<Rejection> {
+        let path = fragment.path();
+        let res = match read_limited(name, fragment, limit) {
+            Ok(contents) => (self.0)(path, &contents),
+            Err(e) => Err(e.to_string()),
+        };
+        let reason = res.err()?;
+        warn!("Rejected config file '{}': {}", path.display(), reason);
+        Some(Rejection::new(path.to_path_buf(), reason))
+    }
+}
+
+/// Read all the contents of `fragment`, failing if they go over `limit`.
+fn read_limited(
+    name: &OsStr,
+    fragment: &Fragment,
+    limit: Option<SizeLimit>,
+) -> io::Result<Vec<u8>> {
+    let mut reader: Box<dyn BufRead> = Box::new(BufReader::new(fragment.open()?));
+    if let Some(limit) = limit {
+        if fragment.len() > limit.bytes() {
+            let error = limit.error(name);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+        reader = Box::new(LimitedReader::new(reader, limit, name));
+    }
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// A shareable comparison of fragment names.
+type NameCmp = dyn Fn(&OsStr, &OsStr) -> Ordering + Send + Sync;
+
+/// A fragment processing order, see [`Overdrop::sort_by`].
+#[derive(Clone)]
+struct NameOrder(Arc<NameCmp>);
+
+impl fmt::Debug for NameOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NameOrder(..)")
+    }
+}
+
+/// Processing ranks of layers, see [`Overdrop::layer_ranks`].
+#[derive(Default)]
+struct LayerRanks {
+    /// Rank of each scanned directory, by index.
+    dirs: Vec<usize>,
+    /// Rank of synthetic fragments, by priority.
+    synthetic: Vec<usize>,
+    /// Rank after all layers.
+    end: usize,
+}
+
+impl LayerRanks {
+    fn of_fragment(&self, fragment: &Fragment) -> usize {
+        self.dirs
+            .get(fragment.search_layer())
+            .copied()
+            .unwrap_or(self.end)
+    }
+}
+
+/// A directory to scan, under one of the base directories.
+struct SearchLayer<'a> {
+    base: &'a Path,
+    dir: PathBuf,
+    /// Whether the directory belongs to an imported shared path.
+    imported: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TreeBuilder;
+
+    const TREEDIR: &str = "tests/fixtures/tree-basic";
+
+    fn dirs() -> Vec<PathBuf> {
+        ["usr/lib", "run", "etc"]
+            .iter()
+            .map(|d| Path::new(TREEDIR).join(d))
+            .collect()
+    }
+
+    #[test]
+    fn builder_matches_scan() {
+        let fragments = Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs())
+            .allowed_extensions(&["toml"])
+            .scan()
+            .unwrap();
+        let expected = crate::scan(dirs(), "liboverdrop.d", &["toml"], false);
+        assert_eq!(fragments, expected);
+
+        let fragments = Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs())
+            .ignore_dotfiles(true)
+            .scan()
+            .unwrap();
+        let expected = crate::scan::<_, _, _, &str>(dirs(), "liboverdrop.d", &[], true);
+        assert_eq!(fragments, expected);
+    }
+
+    /// Error type for merges, which need conversions from both I/O and scan errors.
+    #[derive(Debug)]
+    enum Error {
+        Io(io::Error),
+        Scan(ScanError),
+    }
+
+    impl From<io::Error> for Error {
+        fn from(e: io::Error) -> Self {
+            Error::Io(e)
+        }
+    }
+
+    impl From<ScanError> for Error {
+        fn from(e: ScanError) -> Self {
+            Error::Scan(e)
+        }
+    }
+
+    #[test]
+    fn builder_scan_and_merge() {
+        let bases =
+            ["usr/lib", "etc", "run"].map(|d| Path::new("tests/fixtures/tree-merge").join(d));
+        let merged: BTreeMap<String, String> = Overdrop::new("liboverdrop.d")
+            .base_dirs(&bases)
+            .inject("35-runtime.conf", "retries=7\n", 3)
+            .scan_and_merge(|mut values, _, reader| {
+                crate::merge_key_values(&mut values, reader, false)?;
+                Ok::<_, Error>(values)
+            })
+            .unwrap();
+        let merged: Vec<_> = merged
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            merged,
+            [("name", "runtime"), ("retries", "7"), ("verbose", "true")]
+        );
+
+        let res = Overdrop::new("liboverdrop.d")
+            .base_dirs([Path::new("tests/fixtures/missing")])
+            .require_all_dirs_exist(true)
+            .scan_and_merge(|(), _, _| Ok::<_, Error>(()));
+        assert!(matches!(res, Err(Error::Scan(ScanError::MissingDirs(_)))));
+        let res = Overdrop::new("liboverdrop.d")
+            .base_dirs(&bases)
+            .scan_and_merge(|(), _, _| Err(Error::Io(io::ErrorKind::Other.into())));
+        assert!(matches!(res, Err(Error::Io(e)) if e.kind() == io::ErrorKind::Other));
+    }
+
+    #[test]
+    fn size_limits() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "a=1\n")
+            .fragment("etc/svc.d/20-b.conf", "b=22\n");
+        let scanner = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc"]))
+            .inject("15-synthetic.conf", "s=1\n", 1);
+        let merge = |scanner: Overdrop| {
+            scanner.scan_and_merge(|mut values, _, reader| {
+                crate::merge_key_values(&mut values, reader, false)?;
+                Ok::<_, Error>(values)
+            })
+        };
+        assert_eq!(
+            merge(scanner.clone().max_fragment_size(5)).unwrap().len(),
+            3
+        );
+        assert_eq!(merge(scanner.clone().max_total_size(13)).unwrap().len(), 3);
+
+        let res = merge(scanner.clone().max_fragment_size(4));
+        assert!(matches!(
+            res,
+            Err(Error::Scan(ScanError::FragmentTooLarge { name, limit: 4 })) if name == "20-b.conf"
+        ));
+        let res = merge(scanner.max_fragment_size(5).max_total_size(12));
+        assert!(matches!(
+            res,
+            Err(Error::Scan(ScanError::TotalTooLarge { name, limit: 12 })) if name == "20-b.conf"
+        ));
+
+        // Fragments growing after the scan fail while being read.
+        let source = Source::Memory(Arc::from(&b"a=1\nb=2\n"[..]));
+        let name = OsStr::new("10-a.conf");
+        let mut reader = LimitedReader::new(source.open().unwrap(), SizeLimit::Fragment(4), name);
+        let err = io::read_to_string(&mut reader).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(
+            err.into_inner().unwrap().downcast::<ScanError>().as_deref(),
+            Ok(ScanError::FragmentTooLarge { limit: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn recursive_scan() {
+        let treedir = Path::new("tests/fixtures/tree-recursive");
+        let bases = ["usr/lib", "etc"].map(|d| treedir.join(d));
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs(&bases);
+        let scan = |scanner: Overdrop| -> Vec<_> {
+            let fragments = scanner.scan().unwrap();
+            fragments
+                .into_iter()
+                .map(|(name, path)| (name, path.strip_prefix(treedir).unwrap().to_path_buf()))
+                .collect()
+        };
+        assert_eq!(scan(scanner.clone()).len(), 1);
+
+        let network = Path::new("network");
+        let fragments = scan(scanner.clone().max_depth(1));
+        assert_eq!(
+            fragments,
+            [
+                (
+                    "00-top.toml".into(),
+                    "usr/lib/liboverdrop.d/00-top.toml".into()
+                ),
+                (
+                    network.join("10-dhcp.toml").into(),
+                    "etc/liboverdrop.d/network/10-dhcp.toml".into()
+                ),
+                (
+                    network.join("20-dns.toml").into(),
+                    "usr/lib/liboverdrop.d/network/20-dns.toml".into()
+                ),
+            ]
+        );
+
+        let fragments = scan(scanner.max_depth(2));
+        assert_eq!(fragments.len(), 4);
+        assert_eq!(fragments[3].0, network.join("deep/30-deep.toml"));
+    }
+
+    #[test]
+    fn multiple_shared_paths() {
+        let bases = ["usr/lib", "etc"].map(|d| Path::new("tests/fixtures/tree-shared").join(d));
+        let shared_paths = ["myapp/conf.d", "myapp/config.d"];
+        let scanner = Overdrop::new("myapp/config.d")
+            .base_dirs(&bases)
+            .shared_paths(shared_paths);
+        assert_eq!(
+            scanner.scan().unwrap(),
+            crate::scan_shared_paths(&bases, &shared_paths, &[] as &[&str], false)
+        );
+        assert_eq!(
+            scanner.search_dirs(),
+            [
+                bases[0].join("myapp/conf.d"),
+                bases[0].join("myapp/config.d"),
+                bases[1].join("myapp/conf.d"),
+                bases[1].join("myapp/config.d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn name_patterns() {
+        let patterns = ["0[1-4]-*", "config.*"].map(|p| p.parse().unwrap());
+        let fragments = Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs())
+            .name_patterns(patterns)
+            .scan()
+            .unwrap();
+        assert_eq!(
+            fragments.keys().collect::<Vec<_>>(),
+            [
+                "01-config-a.toml",
+                "02-config-b.toml",
+                "03-config-c.toml",
+                "04-config-d.toml",
+                "config.conf"
+            ]
+        );
+
+        let fragments = Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs())
+            .allowed_extensions(&["toml"])
+            .name_patterns(["*-config-[a-c].*".parse().unwrap()])
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.len(), 3);
+    }
+
+    #[test]
+    fn exclude_patterns() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .fragment("usr/lib/svc.d/README", "")
+            .fragment("etc/svc.d/10-a.conf.rpmsave", "old")
+            .mask("etc/svc.d/placeholder.conf")
+            .fragment("run/svc.d/placeholder.conf", "");
+        let fragments = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc", "run"]))
+            .exclude_patterns(
+                ["README", "placeholder.conf", "*.rpmsave"].map(|p| p.parse().unwrap()),
+            )
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.keys().collect::<Vec<_>>(), ["10-a.conf"]);
+    }
+
+    #[test]
+    fn name_prefix() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-networkd-a.conf", "vendor")
+            .fragment("usr/lib/svc.d/10-resolved-a.conf", "vendor")
+            .fragment("etc/svc.d/10-networkd-a.conf", "admin")
+            .mask("etc/svc.d/10-networkd-b.conf")
+            .fragment("etc/svc.d/20-networkd-c.conf", "admin");
+        let fragments = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc"]))
+            .name_prefix("10-networkd-")
+            .scan()
+            .unwrap();
+        assert_eq!(
+            fragments.into_iter().collect::<Vec<_>>(),
+            [(
+                "10-networkd-a.conf".into(),
+                tree.path("etc/svc.d/10-networkd-a.conf")
+            )]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skipped_entries() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .fragment("usr/lib/svc.d/10-a.conf.txt", "")
+            .fragment("usr/lib/svc.d/.20-hidden.conf", "")
+            .fragment("usr/lib/svc.d/README", "")
+            .fragment("usr/lib/svc.d/30-big.conf", "x".repeat(64))
+            .fragment("usr/lib/svc.d/40-dir.conf/nested", "")
+            .fragment("usr/lib/svc.d/50-rejected.conf", "invalid")
+            .fragment("etc/svc.d", "not a directory");
+        let skipped = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let collected = Arc::clone(&skipped);
+        let fragments = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc", "missing"]))
+            .allowed_extensions(&["conf"])
+            .ignore_dotfiles(true)
+            .exclude_patterns(["README".parse().unwrap()])
+            .filter(|_, meta| meta.len() != 64)
+            .validator(|_, contents| match contents {
+                b"invalid" => Err("invalid contents".into()),
+                _ => Ok(()),
+            })
+            .on_skipped(move |entry| collected.lock().unwrap().push(entry.clone()))
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.keys().collect::<Vec<_>>(), ["10-a.conf"]);
+
+        let mut skipped: Vec<_> = skipped
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                let path = entry.path().strip_prefix(tree.root()).unwrap();
+                (path.to_str().unwrap().to_string(), entry.reason().clone())
+            })
+            .collect();
+        skipped.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let expected = [
+            (
+                "etc/svc.d",
+                SkipReason::Unreadable(io::Error::from_raw_os_error(20).kind()),
+            ),
+            ("usr/lib/svc.d/.20-hidden.conf", SkipReason::Hidden),
+            (
+                "usr/lib/svc.d/10-a.conf.txt",
+                SkipReason::UnmatchedExtension,
+            ),
+            ("usr/lib/svc.d/30-big.conf", SkipReason::Filtered),
+            ("usr/lib/svc.d/40-dir.conf", SkipReason::NotAFile),
+            (
+                "usr/lib/svc.d/50-rejected.conf",
+                SkipReason::Rejected("invalid contents".into()),
+            ),
+            ("usr/lib/svc.d/README", SkipReason::UnmatchedExtension),
+        ];
+        assert_eq!(
+            skipped,
+            expected.map(|(path, reason)| (path.to_string(), reason))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn strict_unreadable_dirs() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .fragment("etc/svc.d", "not a directory")
+            .symlink("run/svc.d", "svc.d");
+        let scanner =
+            Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "missing", "etc"]));
+        assert_eq!(scanner.scan().unwrap().len(), 1);
+        let err = scanner.clone().strict(true).scan().unwrap_err();
+        assert!(
+            matches!(&err, ScanError::Io { path, .. } if *path == tree.path("etc/svc.d")),
+            "{}",
+            err
+        );
+
+        // Symlink loops are errors too.
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "run"]));
+        assert_eq!(scanner.scan().unwrap().len(), 1);
+        assert!(scanner.strict(true).scan().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dedupe_files() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "name=a\n")
+            .fragment("usr/lib/svc.d/30-c.conf", "name=c\n")
+            .base_dir("etc/svc.d")
+            .symlink(
+                "run/svc.d/40-symlinked.conf",
+                "../../usr/lib/svc.d/10-a.conf",
+            );
+        fs::hard_link(
+            tree.path("usr/lib/svc.d/10-a.conf"),
+            tree.path("etc/svc.d/20-linked.conf"),
+        )
+        .unwrap();
+        let scanner = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc", "run"]))
+            .symlinks(SymlinkPolicy::Follow);
+        assert_eq!(scanner.scan().unwrap().len(), 4);
+        let scanner = scanner.dedupe_files(true);
+        let mut fragments = scanner.scan_fragments().unwrap();
+        assert_eq!(
+            fragments.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["10-a.conf", "30-c.conf"]
+        );
+
+        // New links to the same files are deduplicated on partial rescans too.
+        let linked = tree.path("etc/svc.d/50-linked.conf");
+        fs::hard_link(tree.path("usr/lib/svc.d/30-c.conf"), &linked).unwrap();
+        assert!(scanner.rescan_changed(&mut fragments, [&linked]).is_empty());
+        assert_eq!(fragments, scanner.scan_fragments().unwrap());
+    }
+
+    #[test]
+    fn entry_filter() {
+        let bases =
+            ["usr/lib", "etc", "run"].map(|d| Path::new("tests/fixtures/tree-merge").join(d));
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs(&bases);
+
+        let fragments = scanner
+            .clone()
+            .filter(|_, meta| meta.len() < 20)
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.len(), 3);
+        assert!(!fragments.contains_key(OsStr::new("10-base.conf")));
+
+        // Filtered out fragments do not override lower-priority ones.
+        let etc = bases[1].clone();
+        let fragments = scanner
+            .filter(move |path, _| !path.starts_with(&etc))
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(
+            fragments[OsStr::new("20-extra.conf")],
+            bases[0].join("liboverdrop.d/20-extra.conf")
+        );
+    }
+
+    #[test]
+    fn custom_keys() {
+        let treedir = Path::new("tests/fixtures/tree-keys");
+        let bases = ["usr/lib", "etc"].map(|d| treedir.join(d));
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs(&bases);
+        assert_eq!(scanner.scan().unwrap().len(), 8);
+
+        let fragments = scanner
+            .key_fn(|name| Path::new(name).file_stem().unwrap_or(name).to_os_string())
+            .scan()
+            .unwrap();
+        let expected: BTreeMap<OsString, PathBuf> = [
+            ("10-foo".into(), bases[0].join("liboverdrop.d/10-foo.toml")),
+            ("20-bar".into(), bases[1].join("liboverdrop.d/20-bar.json")),
+            ("custom".into(), bases[1].join("liboverdrop.d/custom.toml")),
+            ("other".into(), bases[0].join("liboverdrop.d/other.toml")),
+        ]
+        .into();
+        assert_eq!(fragments, expected);
+    }
+
+    #[test]
+    fn extension_priority() {
+        let treedir = Path::new("tests/fixtures/tree-keys");
+        let bases = ["usr/lib", "etc"].map(|d| treedir.join(d));
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs(&bases);
+        let scan = |scanner: Overdrop| -> Vec<_> {
+            let fragments = scanner.scan().unwrap();
+            fragments
+                .into_iter()
+                .map(|(name, path)| (name, path.strip_prefix(treedir).unwrap().to_path_buf()))
+                .collect()
+        };
+
+        let fragments = scan(scanner.clone().extension_priority(&["json", ".toml"]));
+        assert_eq!(
+            fragments,
+            [
+                ("10-foo".into(), "usr/lib/liboverdrop.d/10-foo.json".into()),
+                ("20-bar".into(), "etc/liboverdrop.d/20-bar.json".into()),
+                ("custom".into(), "etc/liboverdrop.d/custom.toml".into()),
+                (
+                    "custom.yaml".into(),
+                    "usr/lib/liboverdrop.d/custom.yaml".into()
+                ),
+                ("other".into(), "usr/lib/liboverdrop.d/other.json".into()),
+            ]
+        );
+
+        let fragments = scan(scanner.extension_priority(&["toml", "json"]));
+        assert_eq!(
+            fragments[0].1,
+            Path::new("usr/lib/liboverdrop.d/10-foo.toml")
+        );
+        assert_eq!(fragments[1].1, Path::new("etc/liboverdrop.d/20-bar.json"));
+    }
+
+    #[test]
+    fn version_sorting() {
+        let mut tree = TreeBuilder::new();
+        for name in ["10-b.conf", "2-a.conf", "1.conf"] {
+            tree = tree.fragment(Path::new("liboverdrop.d").join(name), name);
+        }
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs([tree.root()]);
+        let names = |scanner: &Overdrop| -> Vec<_> {
+            let fragments = scanner.scan_sorted().unwrap();
+            fragments.into_iter().map(|(name, _)| name).collect()
+        };
+        assert_eq!(names(&scanner), ["1.conf", "10-b.conf", "2-a.conf"]);
+
+        let scanner = scanner.version_sort(true);
+        assert_eq!(names(&scanner), ["1.conf", "2-a.conf", "10-b.conf"]);
+        let merged = scanner.scan_and_merge(|mut merged: String, _, reader| {
+            reader.read_to_string(&mut merged)?;
+            Ok::<_, Error>(merged)
+        });
+        assert_eq!(merged.unwrap(), "1.conf2-a.conf10-b.conf");
+
+        // Custom orders are stable.
+        let by_len = scanner.clone().sort_by(|a, b| a.len().cmp(&b.len()));
+        assert_eq!(names(&by_len), ["1.conf", "2-a.conf", "10-b.conf"]);
+        let reversed = scanner.sort_by_key(|name| std::cmp::Reverse(name.to_os_string()));
+        assert_eq!(names(&reversed), ["2-a.conf", "10-b.conf", "1.conf"]);
+    }
+
+    #[test]
+    fn layer_merge_order() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor a\n")
+            .fragment("usr/lib/svc.d/30-c.conf", "vendor c\n")
+            .fragment("etc/svc.d/20-b.conf", "admin b\n")
+            .fragment("etc/svc.d/40-d.conf", "admin d\n");
+        let scanner = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc"]))
+            .inject("15-synthetic.conf", "synthetic\n", 1);
+        let merge = |scanner: &Overdrop| {
+            let merged = scanner.scan_and_merge(|mut merged: String, _, reader| {
+                reader.read_to_string(&mut merged)?;
+                Ok::<_, Error>(merged)
+            });
+            merged.unwrap().lines().collect::<Vec<_>>().join(", ")
+        };
+        let names = |scanner: &Overdrop| -> Vec<_> {
+            let fragments = scanner.scan_sorted().unwrap();
+            fragments.into_iter().map(|(name, _)| name).collect()
+        };
+
+        assert_eq!(
+            merge(&scanner),
+            "vendor a, synthetic, admin b, vendor c, admin d"
+        );
+        assert_eq!(
+            names(&scanner),
+            ["10-a.conf", "20-b.conf", "30-c.conf", "40-d.conf"]
+        );
+
+        let by_layer = scanner.merge_order(MergeOrder::Layer);
+        assert_eq!(
+            merge(&by_layer),
+            "vendor a, vendor c, synthetic, admin b, admin d"
+        );
+        assert_eq!(
+            names(&by_layer),
+            ["10-a.conf", "30-c.conf", "20-b.conf", "40-d.conf"]
+        );
+        assert_eq!(
+            by_layer.effective_paths().unwrap(),
+            [
+                "usr/lib/svc.d/10-a.conf",
+                "usr/lib/svc.d/30-c.conf",
+                "etc/svc.d/20-b.conf",
+                "etc/svc.d/40-d.conf",
+            ]
+            .map(|path| tree.path(path))
+        );
+        let reversed = by_layer.sort_by_key(|name| std::cmp::Reverse(name.to_os_string()));
+        assert_eq!(
+            merge(&reversed),
+            "vendor c, vendor a, synthetic, admin d, admin b"
+        );
+    }
+
+    #[test]
+    fn layer_merge_order_keyed() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.toml", "vendor a\n")
+            .fragment("usr/lib/svc.d/30-c.json", "vendor c\n")
+            .fragment("etc/svc.d/20-b.json", "admin b\n");
+        let scanner = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc"]))
+            .extension_priority(&["toml", "json"])
+            .merge_order(MergeOrder::Layer);
+        assert_eq!(
+            scanner.effective_paths().unwrap(),
+            [
+                "usr/lib/svc.d/10-a.toml",
+                "usr/lib/svc.d/30-c.json",
+                "etc/svc.d/20-b.json",
+            ]
+            .map(|path| tree.path(path))
+        );
+        let merged = scanner.scan_and_merge(|mut merged: String, _, reader| {
+            reader.read_to_string(&mut merged)?;
+            Ok::<_, Error>(merged)
+        });
+        assert_eq!(merged.unwrap(), "vendor a\nvendor c\nadmin b\n");
+    }
+
+    #[test]
+    fn open_fragments() {
+        use std::io::Read;
+
+        let tree = TreeBuilder::new().fragment("liboverdrop.d/10-a.conf", "scanned");
+        let dir = tree.path("liboverdrop.d");
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs([tree.root()]);
+
+        let mut opened = scanner.scan_open().unwrap();
+        let (path, file) = opened.get_mut(OsStr::new("10-a.conf")).unwrap();
+        assert_eq!(*path, dir.join("10-a.conf"));
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "scanned");
+
+        // Replacing a scanned fragment is detected.
+        let fragments = scanner.scan_fragments().unwrap();
+        fs::write(tree.path("other.conf"), "attacker").unwrap();
+        fs::rename(tree.path("other.conf"), dir.join("10-a.conf")).unwrap();
+        let fragment = fragments.get("10-a.conf").unwrap();
+        if cfg!(unix) {
+            assert!(fragment.open().is_err());
+        }
+        let fragments = scanner.scan_fragments().unwrap();
+        assert!(fragments.get("10-a.conf").unwrap().open().is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn open_symlinked_fragments() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .fragment("usr/lib/svc.d/20-b.conf", "vendor")
+            .fragment("data/c.conf", "data")
+            .symlink("etc/svc.d/30-c.conf", "../../data/c.conf");
+        let scanner = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc"]))
+            .symlinks(SymlinkPolicy::Follow);
+        let fragments = scanner.scan_fragments().unwrap();
+        assert!(fragments.get("30-c.conf").unwrap().open().is_ok());
+
+        // A symlink to the scanned file, or a FIFO, is not opened instead of it.
+        let dir = tree.path("usr/lib/svc.d");
+        fs::rename(dir.join("10-a.conf"), tree.path("data/a.conf")).unwrap();
+        std::os::unix::fs::symlink("../../../data/a.conf", dir.join("10-a.conf")).unwrap();
+        assert!(fragments.get("10-a.conf").unwrap().open().is_err());
+        let fifo = tree.path("data/fifo");
+        let status = std::process::Command::new("mkfifo").arg(&fifo).status();
+        if status.map_or(false, |status| status.success()) {
+            fs::rename(&fifo, dir.join("20-b.conf")).unwrap();
+            assert!(fragments.get("20-b.conf").unwrap().open().is_err());
+        }
+    }
+
+    #[test]
+    fn hidden_files_policy() {
+        let fragments = Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs())
+            .hidden_files(|name| name == "noextension" || name == "config.conf")
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.len(), 8);
+        assert!(fragments.contains_key(OsStr::new(".hidden.conf")));
+        assert!(!fragments.contains_key(OsStr::new("noextension")));
+
+        let fragments = Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs())
+            .ignore_dotfiles(true)
+            .hidden_files(|name| name == "noextension")
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.len(), 8);
+        assert!(!fragments.contains_key(OsStr::new(".hidden.conf")));
+        assert!(fragments.contains_key(OsStr::new("config.conf")));
+    }
+
+    #[test]
+    fn dir_status() {
+        let mut base_dirs = dirs();
+        base_dirs.push(Path::new(TREEDIR).join("missing"));
+        base_dirs.push(Path::new(TREEDIR).join("etc/liboverdrop.d/config.conf"));
+        let (fragments, statuses) = Overdrop::new("")
+            .base_dirs(&base_dirs)
+            .allowed_extensions(&["toml"])
+            .scan_with_status()
+            .unwrap();
+        assert_eq!(fragments.len(), 0);
+        assert_eq!(statuses.len(), 5);
+        assert!(statuses[..3].iter().all(|s| s.exists() && s.matched() == 0));
+
+        let (fragments, statuses) = Overdrop::new("liboverdrop.d")
+            .base_dirs(&base_dirs)
+            .allowed_extensions(&["toml"])
+            .scan_with_status()
+            .unwrap();
+        assert_eq!(fragments.len(), 7);
+        let matched: Vec<_> = statuses.iter().map(DirStatus::matched).collect();
+        assert_eq!(matched, [4, 4, 4, 0, 0]);
+        assert!(statuses[..3].iter().all(|s| s.exists() && s.readable()));
+        assert_eq!(statuses[0].path(), base_dirs[0].join("liboverdrop.d"));
+
+        let missing = &statuses[3];
+        assert!(!missing.exists());
+        assert!(!missing.readable());
+        assert_eq!(missing.error(), Some(io::ErrorKind::NotFound));
+
+        // A path under a regular file exists as far as the service is concerned,
+        // but cannot be read as a directory.
+        let not_dir = &statuses[4];
+        assert!(!not_dir.readable());
+        assert!(not_dir.error().is_some());
+    }
+
+    fn copy_tree(src: &Path, dst: &Path) {
+        fs::create_dir_all(dst).unwrap();
+        for entry in fs::read_dir(src).unwrap() {
+            let entry = entry.unwrap();
+            let target = dst.join(entry.file_name());
+            if entry.file_type().unwrap().is_dir() {
+                copy_tree(&entry.path(), &target);
+            } else {
+                fs::copy(entry.path(), target).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn rescan_changed() {
+        let root = std::env::temp_dir().join(format!("liboverdrop-rescan-{}", std::process::id()));
+        copy_tree(Path::new("tests/fixtures/tree-merge"), &root);
+        let dirs = ["usr/lib", "etc", "run"].map(|d| root.join(d).join("liboverdrop.d"));
+        let scanner = Overdrop::new("liboverdrop.d")
+            .base_dirs(["usr/lib", "etc", "run"].map(|d| root.join(d)))
+            .allowed_extensions(&["conf"]);
+        let mut fragments = scanner.scan_fragments().unwrap();
+
+        // Removing an override falls back to the lower-priority fragment.
+        fs::remove_file(dirs[1].join("20-extra.conf")).unwrap();
+        // New fragments are picked up, and filtered out ones ignored.
+        fs::write(dirs[0].join("50-new.conf"), "a=1\n").unwrap();
+        fs::write(dirs[0].join("50-new.txt"), "a=1\n").unwrap();
+        let changed = scanner.rescan_changed(
+            &mut fragments,
+            [
+                dirs[1].join("20-extra.conf"),
+                dirs[0].join("50-new.conf"),
+                dirs[0].join("50-new.txt"),
+                dirs[0].join("10-base.conf"),
+                root.join("elsewhere.conf"),
+            ],
+        );
+        assert_eq!(changed, ["20-extra.conf", "50-new.conf"]);
+        assert_eq!(fragments, scanner.scan_fragments().unwrap());
+        assert_eq!(
+            fragments.get("20-extra.conf").unwrap().path(),
+            dirs[0].join("20-extra.conf")
+        );
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(crate::DEVNULL, dirs[2].join("10-base.conf")).unwrap();
+            let changed = scanner.rescan_changed(&mut fragments, [dirs[2].join("10-base.conf")]);
+            assert_eq!(changed, ["10-base.conf"]);
+            assert!(fragments.get("10-base.conf").is_none());
+        }
+
+        // Directory-level changes trigger a full rescan.
+        fs::remove_dir_all(&dirs[2]).unwrap();
+        let changed = scanner.rescan_changed(&mut fragments, [&dirs[2]]);
+        assert!(changed.contains(&OsString::from("40-runtime.conf")));
+        assert_eq!(fragments, scanner.scan_fragments().unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rescan_changed_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/a.d/10-x.conf", "vendor")
+            .fragment("usr/lib/a.d/20-y.conf", "vendor")
+            .fragment("data/x.conf", "linked")
+            .base_dir("etc/a.d");
+        let scanner = Overdrop::new("a.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc"]))
+            .symlinks(SymlinkPolicy::Follow)
+            .broken_symlinks_mask(true);
+        let mut fragments = scanner.scan_fragments().unwrap();
+
+        let (link, broken) = (
+            tree.path("etc/a.d/10-x.conf"),
+            tree.path("etc/a.d/20-y.conf"),
+        );
+        symlink(tree.path("data/x.conf"), &link).unwrap();
+        symlink(tree.path("data/missing.conf"), &broken).unwrap();
+        let changed = scanner.rescan_changed(&mut fragments, [&link, &broken]);
+        assert_eq!(changed, ["10-x.conf", "20-y.conf"]);
+        assert_eq!(fragments, scanner.scan_fragments().unwrap());
+        assert_eq!(fragments.get("10-x.conf").unwrap().path(), link);
+    }
+
+    #[test]
+    fn report_unmatched() {
+        let scanner = Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs())
+            .allowed_extensions(&["toml"])
+            .ignore_dotfiles(true);
+        let (_, statuses) = scanner.scan_with_status().unwrap();
+        assert!(statuses.iter().all(|s| s.unmatched().next().is_none()));
+
+        let (fragments, statuses) = scanner.report_unmatched(true).scan_with_status().unwrap();
+        assert_eq!(fragments.len(), 7);
+        let mut unmatched: Vec<_> = statuses[2].unmatched().collect();
+        unmatched.sort();
+        assert_eq!(unmatched, ["config.conf", "noextension"]);
+        assert_eq!(statuses[0].unmatched().count(), 0);
+    }
+
+    #[test]
+    fn require_all_dirs_exist() {
+        let mut base_dirs = dirs();
+        base_dirs.push(Path::new(TREEDIR).join("missing"));
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs(&base_dirs);
+        assert_eq!(scanner.scan().unwrap().len(), 10);
+
+        let err = scanner.require_all_dirs_exist(true).scan().unwrap_err();
+        match err {
+            ScanError::MissingDirs(dirs) => {
+                assert_eq!(dirs, [Path::new(TREEDIR).join("missing/liboverdrop.d")])
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+
+        Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs())
+            .require_all_dirs_exist(true)
+            .scan()
+            .unwrap();
+    }
+
+    #[test]
+    fn required_layers() {
+        let spec = format!("{0}/usr/lib:{0}/missing:!{0}/etc", TREEDIR);
+        let scanner = Overdrop::new("liboverdrop.d").layers(&spec.parse().unwrap());
+        assert_eq!(scanner.search_dirs().len(), 3);
+        assert_eq!(scanner.scan().unwrap().len(), 9);
+
+        let spec = format!("{0}/usr/lib:!{0}/missing:{0}/etc", TREEDIR);
+        let err = Overdrop::new("liboverdrop.d")
+            .layers(&spec.parse().unwrap())
+            .scan()
+            .unwrap_err();
+        match err {
+            ScanError::MissingDirs(dirs) => {
+                assert_eq!(dirs, [Path::new(TREEDIR).join("missing/liboverdrop.d")])
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn layer_policies() {
+        let treedir = Path::new("tests/fixtures/tree-mask");
+        let bases = ["usr/lib", "etc", "run"].map(|d| treedir.join(d));
+        let scan = |scanner: Overdrop| -> Vec<_> {
+            let fragments = scanner.scan().unwrap();
+            fragments
+                .into_iter()
+                .map(|(name, path)| (name, path.strip_prefix(treedir).unwrap().to_path_buf()))
+                .collect()
+        };
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs(&bases);
+        assert_eq!(
+            scanner
+                .clone()
+                .layer_policy(&bases[0], LayerPolicy::new())
+                .scan()
+                .unwrap(),
+            scanner.scan().unwrap()
+        );
+
+        // Only /run may mask fragments.
+        let fragments = scan(
+            scanner
+                .clone()
+                .layer_policy(&bases[1], LayerPolicy::new().masks(false)),
+        );
+        assert_eq!(
+            fragments,
+            [
+                ("10-a.conf".into(), "run/liboverdrop.d/10-a.conf".into()),
+                ("20-b.conf".into(), "usr/lib/liboverdrop.d/20-b.conf".into()),
+                ("30-c.conf".into(), "usr/lib/liboverdrop.d/30-c.conf".into()),
+            ]
+        );
+
+        // Vendor fragments can be neither masked nor overridden.
+        let policy = LayerPolicy::new().maskable(false).overridable(false);
+        let fragments = scan(scanner.clone().layer_policy(&bases[0], policy));
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(fragments[0].1, Path::new("usr/lib/liboverdrop.d/10-a.conf"));
+        assert_eq!(fragments[1].1, Path::new("usr/lib/liboverdrop.d/20-b.conf"));
+
+        // Empty files only mask if explicitly enabled.
+        let treedir = Path::new("tests/fixtures/tree-policy");
+        let bases = ["usr/lib", "etc"].map(|d| treedir.join(d));
+        let scanner = Overdrop::new("liboverdrop.d").base_dirs(&bases);
+        let fragments = scanner.scan().unwrap();
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(
+            fragments[OsStr::new("10-a.conf")],
+            bases[1].join("liboverdrop.d/10-a.conf")
+        );
+        let policy = LayerPolicy::new().empty_file_masks(true);
+        let fragments = scanner
+            .clone()
+            .layer_policy(&bases[1], policy)
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.keys().collect::<Vec<_>>(), ["20-b.conf"]);
+        let fragments = scanner.clone().empty_file_masks(true).scan().unwrap();
+        assert_eq!(fragments.keys().collect::<Vec<_>>(), ["20-b.conf"]);
+        let policy = LayerPolicy::new().empty_file_masks(true).masks(false);
+        let fragments = scanner
+            .empty_file_masks(true)
+            .layer_policy(&bases[1], policy)
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.len(), 2);
+    }
+
+    #[test]
+    fn alternate_root() {
+        let treedir = Path::new("tests/fixtures/tree-mask");
+        let bases = ["usr/lib", "etc", "run"].map(|d| treedir.join(d));
+        let scanner = Overdrop::new("liboverdrop.d").root(treedir);
+        assert_eq!(
+            scanner.search_dirs()[1],
+            treedir.join("usr/local/lib/liboverdrop.d")
+        );
+        let expected = Overdrop::new("liboverdrop.d").base_dirs(&bases);
+        assert_eq!(scanner.scan().unwrap(), expected.scan().unwrap());
+
+        // Policies apply to base directories as given.
+        let policy = LayerPolicy::new().masks(false);
+        let fragments = scanner.layer_policy("/etc", policy).scan().unwrap();
+        let expected = expected.layer_policy(&bases[1], policy).scan().unwrap();
+        assert_eq!(fragments, expected);
+        assert_eq!(fragments.len(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn alternate_root_masks() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .fragment("usr/lib/svc.d/20-b.conf", "vendor")
+            .fragment("usr/lib/svc.d/30-c.conf", "vendor")
+            .symlink("usr/lib/null-mask", "/dev/null")
+            .symlink("etc/svc.d/10-a.conf", "../../dev/null")
+            .symlink("etc/svc.d/20-b.conf", "/usr/lib/null-mask");
+        let scanner = Overdrop::new("svc.d")
+            .base_dirs(["/usr/lib", "/etc"])
+            .root(tree.root());
+        let fragments = scanner.scan().unwrap();
+        assert_eq!(fragments.keys().collect::<Vec<_>>(), ["30-c.conf"]);
+    }
+
+    #[test]
+    fn synthetic_fragments() {
+        let bases =
+            ["usr/lib", "etc", "run"].map(|d| Path::new("tests/fixtures/tree-mask").join(d));
+        let scanner = Overdrop::new("liboverdrop.d")
+            .base_dirs(&bases)
+            .inject("10-a.conf", "runtime a", 3)
+            .inject("20-b.conf", "masked by etc", 0)
+            .inject("30-c.conf", "overridden by usr/lib", 0)
+            .inject("40-d.conf", "masked by run", 2)
+            .inject("50-e.conf", "replaced", 1)
+            .inject("50-e.conf", "runtime e", 1);
+        let sources = scanner.scan_sources().unwrap();
+        assert_eq!(
+            sources.keys().collect::<Vec<_>>(),
+            ["10-a.conf", "30-c.conf", "50-e.conf"]
+        );
+        let contents = |name: &str| {
+            let mut contents = String::new();
+            let mut reader = sources[OsStr::new(name)].open().unwrap();
+            io::Read::read_to_string(&mut reader, &mut contents).unwrap();
+            contents
+        };
+        assert_eq!(contents("10-a.conf"), "runtime a");
+        assert_eq!(contents("50-e.conf"), "runtime e");
+        assert_eq!(
+            sources[OsStr::new("30-c.conf")].fragment().unwrap().path(),
+            bases[0].join("liboverdrop.d/30-c.conf")
+        );
+
+        // Path-based results only list fragment files.
+        let fragments = scanner.scan().unwrap();
+        assert_eq!(fragments.keys().collect::<Vec<_>>(), ["30-c.conf"]);
+    }
+
+    #[test]
+    fn imported_layers() {
+        let bases =
+            ["usr/lib", "etc", "run"].map(|d| Path::new("tests/fixtures/tree-import").join(d));
+        let scanner = Overdrop::new("svc.d")
+            .base_dirs(&bases)
+            .import("fleet.d")
+            .require_all_dirs_exist(true);
+        assert_eq!(scanner.search_dirs().len(), 6);
+        assert_eq!(scanner.search_dirs()[0], bases[0].join("fleet.d"));
+
+        let err = scanner.scan().unwrap_err();
+        assert!(
+            matches!(err, ScanError::MissingDirs(ref dirs) if dirs == &[bases[2].join("svc.d")])
+        );
+
+        // The service's own vendor fragment wins over the imported admin one.
+        let fragments = scanner.base_dirs(&bases[..2]).scan().unwrap();
+        let expected = [
+            ("10-site.conf", bases[0].join("fleet.d/10-site.conf")),
+            ("20-shared.conf", bases[0].join("svc.d/20-shared.conf")),
+            ("30-local.conf", bases[1].join("svc.d/30-local.conf")),
+        ]
+        .map(|(n, p)| (OsString::from(n), p));
+        assert_eq!(fragments, BTreeMap::from(expected));
+    }
+
+    #[test]
+    fn validator() {
+        let bases =
+            ["usr/lib", "etc", "run"].map(|d| Path::new("tests/fixtures/tree-merge").join(d));
+        let scanner = Overdrop::new("liboverdrop.d")
+            .base_dirs(&bases)
+            .validator(|_, contents| {
+                if contents.starts_with(b"verbose=") {
+                    return Err("verbose is not allowed".to_string());
+                }
+                Ok(())
+            });
+
+        // Both the vendor and the admin `20-extra.conf` are rejected, and thus skipped.
+        let (fragments, statuses) = scanner.scan_with_status().unwrap();
+        let names: Vec<_> = fragments.iter().map(|(n, _)| n.clone()).collect();
+        assert_eq!(names, ["10-base.conf", "30-local.conf", "40-runtime.conf"]);
+        let rejected: Vec<_> = statuses.iter().flat_map(DirStatus::rejected).collect();
+        assert_eq!(rejected.len(), 2);
+        assert_eq!(
+            rejected[1].path(),
+            bases[1].join("liboverdrop.d/20-extra.conf")
+        );
+        assert_eq!(rejected[1].reason(), "verbose is not allowed");
+
+        let err = scanner.fail_on_rejection(true).scan().unwrap_err();
+        match err {
+            ScanError::Rejected(rejection) => {
+                assert_eq!(
+                    rejection.path(),
+                    bases[0].join("liboverdrop.d/20-extra.conf")
+                )
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn validator_effective_only() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "invalid")
+            .fragment("etc/svc.d/10-a.conf", "admin")
+            .fragment("usr/lib/svc.d/20-b.conf", "vendor")
+            .fragment("etc/svc.d/20-b.conf", "too large")
+            .fragment("usr/lib/svc.d/30-c.conf", "invalid")
+            .mask("etc/svc.d/30-c.conf");
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let validated = Arc::clone(&seen);
+        let (fragments, statuses) = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc"]))
+            .max_fragment_size(8)
+            .validator(move |path, contents| {
+                validated.lock().unwrap().push(path.to_path_buf());
+                match contents {
+                    b"invalid" => Err("invalid".to_string()),
+                    _ => Ok(()),
+                }
+            })
+            .scan_with_status()
+            .unwrap();
+
+        // Shadowed and masked fragments are not validated; the oversized admin
+        // `20-b.conf` is rejected without reaching the validator, and the vendor one takes over.
+        let paths: Vec<_> = fragments
+            .iter()
+            .map(|(_, f)| f.path().to_path_buf())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                tree.path("etc/svc.d/10-a.conf"),
+                tree.path("usr/lib/svc.d/20-b.conf")
+            ]
+        );
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                tree.path("etc/svc.d/10-a.conf"),
+                tree.path("usr/lib/svc.d/20-b.conf")
+            ]
+        );
+        let rejected: Vec<_> = statuses.iter().flat_map(DirStatus::rejected).collect();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].path(), tree.path("etc/svc.d/20-b.conf"));
+    }
+
+    #[test]
+    fn require_nonempty() {
+        let scanner = Overdrop::new("liboverdorp.d").base_dirs(dirs());
+        assert!(scanner.scan().unwrap().is_empty());
+
+        let err = scanner.require_nonempty(true).scan().unwrap_err();
+        assert!(matches!(err, ScanError::NoFragments));
+
+        Overdrop::new("liboverdrop.d")
+            .base_dirs(dirs())
+            .require_nonempty(true)
+            .scan()
+            .unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+//! Struct-level overlaying of parsed configuration fragments.
+
+use crate::scan_and_merge_with;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+/// A configuration value which a higher-priority one can be overlaid onto.
+///
+/// This is implemented for the building blocks of configuration structs:
+///
+/// - `Option<T>`: a `Some` higher value replaces the current one, `None` keeps it;
+/// - `Vec<T>`: higher items are appended;
+/// - `BTreeMap<K, V>` and `HashMap<K, V>`: higher entries are added, replacing the
+///   current ones with the same key.
+///
+/// Scalar settings are thus expected to be wrapped in `Option`, so that a fragment
+/// which does not set them leaves them unchanged. With the `derive` feature,
+/// `#[derive(Merge)]` implements this for structs by merging each field in turn, so
+/// that nested structs are merged recursively:
+///
+/// ```rust
+/// # #[cfg(feature = "derive")] {
+/// use liboverdrop::Merge;
+///
+/// #[derive(Default, Merge)]
+/// struct Config {
+///     interval: Option<u64>,
+///     peers: Vec<String>,
+/// }
+///
+/// let mut config = Config { interval: Some(5), peers: vec!["a".into()] };
+/// config.merge(Config { interval: None, peers: vec!["b".into()] });
+/// assert_eq!(config.interval, Some(5));
+/// assert_eq!(config.peers, ["a", "b"]);
+/// # }
+/// ```
+pub trait Merge {
+    /// Overlay `higher`, from a higher-priority fragment, onto `self`.
+    fn merge(&mut self, higher: Self);
+}
+
+impl<T> Merge for Option<T> {
+    fn merge(&mut self, higher: Self) {
+        if higher.is_some() {
+            *self = higher;
+        }
+    }
+}
+
+impl<T> Merge for Vec<T> {
+    fn merge(&mut self, higher: Self) {
+        self.extend(higher);
+    }
+}
+
+impl<K: Ord, V> Merge for BTreeMap<K, V> {
+    fn merge(&mut self, higher: Self) {
+        self.extend(higher);
+    }
+}
+
+impl<K: Eq + Hash, V, S: std::hash::BuildHasher> Merge for HashMap<K, V, S> {
+    fn merge(&mut self, higher: Self) {
+        self.extend(higher);
+    }
+}
+
+/// Scan configuration fragments, parse each of them, and overlay them with [`Merge`].
+///
+/// Arguments are the same as for [`scan`](crate::scan). Each unique configuration
+/// fragment is opened and passed to `parse`, and the parsed value is merged onto the
+/// ones before it, starting from `T::default()`, in the same order as returned by
+/// [`scan`](crate::scan) (i.e. later filenames take precedence).
+///
+/// The first error returned by `parse` (or encountered while opening a fragment)
+/// stops processing and is returned.
+pub fn scan_and_merge_into<T, BdS, BdI, Sp, As, E, F>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    mut parse: F,
+) -> Result<T, E>
+where
+    T: Merge + Default,
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    E: From<io::Error>,
+    F: FnMut(&OsStr, &mut BufReader<File>) -> Result<T, E>,
+{
+    scan_and_merge_with(
+        base_dirs,
+        shared_path,
+        allowed_extensions,
+        ignore_dotfiles,
+        T::default(),
+        |value, name, reader| {
+            value.merge(parse(name, reader)?);
+            Ok(())
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{merge_key_values, testing::TreeBuilder};
+
+    #[derive(Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "derive", derive(crate::Merge))]
+    struct Settings {
+        interval: Option<u64>,
+        names: Vec<String>,
+        env: BTreeMap<String, String>,
+    }
+
+    #[cfg(not(feature = "derive"))]
+    impl Merge for Settings {
+        fn merge(&mut self, higher: Self) {
+            Merge::merge(&mut self.interval, higher.interval);
+            Merge::merge(&mut self.names, higher.names);
+            Merge::merge(&mut self.env, higher.env);
+        }
+    }
+
+    fn parse(_: &OsStr, reader: &mut BufReader<File>) -> io::Result<Settings> {
+        let mut settings = Settings::default();
+        let mut values = BTreeMap::new();
+        merge_key_values(&mut values, reader, false)?;
+        for (key, value) in values {
+            match key.as_str() {
+                "interval" => settings.interval = value.parse().ok(),
+                "name" => settings.names.push(value),
+                _ => {
+                    settings.env.insert(key, value);
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    #[test]
+    fn merge_into() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "interval=5\nname=a\nA=vendor\n")
+            .fragment("usr/lib/svc.d/20-b.conf", "name=b\nB=vendor\n")
+            .fragment("etc/svc.d/10-a.conf", "name=admin\nA=admin\n");
+        let dirs = tree.base_dirs(["usr/lib", "etc"]);
+        let settings: Settings =
+            scan_and_merge_into(&dirs, "svc.d", &["conf"], false, parse).unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                interval: None,
+                names: vec!["admin".into(), "b".into()],
+                env: BTreeMap::from([("A".into(), "admin".into()), ("B".into(), "vendor".into())]),
+            }
+        );
+
+        let mut interval = Some(1);
+        interval.merge(None);
+        assert_eq!(interval, Some(1));
+        interval.merge(Some(2));
+        assert_eq!(interval, Some(2));
+    }
+}
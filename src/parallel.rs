@@ -0,0 +1,132 @@
+//! Parallel merging of configuration fragments.
+
+use crate::scan;
+use rayon::prelude::*;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Scan configuration fragments, parse them concurrently and combine the results.
+///
+/// Arguments are the same as for [`scan`]. Each unique configuration fragment is
+/// opened and passed to `parse` (together with its filename) on the rayon thread
+/// pool, yielding a partial value. Partial values are then combined pairwise
+/// via `combine(lower, higher)`, where `higher` comes from higher-priority
+/// fragments (i.e. later in the order returned by [`scan`]).
+///
+/// `combine` must be associative; under this condition the result is the same as
+/// folding all partial values sequentially in priority order, regardless of
+/// scheduling. Returns `None` if no fragment was found.
+///
+/// If several fragments fail to be opened or parsed, which error is returned is
+/// unspecified.
+///
+/// This is available with the `rayon` feature.
+pub fn scan_and_merge_par<BdS, BdI, Sp, As, P, E, F, C>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+    parse: F,
+    combine: C,
+) -> Result<Option<P>, E>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+    P: Send,
+    E: From<io::Error> + Send,
+    F: Fn(&OsStr, &mut BufReader<File>) -> Result<P, E> + Sync,
+    C: Fn(P, P) -> P + Sync + Send,
+{
+    let fragments: Vec<(OsString, PathBuf)> =
+        scan(base_dirs, shared_path, allowed_extensions, ignore_dotfiles)
+            .into_iter()
+            .collect();
+
+    let partials = fragments
+        .par_iter()
+        .map(|(name, path)| {
+            let mut reader = BufReader::new(File::open(path)?);
+            parse(name, &mut reader)
+        })
+        .collect::<Result<Vec<P>, E>>()?;
+    Ok(partials.into_par_iter().reduce_with(combine))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::io::BufRead;
+
+    type Settings = BTreeMap<String, String>;
+
+    fn dirs() -> [PathBuf; 3] {
+        ["usr/lib", "etc", "run"].map(|d| Path::new("tests/fixtures/tree-merge").join(d))
+    }
+
+    fn parse(_: &OsStr, reader: &mut BufReader<File>) -> io::Result<Settings> {
+        let mut settings = Settings::new();
+        for line in reader.lines() {
+            if let Some((k, v)) = line?.split_once('=') {
+                settings.insert(k.to_string(), v.to_string());
+            }
+        }
+        Ok(settings)
+    }
+
+    #[test]
+    fn parallel_matches_sequential() {
+        let merged = scan_and_merge_par(
+            dirs(),
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            parse,
+            |mut lower, higher| {
+                lower.extend(higher);
+                lower
+            },
+        )
+        .unwrap()
+        .unwrap();
+
+        let expected: Settings = crate::scan_and_merge(
+            dirs(),
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            |mut acc: Settings, name, reader| -> io::Result<_> {
+                acc.extend(parse(name, reader)?);
+                Ok(acc)
+            },
+        )
+        .unwrap();
+        assert_eq!(merged, expected);
+
+        // Order of combination is deterministic, for associative operations.
+        let order = scan_and_merge_par(
+            dirs(),
+            "liboverdrop.d",
+            &["conf"],
+            false,
+            |name, _| Ok::<_, io::Error>(vec![name.to_os_string()]),
+            |mut lower, higher| {
+                lower.extend(higher);
+                lower
+            },
+        )
+        .unwrap()
+        .unwrap();
+        let names: Vec<_> = crate::scan(dirs(), "liboverdrop.d", &["conf"], false)
+            .into_keys()
+            .collect();
+        assert_eq!(order, names);
+
+        let none = scan_and_merge_par(dirs(), "missing.d", &["conf"], false, parse, |a, _| a);
+        assert!(none.unwrap().is_none());
+    }
+}
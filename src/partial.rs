@@ -0,0 +1,171 @@
+//! Typed overlays of partial configuration fragments.
+//!
+//! A common pattern for typed configuration is to deserialize each fragment
+//! into a "partial" struct (whose fields are all `Option`), and then fold all
+//! partials in priority order on top of the default configuration. Only the
+//! fields which are set in a fragment override the value from lower-priority
+//! fragments.
+//!
+//! The [`partial!`](crate::partial!) macro generates such a partial struct
+//! together with its [`Partial`] implementation, and [`fold_partials`] performs
+//! the fold.
+
+/// A configuration fragment which may set only some of the fields of `T`.
+pub trait Partial<T> {
+    /// Apply all the fields set in this fragment on top of `target`.
+    fn apply_to(self, target: &mut T);
+}
+
+impl<T, P: Partial<T>> Partial<T> for Option<P> {
+    fn apply_to(self, target: &mut T) {
+        if let Some(partial) = self {
+            partial.apply_to(target);
+        }
+    }
+}
+
+/// Fold partial fragments over `defaults`, returning the final configuration.
+///
+/// `partials` are applied in order, thus later ones (i.e. higher-priority
+/// fragments, as iterated from the result of [`scan`](crate::scan)) override
+/// fields set by earlier ones.
+pub fn fold_partials<T, P, I>(defaults: T, partials: I) -> T
+where
+    P: Partial<T>,
+    I: IntoIterator<Item = P>,
+{
+    let mut config = defaults;
+    for partial in partials {
+        partial.apply_to(&mut config);
+    }
+    config
+}
+
+/// Generate a partial struct for a configuration type.
+///
+/// Each field listed in the macro invocation must exist in the target type;
+/// the generated struct holds an `Option` of the same type for each of them,
+/// and implements [`Partial`] for the target type.
+/// Attributes (e.g. `#[derive(Deserialize)]` or `#[serde(...)]`) are forwarded
+/// to the generated struct and its fields.
+///
+/// # Example
+///
+/// ```rust
+/// #[derive(Debug, Default, PartialEq)]
+/// struct Config {
+///     enabled: bool,
+///     interval: u64,
+/// }
+///
+/// liboverdrop::partial! {
+///     #[derive(Default)]
+///     struct PartialConfig for Config {
+///         enabled: bool,
+///         interval: u64,
+///     }
+/// }
+///
+/// let fragments = vec![
+///     PartialConfig { enabled: Some(true), interval: Some(10) },
+///     PartialConfig { enabled: None, interval: Some(30) },
+/// ];
+/// let config = liboverdrop::fold_partials(Config::default(), fragments);
+/// assert_eq!(config, Config { enabled: true, interval: 30 });
+/// ```
+#[macro_export]
+macro_rules! partial {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident for $target:ty {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident : $field_ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: ::std::option::Option<$field_ty>,
+            )*
+        }
+
+        impl $crate::Partial<$target> for $name {
+            fn apply_to(self, target: &mut $target) {
+                $(
+                    if let ::std::option::Option::Some(value) = self.$field {
+                        target.$field = value;
+                    }
+                )*
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Config {
+        name: String,
+        retries: u8,
+        verbose: bool,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                name: "default".to_string(),
+                retries: 3,
+                verbose: false,
+            }
+        }
+    }
+
+    crate::partial! {
+        #[derive(Default)]
+        struct PartialConfig for Config {
+            name: String,
+            retries: u8,
+            verbose: bool,
+        }
+    }
+
+    #[test]
+    fn fold_over_defaults() {
+        let partials = vec![
+            PartialConfig {
+                name: Some("vendor".to_string()),
+                retries: Some(5),
+                ..Default::default()
+            },
+            PartialConfig::default(),
+            PartialConfig {
+                retries: Some(1),
+                verbose: Some(true),
+                ..Default::default()
+            },
+        ];
+
+        let config = fold_partials(Config::default(), partials);
+        let expected = Config {
+            name: "vendor".to_string(),
+            retries: 1,
+            verbose: true,
+        };
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn fold_nothing() {
+        let config = fold_partials(Config::default(), Vec::<PartialConfig>::new());
+        assert_eq!(config, Config::default());
+
+        let skipped = vec![None, Some(PartialConfig::default())];
+        let config = fold_partials(Config::default(), skipped);
+        assert_eq!(config, Config::default());
+    }
+}
@@ -0,0 +1,220 @@
+//! Glob-like patterns for filtering fragments by filename.
+
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::str::FromStr;
+
+/// Error returned when parsing an invalid [`NamePattern`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PatternError {
+    /// A `[` character class is not terminated by `]`.
+    UnterminatedClass(String),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternError::UnterminatedClass(pattern) => {
+                write!(f, "unterminated character class in pattern '{}'", pattern)
+            }
+        }
+    }
+}
+
+impl Error for PatternError {}
+
+/// A glob-like pattern, matched against whole fragment filenames.
+///
+/// Patterns support `*` (any sequence of characters), `?` (any single character),
+/// character classes such as `[0-9]` or `[!~]` (negated with `!` or `^`), and `\`
+/// to escape the next character. See
+/// [`Overdrop::name_patterns`](crate::Overdrop::name_patterns).
+///
+/// ```rust
+/// # use liboverdrop::NamePattern;
+/// let pattern: NamePattern = "[0-9][0-9]-*.toml".parse()?;
+/// assert!(pattern.matches("10-network.toml".as_ref()));
+/// assert!(!pattern.matches("network.toml".as_ref()));
+/// # Ok::<(), liboverdrop::PatternError>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamePattern {
+    pattern: String,
+    tokens: Vec<Token>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    AnyChar,
+    AnySequence,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    /// Whether this single-character token matches `c`.
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(l) => *l == c,
+            Token::AnyChar => true,
+            Token::AnySequence => false,
+            Token::Class { negated, ranges } => {
+                ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+impl NamePattern {
+    /// Parse a pattern.
+    pub fn new(pattern: &str) -> Result<Self, PatternError> {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*' => Token::AnySequence,
+                '?' => Token::AnyChar,
+                '\\' => Token::Literal(chars.next().unwrap_or('\\')),
+                '[' => {
+                    let unterminated = || PatternError::UnterminatedClass(pattern.to_string());
+                    let negated = chars.next_if(|c| *c == '!' || *c == '^').is_some();
+                    let mut ranges = Vec::new();
+                    // A leading `]` is part of the class.
+                    let mut first = true;
+                    loop {
+                        let lo = match chars.next() {
+                            Some(']') if !first => break,
+                            Some(c) => c,
+                            None => return Err(unterminated()),
+                        };
+                        first = false;
+                        let hi = match chars.peek() {
+                            Some('-') => {
+                                chars.next();
+                                match chars.next() {
+                                    Some(']') => {
+                                        // A trailing `-` is literal.
+                                        ranges.push((lo, lo));
+                                        ranges.push(('-', '-'));
+                                        break;
+                                    }
+                                    Some(hi) => hi,
+                                    None => return Err(unterminated()),
+                                }
+                            }
+                            _ => lo,
+                        };
+                        ranges.push((lo, hi));
+                    }
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Literal(c),
+            };
+            tokens.push(token);
+        }
+        Ok(Self {
+            pattern: pattern.to_string(),
+            tokens,
+        })
+    }
+
+    /// The source pattern.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether `fname` matches this pattern.
+    ///
+    /// As for extensions, non-UTF-8 filenames are lossily converted before matching.
+    pub fn matches(&self, fname: &OsStr) -> bool {
+        let fname: Vec<char> = fname.to_string_lossy().chars().collect();
+        let (mut t, mut n) = (0, 0);
+        // Position of the last `*`, and of the name when it was reached.
+        let mut backtrack = None;
+        while n < fname.len() {
+            match self.tokens.get(t) {
+                Some(Token::AnySequence) => {
+                    backtrack = Some((t, n));
+                    t += 1;
+                    continue;
+                }
+                Some(token) if token.matches(fname[n]) => {
+                    t += 1;
+                    n += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            // Let the last `*` match one more character, if any.
+            match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    t = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            }
+        }
+        self.tokens[t..]
+            .iter()
+            .all(|token| *token == Token::AnySequence)
+    }
+}
+
+impl FromStr for NamePattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl fmt::Display for NamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, fname: &str) -> bool {
+        NamePattern::new(pattern)
+            .unwrap()
+            .matches(OsStr::new(fname))
+    }
+
+    #[test]
+    fn pattern_matching() {
+        assert!(matches("*", ""));
+        assert!(matches("*.toml", "10-a.toml"));
+        assert!(!matches("*.toml", "10-a.toml.bak"));
+        assert!(matches("[0-9][0-9]-*.toml", "42-x.toml"));
+        assert!(!matches("[0-9][0-9]-*.toml", "4-x.toml"));
+        assert!(matches("??-*", "ab-"));
+        assert!(matches("*a*b*c", "xaybzc"));
+        assert!(!matches("*a*b*c", "xaybzcd"));
+        assert!(matches("[!~]*", "a~"));
+        assert!(!matches("[^~]*", "~a"));
+        assert!(matches("[]-]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches(r"\*", "*"));
+        assert!(!matches(r"\*", "a"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in ["[0-9", "[", "[]", "[a-"] {
+            assert_eq!(
+                pattern.parse::<NamePattern>(),
+                Err(PatternError::UnterminatedClass(pattern.to_string()))
+            );
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//! Structured reports of scan results, e.g. for bug reports.
+
+use crate::layout::LayerEntries;
+use crate::{DirStatus, Entry, Fragment, LayerPolicy, Source};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A structured report of a scan, see [`Overdrop::report`](crate::Overdrop::report).
+///
+/// This holds the same information as the [`Layout`](crate::Layout), meant for
+/// machines instead of humans: the status of each scanned directory, the effective
+/// fragments with the ones they shadow, the masked filenames, and the entries skipped
+/// while scanning. With the `serde` feature, it implements `serde::Serialize`, e.g.
+/// for dumping it as JSON; filenames and paths are serialized as (lossily converted)
+/// strings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanReport {
+    dirs: Vec<DirStatus>,
+    fragments: Vec<ReportedFragment>,
+    masked: Vec<MaskedName>,
+    skipped: Vec<SkippedEntry>,
+}
+
+/// An effective fragment, as listed in a [`ScanReport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportedFragment {
+    name: OsString,
+    path: Option<PathBuf>,
+    shadowed: Vec<PathBuf>,
+}
+
+impl ReportedFragment {
+    /// Filename of the fragment.
+    pub fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Path of the fragment, or `None` for [synthetic](crate::Overdrop::inject) fragments.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Paths of lower-priority fragments with the same filename, in increasing priority order.
+    pub fn shadowed(&self) -> &[PathBuf] {
+        &self.shadowed
+    }
+}
+
+/// A masked filename, as listed in a [`ScanReport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MaskedName {
+    name: OsString,
+    mask: PathBuf,
+}
+
+impl MaskedName {
+    /// The masked filename.
+    pub fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Path of the effective mask.
+    pub fn mask(&self) -> &Path {
+        &self.mask
+    }
+}
+
+/// An entry skipped while scanning, as listed in a [`ScanReport`] or passed to [`Overdrop::on_skipped`](crate::Overdrop::on_skipped).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedEntry {
+    path: PathBuf,
+    reason: SkipReason,
+}
+
+impl SkippedEntry {
+    pub(crate) fn new(path: PathBuf, reason: SkipReason) -> Self {
+        Self { path, reason }
+    }
+
+    /// Path of the skipped entry.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Why the entry was skipped.
+    pub fn reason(&self) -> &SkipReason {
+        &self.reason
+    }
+}
+
+/// Why an entry was skipped while scanning.
+///
+/// A [`ScanReport`] only lists [`UnmatchedExtension`](Self::UnmatchedExtension) and
+/// [`Rejected`](Self::Rejected) entries; all reasons are passed to
+/// [`Overdrop::on_skipped`](crate::Overdrop::on_skipped).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SkipReason {
+    /// The file did not match the allowed extensions.
+    ///
+    /// In a [`ScanReport`], this is only reported if
+    /// [`Overdrop::report_unmatched`](crate::Overdrop::report_unmatched) is enabled.
+    UnmatchedExtension,
+    /// The fragment was rejected by the [validator](crate::Overdrop::validator) or the
+    /// [trust policy](crate::Overdrop::trust), for the given reason.
+    Rejected(String),
+    /// The entry is hidden, e.g. a dotfile while [ignoring them](crate::Overdrop::ignore_dotfiles).
+    Hidden,
+    /// The name did not match the [patterns](crate::Overdrop::name_patterns) or
+    /// [prefix](crate::Overdrop::name_prefix), or matched an
+    /// [excluded pattern](crate::Overdrop::exclude_patterns).
+    UnmatchedPattern,
+    /// The entry was excluded by the [filter](crate::Overdrop::filter).
+    Filtered,
+    /// The entry is neither a regular file nor a mask, e.g. a directory or a symlink which is not followed.
+    NotAFile,
+    /// The metadata of the entry could not be read.
+    Metadata(io::ErrorKind),
+    /// The directory at this path could not be read, for other reasons than not existing.
+    Unreadable(io::ErrorKind),
+}
+
+impl ScanReport {
+    /// Build the report of all `layers`, given the winning `sources`.
+    pub(crate) fn new(layers: Vec<LayerEntries>, sources: &BTreeMap<OsString, Source>) -> Self {
+        let is_mask = |policy: &LayerPolicy, entry: &Entry<Fragment>| match entry {
+            Entry::Mask => policy.masks,
+            Entry::Empty(_) => policy.masks && policy.empty_file_masks,
+            Entry::Fragment(_) => false,
+        };
+        // Highest-priority applied mask, and layer of each winning fragment file.
+        let mut maskers = BTreeMap::new();
+        let mut winner_layers = BTreeMap::new();
+        let mut skipped = Vec::new();
+        for (idx, (status, policy, entries)) in layers.iter().enumerate() {
+            for (name, entry) in entries {
+                if is_mask(policy, entry) {
+                    maskers.insert(name, status.path().join(name));
+                }
+                if let Entry::Fragment(f) | Entry::Empty(f) = entry {
+                    if sources.get(name).and_then(Source::fragment) == Some(f) {
+                        winner_layers.insert(name, idx);
+                    }
+                }
+            }
+            skipped.extend(status.unmatched().map(|name| SkippedEntry {
+                path: status.path().join(name),
+                reason: SkipReason::UnmatchedExtension,
+            }));
+            skipped.extend(status.rejected().iter().map(|rejection| SkippedEntry {
+                path: rejection.path().to_path_buf(),
+                reason: SkipReason::Rejected(rejection.reason().to_string()),
+            }));
+        }
+
+        let fragments = sources
+            .iter()
+            .map(|(name, source)| {
+                // Synthetic winners have no layer, and shadow all fragment files.
+                let winner_layer = winner_layers.get(name).copied().unwrap_or(layers.len());
+                let shadowed = layers[..winner_layer]
+                    .iter()
+                    .filter_map(|(status, policy, entries)| {
+                        let (_, entry) = entries.iter().find(|(n, _)| n == name)?;
+                        match entry {
+                            Entry::Fragment(_) | Entry::Empty(_) if !is_mask(policy, entry) => {
+                                Some(status.path().join(name))
+                            }
+                            _ => None,
+                        }
+                    })
+                    .filter(|path| !is_rejected(&layers, path))
+                    .collect();
+                ReportedFragment {
+                    name: name.clone(),
+                    path: source.fragment().map(|f| f.path().to_path_buf()),
+                    shadowed,
+                }
+            })
+            .collect();
+        let masked = maskers
+            .into_iter()
+            .filter(|(name, _)| !sources.contains_key(*name))
+            .map(|(name, mask)| MaskedName {
+                name: name.clone(),
+                mask,
+            })
+            .collect();
+        Self {
+            dirs: layers.into_iter().map(|(status, ..)| status).collect(),
+            fragments,
+            masked,
+            skipped,
+        }
+    }
+
+    /// Status of each scanned directory, in increasing priority order.
+    pub fn dirs(&self) -> &[DirStatus] {
+        &self.dirs
+    }
+
+    /// The effective fragments, by filename.
+    pub fn fragments(&self) -> &[ReportedFragment] {
+        &self.fragments
+    }
+
+    /// Filenames masked without any higher-priority fragment, by filename.
+    pub fn masked(&self) -> &[MaskedName] {
+        &self.masked
+    }
+
+    /// Entries skipped while scanning, in increasing priority order of their directories.
+    pub fn skipped(&self) -> &[SkippedEntry] {
+        &self.skipped
+    }
+}
+
+/// Whether the fragment at `path` was rejected by the validator.
+fn is_rejected(layers: &[LayerEntries], path: &Path) -> bool {
+    layers
+        .iter()
+        .flat_map(|(status, ..)| status.rejected())
+        .any(|rejection| rejection.path() == path)
+}
+
+#[cfg(feature = "serde")]
+mod ser {
+    use super::*;
+    use crate::Rejection;
+    use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+    fn lossy(s: &OsStr) -> std::borrow::Cow<'_, str> {
+        s.to_string_lossy()
+    }
+
+    fn lossy_paths(paths: &[PathBuf]) -> Vec<std::borrow::Cow<'_, str>> {
+        paths.iter().map(|p| p.to_string_lossy()).collect()
+    }
+
+    impl Serialize for ScanReport {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut s = serializer.serialize_struct("ScanReport", 4)?;
+            s.serialize_field("dirs", &self.dirs)?;
+            s.serialize_field("fragments", &self.fragments)?;
+            s.serialize_field("masked", &self.masked)?;
+            s.serialize_field("skipped", &self.skipped)?;
+            s.end()
+        }
+    }
+
+    impl Serialize for ReportedFragment {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut s = serializer.serialize_struct("ReportedFragment", 3)?;
+            s.serialize_field("name", &lossy(&self.name))?;
+            s.serialize_field("path", &self.path.as_ref().map(|p| p.to_string_lossy()))?;
+            s.serialize_field("shadowed", &lossy_paths(&self.shadowed))?;
+            s.end()
+        }
+    }
+
+    impl Serialize for MaskedName {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut s = serializer.serialize_struct("MaskedName", 2)?;
+            s.serialize_field("name", &lossy(&self.name))?;
+            s.serialize_field("mask", &self.mask.to_string_lossy())?;
+            s.end()
+        }
+    }
+
+    impl Serialize for SkippedEntry {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let (reason, detail) = match &self.reason {
+                SkipReason::UnmatchedExtension => ("unmatched-extension", None),
+                SkipReason::Rejected(reason) => ("rejected", Some(reason.clone())),
+                SkipReason::Hidden => ("hidden", None),
+                SkipReason::UnmatchedPattern => ("unmatched-pattern", None),
+                SkipReason::Filtered => ("filtered", None),
+                SkipReason::NotAFile => ("not-a-file", None),
+                SkipReason::Metadata(kind) => ("metadata", Some(kind.to_string())),
+                SkipReason::Unreadable(kind) => ("unreadable", Some(kind.to_string())),
+            };
+            let mut s = serializer.serialize_struct("SkippedEntry", 3)?;
+            s.serialize_field("path", &self.path.to_string_lossy())?;
+            s.serialize_field("reason", reason)?;
+            s.serialize_field("detail", &detail)?;
+            s.end()
+        }
+    }
+
+    impl Serialize for DirStatus {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let unmatched: Vec<_> = self.unmatched().map(lossy).collect();
+            let symlinks: Vec<_> = self
+                .symlinks()
+                .iter()
+                .map(|p| p.to_string_lossy())
+                .collect();
+            let mut s = serializer.serialize_struct("DirStatus", 6)?;
+            s.serialize_field("path", &self.path().to_string_lossy())?;
+            s.serialize_field("error", &self.error().map(|kind| kind.to_string()))?;
+            s.serialize_field("matched", &self.matched())?;
+            s.serialize_field("unmatched", &unmatched)?;
+            s.serialize_field("rejected", self.rejected())?;
+            s.serialize_field("symlinks", &symlinks)?;
+            s.end()
+        }
+    }
+
+    impl Serialize for Rejection {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut s = serializer.serialize_struct("Rejection", 2)?;
+            s.serialize_field("path", &self.path().to_string_lossy())?;
+            s.serialize_field("reason", self.reason())?;
+            s.end()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Overdrop, SkipReason};
+    use std::path::Path;
+
+    #[test]
+    fn scan_report() {
+        let treedir = Path::new("tests/fixtures/tree-mask");
+        let bases = ["usr/lib", "etc", "run"].map(|d| treedir.join(d));
+        let report = Overdrop::new("liboverdrop.d")
+            .base_dirs(&bases)
+            .allowed_extensions(&["conf"])
+            .report_unmatched(true)
+            .inject("50-e.conf", "runtime e", 3)
+            .report();
+        let dir = |idx: usize, name: &str| bases[idx].join("liboverdrop.d").join(name);
+
+        assert_eq!(report.dirs().len(), 3);
+        let fragments: Vec<_> = report
+            .fragments()
+            .iter()
+            .map(|f| (f.name().to_str().unwrap(), f.path(), f.shadowed()))
+            .collect();
+        assert_eq!(
+            fragments,
+            [
+                (
+                    "10-a.conf",
+                    Some(dir(2, "10-a.conf").as_path()),
+                    &[dir(0, "10-a.conf")][..]
+                ),
+                ("30-c.conf", Some(dir(0, "30-c.conf").as_path()), &[][..]),
+                ("50-e.conf", None, &[][..]),
+            ]
+        );
+        let masked: Vec<_> = report
+            .masked()
+            .iter()
+            .map(|m| (m.name().to_str().unwrap(), m.mask()))
+            .collect();
+        assert_eq!(
+            masked,
+            [
+                ("20-b.conf", dir(1, "20-b.conf").as_path()),
+                ("40-d.conf", dir(2, "40-d.conf").as_path()),
+            ]
+        );
+        assert!(report
+            .skipped()
+            .iter()
+            .all(|s| *s.reason() == SkipReason::UnmatchedExtension));
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "json"))]
+    fn serialize_report() {
+        let treedir = Path::new("tests/fixtures/tree-mask");
+        let report = Overdrop::new("liboverdrop.d")
+            .base_dirs([treedir.join("etc"), treedir.join("missing")])
+            .report();
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "dirs": [
+                    {
+                        "path": "tests/fixtures/tree-mask/etc/liboverdrop.d",
+                        "error": null,
+                        "matched": 1,
+                        "unmatched": [],
+                        "rejected": [],
+                        "symlinks": [],
+                    },
+                    {
+                        "path": "tests/fixtures/tree-mask/missing/liboverdrop.d",
+                        "error": "entity not found",
+                        "matched": 0,
+                        "unmatched": [],
+                        "rejected": [],
+                        "symlinks": [],
+                    },
+                ],
+                "fragments": [],
+                "masked": [
+                    {
+                        "name": "20-b.conf",
+                        "mask": "tests/fixtures/tree-mask/etc/liboverdrop.d/20-b.conf",
+                    },
+                ],
+                "skipped": [],
+            })
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Pure override and masking resolution, independent of any filesystem I/O.
+//!
+//! The layering rules applied by [`scan`](crate::scan) and [`Overdrop`](crate::Overdrop)
+//! are implemented here on plain entry lists, with directory walks being just one
+//! producer of entries. This allows reusing the exact same semantics on in-memory
+//! data (e.g. archives, or fragments received over an API).
+
+use crate::LayerPolicy;
+use log::trace;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+/// An entry of a single layer, by filename.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Entry<T> {
+    /// A configuration fragment.
+    Fragment(T),
+    /// An empty configuration fragment.
+    ///
+    /// This is a regular fragment, unless the layer policy treats empty files as masks.
+    Empty(T),
+    /// A mask (e.g. a `/dev/null` symlink), hiding fragments with the same filename
+    /// from lower layers.
+    Mask,
+}
+
+impl<T> Entry<T> {
+    /// Map the fragment of this entry, if any.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Entry<U> {
+        match self {
+            Entry::Fragment(fragment) => Entry::Fragment(f(fragment)),
+            Entry::Empty(fragment) => Entry::Empty(f(fragment)),
+            Entry::Mask => Entry::Mask,
+        }
+    }
+}
+
+/// Resolution of unique fragments across layers, in increasing priority order.
+///
+/// ```rust
+/// # use liboverdrop::{Entry, LayerPolicy, Resolver};
+/// let mut resolver = Resolver::new();
+/// resolver.push_layer(
+///     LayerPolicy::new(),
+///     [("10-a.conf".into(), Entry::Fragment("vendor a")), ("20-b.conf".into(), Entry::Fragment("vendor b"))],
+/// );
+/// resolver.push_layer(
+///     LayerPolicy::new(),
+///     [("10-a.conf".into(), Entry::Fragment("admin a")), ("20-b.conf".into(), Entry::Mask)],
+/// );
+/// let fragments = resolver.into_map();
+/// assert_eq!(fragments.len(), 1);
+/// assert_eq!(fragments[std::ffi::OsStr::new("10-a.conf")], "admin a");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Resolver<T> {
+    /// Winning fragments, with the policy of the layer they come from.
+    winners: BTreeMap<OsString, (T, LayerPolicy)>,
+}
+
+impl<T> Default for Resolver<T> {
+    fn default() -> Self {
+        Self {
+            winners: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Resolver<T> {
+    /// Create an empty resolver.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply all the entries of the next (i.e. higher priority) layer.
+    pub fn push_layer<I>(&mut self, policy: LayerPolicy, entries: I)
+    where
+        I: IntoIterator<Item = (OsString, Entry<T>)>,
+    {
+        for (name, entry) in entries {
+            self.apply(policy, name, entry);
+        }
+    }
+
+    /// Apply a single `entry` of a layer with the given `policy`.
+    ///
+    /// Entries of the same layer may be applied in any order, but all of them
+    /// must be applied after those of lower layers.
+    pub fn apply(&mut self, policy: LayerPolicy, name: OsString, entry: Entry<T>) {
+        let owner = self.winners.get(&name).map(|(_, owner)| owner);
+        let fragment = match entry {
+            Entry::Fragment(fragment) => fragment,
+            Entry::Empty(fragment) if !(policy.masks && policy.empty_file_masks) => fragment,
+            Entry::Empty(_) | Entry::Mask => {
+                if !policy.masks || owner.map_or(false, |o| !o.maskable) {
+                    trace!("Ignoring mask for '{}'", Path::new(&name).display());
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(name = %Path::new(&name).display(), "ignoring mask");
+                    return;
+                }
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    name = %Path::new(&name).display(),
+                    masked = owner.is_some(),
+                    "mask applied"
+                );
+                self.winners.remove(&name);
+                return;
+            }
+        };
+        if owner.map_or(false, |o| !o.overridable) {
+            trace!("Ignoring override for '{}'", Path::new(&name).display());
+            #[cfg(feature = "tracing")]
+            tracing::debug!(name = %Path::new(&name).display(), "ignoring override");
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        if owner.is_some() {
+            tracing::debug!(name = %Path::new(&name).display(), "fragment overridden");
+        }
+        self.winners.insert(name, (fragment, policy));
+    }
+
+    /// Return the current winner for `name`, if any.
+    pub fn get(&self, name: impl AsRef<OsStr>) -> Option<&T> {
+        self.winners
+            .get(name.as_ref())
+            .map(|(fragment, _)| fragment)
+    }
+
+    /// Number of unique fragments.
+    pub fn len(&self) -> usize {
+        self.winners.len()
+    }
+
+    /// Whether there is no fragment.
+    pub fn is_empty(&self) -> bool {
+        self.winners.is_empty()
+    }
+
+    /// Return the unique fragments, by filename.
+    pub fn into_map(self) -> BTreeMap<OsString, T> {
+        self.winners
+            .into_iter()
+            .map(|(name, (fragment, _))| (name, fragment))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Layer = Vec<(OsString, Entry<&'static str>)>;
+
+    fn layer(entries: &[(&str, Entry<&'static str>)]) -> Layer {
+        entries
+            .iter()
+            .map(|(name, entry)| (OsString::from(name), *entry))
+            .collect()
+    }
+
+    fn resolve(layers: &[(LayerPolicy, Layer)]) -> Vec<&'static str> {
+        let mut resolver = Resolver::new();
+        for (policy, entries) in layers {
+            resolver.push_layer(*policy, entries.iter().cloned());
+        }
+        resolver.into_map().into_values().collect()
+    }
+
+    #[test]
+    fn default_rules() {
+        let vendor = layer(&[
+            ("10-a", Entry::Fragment("vendor a")),
+            ("20-b", Entry::Fragment("vendor b")),
+            ("30-c", Entry::Fragment("vendor c")),
+        ]);
+        let admin = layer(&[
+            ("10-a", Entry::Fragment("admin a")),
+            ("20-b", Entry::Mask),
+            ("30-c", Entry::Empty("admin c")),
+            ("40-d", Entry::Mask),
+        ]);
+        let runtime = layer(&[("20-b", Entry::Fragment("runtime b"))]);
+        let policy = LayerPolicy::new();
+        assert_eq!(
+            resolve(&[
+                (policy, vendor.clone()),
+                (policy, admin.clone()),
+                (policy, runtime)
+            ]),
+            ["admin a", "runtime b", "admin c"]
+        );
+
+        let masking = LayerPolicy::new().empty_file_masks(true);
+        assert_eq!(resolve(&[(policy, vendor), (masking, admin)]), ["admin a"]);
+    }
+
+    #[test]
+    fn policy_rules() {
+        let vendor = layer(&[
+            ("10-a", Entry::Fragment("vendor a")),
+            ("20-b", Entry::Fragment("vendor b")),
+        ]);
+        let admin = layer(&[("10-a", Entry::Fragment("admin a")), ("20-b", Entry::Mask)]);
+        let policy = LayerPolicy::new();
+
+        let locked = LayerPolicy::new().overridable(false).maskable(false);
+        assert_eq!(
+            resolve(&[(locked, vendor.clone()), (policy, admin.clone())]),
+            ["vendor a", "vendor b"]
+        );
+
+        let unmaskable = LayerPolicy::new().maskable(false);
+        assert_eq!(
+            resolve(&[(unmaskable, vendor.clone()), (policy, admin.clone())]),
+            ["admin a", "vendor b"]
+        );
+
+        let no_masks = LayerPolicy::new().masks(false);
+        assert_eq!(
+            resolve(&[(policy, vendor), (no_masks, admin)]),
+            ["admin a", "vendor b"]
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_events() {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// Record events as `message name` lines.
+        #[derive(Default)]
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        /// The message and `name` field of an event.
+        #[derive(Default)]
+        struct Line(String, String);
+
+        impl Visit for Line {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                match field.name() {
+                    "message" => self.0 = format!("{:?}", value),
+                    "name" => self.1 = format!("{:?}", value),
+                    _ => {}
+                }
+            }
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _: &Attributes<'_>) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut line = Line::default();
+                event.record(&mut line);
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {}", line.0, line.1));
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let events = Arc::default();
+        let recorder = Recorder(Arc::clone(&events));
+        let pinned = LayerPolicy::new().overridable(false);
+        tracing::subscriber::with_default(recorder, || {
+            resolve(&[
+                (pinned, layer(&[("10-a", Entry::Fragment("vendor a"))])),
+                (
+                    LayerPolicy::new(),
+                    layer(&[
+                        ("10-a", Entry::Fragment("admin a")),
+                        ("20-b", Entry::Fragment("admin b")),
+                    ]),
+                ),
+                (
+                    LayerPolicy::new(),
+                    layer(&[
+                        ("20-b", Entry::Fragment("runtime b")),
+                        ("30-c", Entry::Mask),
+                    ]),
+                ),
+            ]);
+        });
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "ignoring override 10-a",
+                "fragment overridden 20-b",
+                "mask applied 30-c",
+            ]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! JSON Schema validation of configuration fragments.
+//!
+//! This module is available with the `jsonschema` feature. It allows services
+//! which publish a JSON Schema for their drop-ins to validate each fragment
+//! (or the merged result) before accepting it.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Error while validating configuration fragments against a schema.
+#[derive(Debug)]
+pub enum SchemaError {
+    /// The caller-supplied schema is not a valid JSON Schema.
+    InvalidSchema(String),
+    /// A fragment could not be read or parsed into a JSON value.
+    Parse {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// A fragment does not match the schema.
+    ///
+    /// `path` is `None` when validating a merged value.
+    Invalid {
+        path: Option<PathBuf>,
+        errors: Vec<String>,
+    },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::InvalidSchema(msg) => write!(f, "invalid schema: {}", msg),
+            SchemaError::Parse { path, source } => {
+                write!(f, "failed to parse '{}': {}", path.display(), source)
+            }
+            SchemaError::Invalid { path, errors } => {
+                match path {
+                    Some(path) => write!(f, "'{}' does not match schema", path.display())?,
+                    None => write!(f, "merged configuration does not match schema")?,
+                }
+                for err in errors {
+                    write!(f, "\n  {}", err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for SchemaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SchemaError::Parse { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// A compiled JSON Schema, used to validate fragments.
+pub struct SchemaValidator {
+    validator: jsonschema::Validator,
+}
+
+impl fmt::Debug for SchemaValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SchemaValidator").finish_non_exhaustive()
+    }
+}
+
+impl SchemaValidator {
+    /// Compile a validator from a JSON Schema document.
+    pub fn new(schema: &Value) -> Result<Self, SchemaError> {
+        let validator = jsonschema::validator_for(schema)
+            .map_err(|e| SchemaError::InvalidSchema(e.to_string()))?;
+        Ok(Self { validator })
+    }
+
+    /// Validate a value which does not come from a single fragment (e.g. a merged value).
+    pub fn validate_merged(&self, value: &Value) -> Result<(), SchemaError> {
+        self.check(None, value)
+    }
+
+    /// Parse and validate each of the `fragments` returned by [`scan`](crate::scan).
+    ///
+    /// `parse` turns a fragment into a JSON value. All fragments are processed,
+    /// and either all parsed values are returned, or all per-fragment errors.
+    pub fn validate_fragments<P, E>(
+        &self,
+        fragments: &BTreeMap<OsString, PathBuf>,
+        mut parse: P,
+    ) -> Result<BTreeMap<OsString, Value>, Vec<SchemaError>>
+    where
+        P: FnMut(&OsStr, &mut BufReader<File>) -> Result<Value, E>,
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let mut values = BTreeMap::new();
+        let mut errors = Vec::new();
+        for (name, path) in fragments {
+            let parsed = File::open(path)
+                .map_err(Into::into)
+                .and_then(|f| parse(name, &mut BufReader::new(f)).map_err(Into::into));
+            let value = match parsed {
+                Ok(v) => v,
+                Err(source) => {
+                    errors.push(SchemaError::Parse {
+                        path: path.clone(),
+                        source,
+                    });
+                    continue;
+                }
+            };
+            match self.check(Some(path), &value) {
+                Ok(()) => {
+                    values.insert(name.clone(), value);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(values)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn check(&self, path: Option<&Path>, value: &Value) -> Result<(), SchemaError> {
+        let errors: Vec<String> = self
+            .validator
+            .iter_errors(value)
+            .map(|e| format!("at '{}': {}", e.instance_path(), e))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(SchemaError::Invalid {
+                path: path.map(Path::to_path_buf),
+                errors,
+            })
+        }
+    }
+}
+
+/// Parse a fragment as a JSON document, for use with [`SchemaValidator::validate_fragments`].
+pub fn parse_json(_name: &OsStr, reader: &mut BufReader<File>) -> serde_json::Result<Value> {
+    serde_json::from_reader(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TREEDIR: &str = "tests/fixtures/tree-schema";
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "interval": { "type": "integer", "minimum": 1 },
+                "enabled": { "type": "boolean" }
+            },
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn schema_per_fragment_errors() {
+        let validator = SchemaValidator::new(&schema()).unwrap();
+        let fragments = crate::scan([TREEDIR], "liboverdrop.d", &["json"], false);
+
+        let errors = validator
+            .validate_fragments(&fragments, parse_json)
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        match &errors[0] {
+            SchemaError::Invalid { path, errors } => {
+                assert!(path.as_ref().unwrap().ends_with("20-bad-type.json"));
+                assert_eq!(errors.len(), 1);
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        assert!(
+            matches!(&errors[1], SchemaError::Parse { path, .. } if path.ends_with("30-broken.json"))
+        );
+    }
+
+    #[test]
+    fn schema_valid_and_merged() {
+        let validator = SchemaValidator::new(&schema()).unwrap();
+        let mut fragments = crate::scan([TREEDIR], "liboverdrop.d", &["json"], false);
+        fragments.retain(|name, _| name == "10-good.json");
+
+        let values = validator
+            .validate_fragments(&fragments, parse_json)
+            .unwrap();
+        assert_eq!(values[OsStr::new("10-good.json")], json!({"interval": 5}));
+
+        validator
+            .validate_merged(&json!({"interval": 1, "enabled": true}))
+            .unwrap();
+        let err = validator
+            .validate_merged(&json!({"unknown": 1}))
+            .unwrap_err();
+        assert!(matches!(err, SchemaError::Invalid { path: None, .. }));
+
+        let err = SchemaValidator::new(&json!({"type": "no-such-type"})).unwrap_err();
+        assert!(matches!(err, SchemaError::InvalidSchema(_)));
+    }
+}
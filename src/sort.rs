@@ -0,0 +1,153 @@
+//! Version-aware ordering of fragment names.
+
+use std::cmp::Ordering;
+use std::ffi::OsStr;
+
+/// Compare fragment names with a version-aware ordering, like systemd does for drop-ins.
+///
+/// Numeric segments are compared by value, so that `2-foo.conf` sorts before
+/// `10-foo.conf`, following the same rules as systemd's `strverscmp_improved()`
+/// (e.g. `~` sorts before anything, even the end of the name, as in `1.0~rc1`).
+/// Names which are equivalent under these rules (e.g. `01-a` and `1-a`) are
+/// ordered as plain strings, so that this is a total order.
+///
+/// As for extensions, non-UTF-8 names are lossily converted before comparing.
+///
+/// ```rust
+/// # use std::ffi::OsStr;
+/// let mut names = ["10-foo.conf", "2-foo.conf", "1.0~rc1.conf", "1.0.conf"].map(OsStr::new);
+/// names.sort_by(|a, b| liboverdrop::version_cmp(a, b));
+/// assert_eq!(names, ["1.0~rc1.conf", "1.0.conf", "2-foo.conf", "10-foo.conf"]);
+/// ```
+pub fn version_cmp(a: &OsStr, b: &OsStr) -> Ordering {
+    let (lossy_a, lossy_b) = (a.to_string_lossy(), b.to_string_lossy());
+    strverscmp(lossy_a.as_bytes(), lossy_b.as_bytes()).then_with(|| a.cmp(b))
+}
+
+/// Whether `c` takes part in version comparisons, other characters are skipped.
+fn is_valid(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"~-^.".contains(&c)
+}
+
+fn strverscmp(mut a: &[u8], mut b: &[u8]) -> Ordering {
+    loop {
+        // Drop leading invalid characters.
+        a = &a[a.iter().position(|c| is_valid(*c)).unwrap_or(a.len())..];
+        b = &b[b.iter().position(|c| is_valid(*c)).unwrap_or(b.len())..];
+        let (ca, cb) = (a.first().copied(), b.first().copied());
+
+        let separator = [b'-', b'^', b'.']
+            .into_iter()
+            .find(|sep| ca == Some(*sep) || cb == Some(*sep));
+        if ca == Some(b'~') || cb == Some(b'~') {
+            // `~` sorts before everything, even the end of the string (for pre-releases).
+            if ca != Some(b'~') {
+                return Ordering::Greater;
+            }
+            if cb != Some(b'~') {
+                return Ordering::Less;
+            }
+        } else if ca.is_none() || cb.is_none() {
+            // If at least one string reaches the end, then the longer one is newer.
+            return ca.cmp(&cb);
+        } else if let Some(sep) = separator {
+            // Separators sort before anything else, but the end of the string.
+            if ca != Some(sep) {
+                return Ordering::Greater;
+            }
+            if cb != Some(sep) {
+                return Ordering::Less;
+            }
+        } else {
+            let res;
+            if a[0].is_ascii_digit() || b[0].is_ascii_digit() {
+                // Longer numeric segments are newer (and numeric segments are newer
+                // than alphabetical ones), leading zeros aside.
+                let (na, ra) = split_segment(a, b'0', u8::is_ascii_digit);
+                let (nb, rb) = split_segment(b, b'0', u8::is_ascii_digit);
+                res = na.len().cmp(&nb.len()).then_with(|| na.cmp(nb));
+                (a, b) = (ra, rb);
+            } else {
+                let (sa, ra) = split_segment(a, 0, u8::is_ascii_alphabetic);
+                let (sb, rb) = split_segment(b, 0, u8::is_ascii_alphabetic);
+                res = sa.cmp(sb);
+                (a, b) = (ra, rb);
+            }
+            if res != Ordering::Equal {
+                return res;
+            }
+            continue;
+        }
+        a = &a[1..];
+        b = &b[1..];
+    }
+}
+
+/// Split the leading segment of `s` matching `pred`, skipping any leading `skip` bytes
+/// (pass `0` to skip nothing).
+fn split_segment(mut s: &[u8], skip: u8, pred: fn(&u8) -> bool) -> (&[u8], &[u8]) {
+    while skip != 0 && s.first() == Some(&skip) {
+        s = &s[1..];
+    }
+    let len = s.iter().position(|c| !pred(c)).unwrap_or(s.len());
+    s.split_at(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmp(a: &str, b: &str) -> Ordering {
+        strverscmp(a.as_bytes(), b.as_bytes())
+    }
+
+    #[test]
+    fn version_ordering() {
+        let sorted = [
+            "~1",
+            "",
+            "ab",
+            "abb",
+            "abc",
+            "0001",
+            "002",
+            "12",
+            "122",
+            "122.9",
+            "123~rc1",
+            "123",
+            "123-a",
+            "123-a.1",
+            "123-a1",
+            "123-a1.1",
+            "123-3",
+            "123-3.1",
+            "123^patch1",
+            "123^1",
+            "123.a-1",
+            "123.1-1",
+            "123a",
+            "123a-a",
+            "123a-1",
+            "123a.a",
+            "123a.1",
+            "123b-a",
+            "124",
+        ];
+        for (i, a) in sorted.iter().enumerate() {
+            for (j, b) in sorted.iter().enumerate() {
+                assert_eq!(cmp(a, b), i.cmp(&j), "comparing '{}' and '{}'", a, b);
+            }
+        }
+
+        assert_eq!(cmp("0001", "1"), Ordering::Equal);
+        assert_eq!(
+            version_cmp(OsStr::new("01-a"), OsStr::new("1-a")),
+            Ordering::Less
+        );
+        assert_eq!(
+            version_cmp(OsStr::new("2-foo.conf"), OsStr::new("10-foo.conf")),
+            Ordering::Less
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Fragment contents, from either the filesystem or memory.
+
+use crate::{Fragment, ScanError};
+use std::ffi::{OsStr, OsString};
+use std::io::{self, BufRead, BufReader, Read};
+use std::sync::Arc;
+
+/// The source of a unique configuration fragment.
+///
+/// Fragments are usually files found while scanning directories, but callers can
+/// also [inject](crate::Overdrop::inject) synthetic fragments held in memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// A fragment file.
+    File(Fragment),
+    /// A synthetic fragment, with its contents.
+    Memory(Arc<[u8]>),
+}
+
+impl Source {
+    /// Return the fragment file, if this is not a synthetic fragment.
+    pub fn fragment(&self) -> Option<&Fragment> {
+        match self {
+            Source::File(fragment) => Some(fragment),
+            Source::Memory(_) => None,
+        }
+    }
+
+    /// Open the fragment contents for reading.
+    ///
+    /// Fragment files are opened with [`Fragment::open`].
+    pub fn open(&self) -> io::Result<Box<dyn BufRead + '_>> {
+        Ok(match self {
+            Source::File(fragment) => Box::new(BufReader::new(fragment.open()?)),
+            Source::Memory(contents) => Box::new(&contents[..]),
+        })
+    }
+
+    /// Size of the fragment contents, in bytes (as of the scan, for fragment files).
+    pub(crate) fn len(&self) -> u64 {
+        match self {
+            Source::File(fragment) => fragment.len(),
+            Source::Memory(contents) => contents.len() as u64,
+        }
+    }
+}
+
+/// A size limit for reading a fragment.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum SizeLimit {
+    /// The limit for each fragment.
+    Fragment(u64),
+    /// The bytes left of the limit for all fragments, and that limit.
+    Total(u64, u64),
+}
+
+impl SizeLimit {
+    /// Number of bytes which can be read.
+    pub(crate) fn bytes(self) -> u64 {
+        match self {
+            SizeLimit::Fragment(limit) | SizeLimit::Total(limit, _) => limit,
+        }
+    }
+
+    /// The error for fragment `name` going over this limit.
+    pub(crate) fn error(self, name: impl Into<OsString>) -> ScanError {
+        let name = name.into();
+        match self {
+            SizeLimit::Fragment(limit) => ScanError::FragmentTooLarge { name, limit },
+            SizeLimit::Total(_, limit) => ScanError::TotalTooLarge { name, limit },
+        }
+    }
+}
+
+/// A reader failing once more than a [`SizeLimit`] would be read.
+///
+/// This guards against fragments growing after they were scanned. The error is of
+/// kind `InvalidData`, wrapping the [`ScanError`] for the limit.
+pub(crate) struct LimitedReader<'a> {
+    inner: Box<dyn BufRead + 'a>,
+    remaining: u64,
+    limit: SizeLimit,
+    name: &'a OsStr,
+}
+
+impl<'a> LimitedReader<'a> {
+    pub(crate) fn new(inner: Box<dyn BufRead + 'a>, limit: SizeLimit, name: &'a OsStr) -> Self {
+        Self {
+            inner,
+            remaining: limit.bytes(),
+            limit,
+            name,
+        }
+    }
+}
+
+impl Read for LimitedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for LimitedReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let buf = self.inner.fill_buf()?;
+        if buf.len() as u64 > self.remaining {
+            let error = self.limit.error(self.name);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+        Ok(buf)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.remaining -= amt as u64;
+        self.inner.consume(amt);
+    }
+}
@@ -0,0 +1,136 @@
+//! Per-directory scan status.
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Entries found while scanning a single directory.
+#[derive(Debug, Default)]
+pub(crate) struct DirScan {
+    /// Number of matching entries (fragments and masks).
+    pub(crate) matched: usize,
+    /// Files ignored because of their extension, if reporting was requested.
+    pub(crate) unmatched: Vec<OsString>,
+    /// Fragments rejected by the validator.
+    pub(crate) rejected: Vec<Rejection>,
+    /// Symlinks skipped, if reporting was requested.
+    pub(crate) symlinks: Vec<PathBuf>,
+}
+
+/// A fragment rejected by a validator, see [`Overdrop::validator`](crate::Overdrop::validator).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    path: PathBuf,
+    reason: String,
+}
+
+impl Rejection {
+    pub(crate) fn new(path: PathBuf, reason: String) -> Self {
+        Self { path, reason }
+    }
+
+    /// Path of the rejected fragment.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reason for the rejection, as reported by the validator.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rejected fragment '{}': {}",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+/// Status of a single directory, as seen while scanning.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirStatus {
+    path: PathBuf,
+    error: Option<io::ErrorKind>,
+    matched: usize,
+    unmatched: Vec<OsString>,
+    rejected: Vec<Rejection>,
+    symlinks: Vec<PathBuf>,
+}
+
+impl DirStatus {
+    pub(crate) fn new(path: PathBuf, res: io::Result<DirScan>) -> Self {
+        let (error, scan) = match res {
+            Ok(scan) => (None, scan),
+            Err(e) => (Some(e.kind()), DirScan::default()),
+        };
+        Self {
+            path,
+            error,
+            matched: scan.matched,
+            unmatched: scan.unmatched,
+            rejected: scan.rejected,
+            symlinks: scan.symlinks,
+        }
+    }
+
+    /// Record a fragment of this directory as rejected by the validator.
+    pub(crate) fn reject(&mut self, rejection: Rejection) {
+        self.rejected.push(rejection);
+    }
+
+    /// Path of the scanned directory (i.e. with the shared path joined onto the base directory).
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the directory exists.
+    ///
+    /// This is only `false` if the directory was not found; other errors
+    /// (e.g. permission denied) mean that it exists, but is not readable.
+    pub fn exists(&self) -> bool {
+        self.error != Some(io::ErrorKind::NotFound)
+    }
+
+    /// Whether the directory could be read.
+    pub fn readable(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Kind of the error encountered while opening the directory, if any.
+    pub fn error(&self) -> Option<io::ErrorKind> {
+        self.error
+    }
+
+    /// Number of entries which matched the scan filters (fragments and masks).
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+
+    /// Files which were present but ignored because they did not match the allowed extensions.
+    ///
+    /// This is only populated if [`Overdrop::report_unmatched`](crate::Overdrop::report_unmatched)
+    /// is enabled.
+    pub fn unmatched(&self) -> impl Iterator<Item = &OsStr> {
+        self.unmatched.iter().map(OsString::as_os_str)
+    }
+
+    /// Fragments which were skipped because the [validator](crate::Overdrop::validator) rejected them.
+    pub fn rejected(&self) -> &[Rejection] {
+        &self.rejected
+    }
+
+    /// Symlinks which were skipped, as they are neither masks nor followed.
+    ///
+    /// This is only populated if [`Overdrop::symlinks`](crate::Overdrop::symlinks) is
+    /// [`SymlinkPolicy::Warn`](crate::SymlinkPolicy::Warn) or
+    /// [`SymlinkPolicy::Error`](crate::SymlinkPolicy::Error).
+    pub fn symlinks(&self) -> &[PathBuf] {
+        &self.symlinks
+    }
+}
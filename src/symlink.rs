@@ -0,0 +1,293 @@
+//! Handling of symlinks which are not masks.
+
+use crate::{
+    classify_entry, is_mask_link, resolve_link_target, DirOptions, DirScan, Entry, EntryKind,
+    Fragment, FragmentSource, MAX_MASK_HOPS,
+};
+use log::{trace, warn};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How symlinks found in scanned directories are handled, unless they are masks.
+///
+/// Symlinks to `/dev/null` always mask fragments. Other symlinks (pointing to regular
+/// files elsewhere, to directories, or nowhere) are ignored by default; see
+/// [`Overdrop::symlinks`](crate::Overdrop::symlinks), and
+/// [`Overdrop::broken_symlinks_mask`](crate::Overdrop::broken_symlinks_mask) for broken
+/// symlinks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SymlinkPolicy {
+    /// Skip symlinks silently, as if they did not exist.
+    #[default]
+    Ignore,
+    /// Include symlinks to regular files as fragments, under the name of the symlink; skip others.
+    Follow,
+    /// Skip symlinks, logging a warning and listing them in [`DirStatus::symlinks`](crate::DirStatus::symlinks).
+    Warn,
+    /// Fail the scan with [`ScanError::Symlink`](crate::ScanError::Symlink).
+    Error,
+}
+
+/// How scanned directories are handled if they are (or are below) a symlink.
+///
+/// Some distributions replace e.g. `/etc/foo/config.d` with a symlink to a shared
+/// location. Such directories are followed by default; see
+/// [`Overdrop::dir_symlinks`](crate::Overdrop::dir_symlinks). Symlink loops are never
+/// followed: the directory is then reported as unreadable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DirSymlinkPolicy {
+    /// Follow symlinked directories.
+    #[default]
+    Follow,
+    /// Only follow symlinked directories pointing to the same filesystem as their base directory.
+    ///
+    /// This is only enforced on Unix; elsewhere, symlinked directories are followed.
+    SameDevice,
+    /// Never follow symlinked directories, e.g. for hardened deployments.
+    Forbid,
+}
+
+/// Check that `dir`, below `base`, may be scanned under `policy`.
+///
+/// Only the components of `dir` below `base` are checked, as the base directories
+/// themselves are given by the caller. Directories which may not be scanned are
+/// reported as a permission error.
+pub(crate) fn check_dir_symlinks(
+    policy: DirSymlinkPolicy,
+    base: &Path,
+    dir: &Path,
+) -> io::Result<()> {
+    if policy == DirSymlinkPolicy::Follow {
+        return Ok(());
+    }
+    let symlinked = dir
+        .ancestors()
+        .take_while(|path| *path != base && path.starts_with(base))
+        .any(|path| fs::symlink_metadata(path).map_or(false, |meta| meta.file_type().is_symlink()));
+    if !symlinked {
+        return Ok(());
+    }
+    let allowed = match policy {
+        DirSymlinkPolicy::SameDevice => same_device(base, dir)?,
+        _ => false,
+    };
+    if allowed {
+        return Ok(());
+    }
+    warn!("Not following symlinked directory '{}'", dir.display());
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("symlinked directory '{}' not allowed", dir.display()),
+    ))
+}
+
+/// Whether `a` and `b` (following symlinks) reside on the same filesystem.
+#[cfg(unix)]
+fn same_device(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(fs::metadata(a)?.dev() == fs::metadata(b)?.dev())
+}
+
+/// Whether `a` and `b` (following symlinks) reside on the same filesystem.
+#[cfg(not(unix))]
+fn same_device(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Classify the symlink at `fpath`, according to `options`.
+///
+/// Symlinks which are skipped are recorded in `scan` if the policy reports them.
+pub(crate) fn classify_symlink<S>(
+    source: &S,
+    fpath: PathBuf,
+    options: &DirOptions,
+    scan: &mut DirScan,
+) -> Option<Entry<Fragment>>
+where
+    S: FragmentSource + ?Sized,
+{
+    let target = source.read_link(&fpath).ok()?;
+    // A devnull symlink is a special case to ignore previous file-names.
+    if is_mask_link(&fpath, &target, options.root.as_deref(), |link| {
+        source.read_link(link)
+    }) {
+        trace!("Nulled config file '{}'", fpath.display());
+        #[cfg(feature = "tracing")]
+        tracing::trace!(path = %fpath.display(), "found mask");
+        return Some(Entry::Mask);
+    }
+
+    match followed_info(source, &fpath) {
+        Ok((kind @ EntryKind::File { .. }, meta)) if options.symlinks == SymlinkPolicy::Follow => {
+            let root = options.root.as_deref();
+            return classify_entry(source, fpath, kind, meta.as_ref(), root)
+                .map(|entry| entry.map(Fragment::followed));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && options.broken_symlinks_mask => {
+            trace!("Broken symlink '{}' masks config file", fpath.display());
+            #[cfg(feature = "tracing")]
+            tracing::trace!(path = %fpath.display(), "found mask (broken symlink)");
+            return Some(Entry::Mask);
+        }
+        _ => {}
+    }
+
+    if let SymlinkPolicy::Warn | SymlinkPolicy::Error = options.symlinks {
+        warn!(
+            "Ignoring symlink '{}' to '{}'",
+            fpath.display(),
+            target.display()
+        );
+        scan.symlinks.push(fpath);
+    }
+    None
+}
+
+/// Return the kind of the target of the symlink at `path` in `source`, with its metadata if available.
+///
+/// Sources without metadata are resolved lexically, as [`MemoryTree`](crate::MemoryTree) does.
+fn followed_info<S>(source: &S, path: &Path) -> io::Result<(EntryKind, Option<fs::Metadata>)>
+where
+    S: FragmentSource + ?Sized,
+{
+    if let Some(meta) = source.metadata(path, true) {
+        let meta = meta?;
+        return Ok((EntryKind::of(&meta), Some(meta)));
+    }
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_MASK_HOPS {
+        match source.entry_kind(&path)? {
+            EntryKind::Symlink => path = resolve_link_target(&path, &source.read_link(&path)?),
+            kind => return Ok((kind, None)),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "too many levels of symbolic links",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::testing::TreeBuilder;
+    use crate::{DirSymlinkPolicy, Overdrop, ScanError, SymlinkPolicy};
+    use std::ffi::OsStr;
+
+    fn tree() -> TreeBuilder {
+        TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-linked.conf", "vendor")
+            .fragment("usr/lib/svc.d/20-broken.conf", "vendor")
+            .fragment("usr/lib/svc.d/30-dir.conf", "vendor")
+            .fragment("shared/linked.conf", "shared")
+            .base_dir("shared/dir.conf")
+            .symlink("etc/svc.d/10-linked.conf", "../../shared/linked.conf")
+            .symlink("etc/svc.d/20-broken.conf", "../../shared/missing.conf")
+            .symlink("etc/svc.d/30-dir.conf", "../../shared/dir.conf")
+    }
+
+    #[test]
+    fn symlink_policies() {
+        let tree = tree();
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "etc"]));
+        let vendor = |name: &str| tree.path("usr/lib/svc.d").join(name);
+
+        // By default, symlinks are ignored and lower-priority fragments take over.
+        let fragments = scanner.scan().unwrap();
+        assert_eq!(fragments.len(), 3);
+        assert!(fragments.values().all(|path| path.starts_with(vendor(""))));
+
+        let fragments = scanner
+            .clone()
+            .symlinks(SymlinkPolicy::Follow)
+            .scan()
+            .unwrap();
+        assert_eq!(
+            fragments[OsStr::new("10-linked.conf")],
+            tree.path("etc/svc.d/10-linked.conf")
+        );
+        assert_eq!(
+            fragments[OsStr::new("20-broken.conf")],
+            vendor("20-broken.conf")
+        );
+        assert_eq!(fragments[OsStr::new("30-dir.conf")], vendor("30-dir.conf"));
+
+        let fragments = scanner
+            .clone()
+            .symlinks(SymlinkPolicy::Follow)
+            .broken_symlinks_mask(true)
+            .scan()
+            .unwrap();
+        assert_eq!(
+            fragments.keys().collect::<Vec<_>>(),
+            ["10-linked.conf", "30-dir.conf"]
+        );
+
+        let (_, statuses) = scanner
+            .clone()
+            .symlinks(SymlinkPolicy::Warn)
+            .scan_with_status()
+            .unwrap();
+        assert!(statuses[0].symlinks().is_empty());
+        assert_eq!(statuses[1].symlinks().len(), 3);
+
+        let err = scanner
+            .symlinks(SymlinkPolicy::Error)
+            .broken_symlinks_mask(true)
+            .scan()
+            .unwrap_err();
+        assert!(
+            matches!(&err, ScanError::Symlink(path) if path.starts_with(tree.path("etc"))),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn symlinked_dirs() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .fragment("usr/share/svc.d/10-a.conf", "shared")
+            .symlink("etc/svc.d", "../usr/share/svc.d")
+            .symlink("run/svc.d", "svc.d");
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "etc", "run"]));
+
+        let (fragments, statuses) = scanner.scan_with_status().unwrap();
+        assert_eq!(
+            fragments.get(OsStr::new("10-a.conf")).map(|f| f.path()),
+            Some(tree.path("etc/svc.d/10-a.conf").as_path())
+        );
+        // Loops are reported as unreadable directories.
+        assert!(statuses[2].exists() && !statuses[2].readable());
+
+        let fragments = scanner
+            .clone()
+            .dir_symlinks(DirSymlinkPolicy::SameDevice)
+            .scan()
+            .unwrap();
+        assert_eq!(
+            fragments[OsStr::new("10-a.conf")],
+            tree.path("etc/svc.d/10-a.conf")
+        );
+
+        let scanner = scanner.dir_symlinks(DirSymlinkPolicy::Forbid);
+        let (fragments, statuses) = scanner.scan_with_status().unwrap();
+        assert_eq!(
+            fragments.get(OsStr::new("10-a.conf")).map(|f| f.path()),
+            Some(tree.path("usr/lib/svc.d/10-a.conf").as_path())
+        );
+        assert_eq!(
+            statuses[1].error(),
+            Some(std::io::ErrorKind::PermissionDenied)
+        );
+
+        // Changes through a forbidden directory symlink are not picked up either.
+        let tree = tree.fragment("usr/share/svc.d/20-b.conf", "shared");
+        let mut fragments = scanner.scan_fragments().unwrap();
+        let changed = scanner.rescan_changed(&mut fragments, [tree.path("etc/svc.d/20-b.conf")]);
+        assert!(changed.is_empty());
+        assert_eq!(fragments, scanner.scan_fragments().unwrap());
+    }
+}
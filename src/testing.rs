@@ -0,0 +1,181 @@
+//! Helpers for building temporary fragment trees in tests.
+//!
+//! This module is available with the `testing` feature, and is meant for the tests
+//! of services using this crate (as a dev-dependency feature):
+//!
+//! ```rust
+//! # use liboverdrop::testing::TreeBuilder;
+//! let tree = TreeBuilder::new()
+//!     .fragment("usr/lib/my-crate/config.d/10-a.conf", "vendor a")
+//!     .fragment("etc/my-crate/config.d/10-a.conf", "admin a")
+//!     .base_dir("run");
+//! let fragments = liboverdrop::scan(tree.base_dirs(["usr/lib", "etc", "run"]), "my-crate/config.d", &["conf"], false);
+//! assert_eq!(
+//!     fragments[std::ffi::OsStr::new("10-a.conf")],
+//!     tree.path("etc/my-crate/config.d/10-a.conf")
+//! );
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counter for unique tree names within the same process.
+static TREES: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory tree of configuration fragments, removed when dropped.
+///
+/// All paths are relative to the root of the tree. Setup failures panic, as this is
+/// meant for tests only.
+#[derive(Debug)]
+pub struct TreeBuilder {
+    root: PathBuf,
+}
+
+impl TreeBuilder {
+    /// Create an empty tree, in a new directory under [`std::env::temp_dir`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the directory cannot be created.
+    pub fn new() -> Self {
+        let name = format!(
+            "liboverdrop-tree-{}-{}",
+            std::process::id(),
+            TREES.fetch_add(1, Ordering::Relaxed)
+        );
+        let root = std::env::temp_dir().join(name);
+        // Leftovers from a previous (aborted) run with the same PID.
+        let _ = fs::remove_dir_all(&root);
+        create_dir(&root);
+        Self { root }
+    }
+
+    /// Root directory of the tree.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Return the absolute location of `path` within the tree.
+    pub fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Return the absolute locations of `base_dirs` within the tree, e.g. for [`scan`](crate::scan).
+    pub fn base_dirs<BdS: AsRef<Path>, BdI: IntoIterator<Item = BdS>>(
+        &self,
+        base_dirs: BdI,
+    ) -> Vec<PathBuf> {
+        base_dirs.into_iter().map(|dir| self.path(dir)).collect()
+    }
+
+    /// Create a (possibly empty) directory at `path`, e.g. a base directory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the directory cannot be created.
+    #[must_use]
+    pub fn base_dir(self, path: impl AsRef<Path>) -> Self {
+        create_dir(&self.path(path));
+        self
+    }
+
+    /// Write a fragment (or a dotfile) at `path`, creating its parent directories.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the fragment cannot be written.
+    #[must_use]
+    pub fn fragment(self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Self {
+        let path = self.parent_dirs(path.as_ref());
+        if let Err(e) = fs::write(&path, contents) {
+            panic!("failed to write '{}': {}", path.display(), e);
+        }
+        self
+    }
+
+    /// Create a mask at `path`, i.e. a symlink to `/dev/null`, creating its parent directories.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the symlink cannot be created.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn mask(self, path: impl AsRef<Path>) -> Self {
+        self.symlink(path, crate::DEVNULL)
+    }
+
+    /// Create a symlink at `path` pointing to `target`, creating its parent directories.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the symlink cannot be created.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn symlink(self, path: impl AsRef<Path>, target: impl AsRef<Path>) -> Self {
+        let path = self.parent_dirs(path.as_ref());
+        if let Err(e) = std::os::unix::fs::symlink(target, &path) {
+            panic!("failed to create '{}': {}", path.display(), e);
+        }
+        self
+    }
+
+    fn parent_dirs(&self, path: &Path) -> PathBuf {
+        let path = self.path(path);
+        if let Some(parent) = path.parent() {
+            create_dir(parent);
+        }
+        path
+    }
+}
+
+impl Default for TreeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TreeBuilder {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn create_dir(path: &Path) {
+    if let Err(e) = fs::create_dir_all(path) {
+        panic!("failed to create '{}': {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan;
+
+    #[test]
+    #[cfg(unix)]
+    fn build_tree() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor a")
+            .fragment("usr/lib/svc.d/20-b.conf", "vendor b")
+            .fragment("usr/lib/svc.d/.30-c.conf", "hidden c")
+            .fragment("etc/svc.d/10-a.conf", "admin a")
+            .mask("etc/svc.d/20-b.conf")
+            .base_dir("run/svc.d");
+        let root = tree.root().to_path_buf();
+        let dirs = tree.base_dirs(["usr/lib", "etc", "run"]);
+        assert!(dirs[2].join("svc.d").is_dir());
+
+        let fragments = scan(&dirs, "svc.d", &["conf"], true);
+        assert_eq!(fragments.len(), 1);
+        assert_eq!(
+            fs::read_to_string(&fragments[std::ffi::OsStr::new("10-a.conf")]).unwrap(),
+            "admin a"
+        );
+        assert_eq!(scan(&dirs, "svc.d", &["conf"], false).len(), 2);
+
+        assert_ne!(TreeBuilder::new().root(), root);
+        drop(tree);
+        assert!(!root.exists());
+    }
+}
@@ -0,0 +1,175 @@
+//! Ownership and permission checks on configuration fragments.
+
+use std::fs::Metadata;
+
+/// Ownership and permission requirements for fragments to be trusted.
+///
+/// Like sshd or sudo do for their configuration, this only accepts entries (fragments
+/// and masks) owned by a given user (root by default), and optionally group, which
+/// are not writable by other users. Entries are checked by their own (non-followed)
+/// metadata. Untrusted entries are rejected, as if they did not exist, and listed in
+/// [`DirStatus::rejected`](crate::DirStatus::rejected); see
+/// [`Overdrop::trust`](crate::Overdrop::trust).
+///
+/// ```rust,no_run
+/// # use liboverdrop::{Overdrop, TrustPolicy};
+/// let fragments = Overdrop::new("my-crate/config.d")
+///     .trust(TrustPolicy::new().gid(0))
+///     .fail_on_rejection(true)
+///     .scan()?;
+/// # Ok::<(), liboverdrop::ScanError>(())
+/// ```
+///
+/// Ownership can only be checked on Unix; elsewhere, all entries are rejected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrustPolicy {
+    /// Required owner, i.e. root by default.
+    uid: u32,
+    gid: Option<u32>,
+    group_writable: bool,
+}
+
+impl TrustPolicy {
+    /// Create the default policy: owned by root, in any group, and only writable by the owner.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require entries to be owned by the user `uid`.
+    #[must_use]
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.uid = uid;
+        self
+    }
+
+    /// Require entries to be owned by the group `gid`.
+    #[must_use]
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Whether entries may be writable by their group (by default, they may not).
+    ///
+    /// Entries writable by other users are never trusted.
+    #[must_use]
+    pub fn group_writable(mut self, allowed: bool) -> Self {
+        self.group_writable = allowed;
+        self
+    }
+
+    /// Check the entry with (non-followed) metadata `meta`, returning the reason for distrusting it, if any.
+    #[cfg(unix)]
+    pub(crate) fn check(&self, meta: &Metadata) -> Result<(), String> {
+        use std::os::unix::fs::MetadataExt;
+
+        if meta.uid() != self.uid {
+            return Err(format!("owned by uid {}, not {}", meta.uid(), self.uid));
+        }
+        if let Some(gid) = self.gid.filter(|gid| *gid != meta.gid()) {
+            return Err(format!("owned by gid {}, not {}", meta.gid(), gid));
+        }
+        // Permissions of symlinks are meaningless.
+        if meta.file_type().is_symlink() {
+            return Ok(());
+        }
+        let mode = meta.mode();
+        if mode & 0o002 != 0 {
+            return Err("writable by others".to_string());
+        }
+        if mode & 0o020 != 0 && !self.group_writable {
+            return Err("writable by group".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check the entry with (non-followed) metadata `meta`, returning the reason for distrusting it, if any.
+    #[cfg(not(unix))]
+    pub(crate) fn check(&self, _meta: &Metadata) -> Result<(), String> {
+        Err("ownership cannot be checked on this platform".to_string())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::testing::TreeBuilder;
+    use crate::{DirStatus, Overdrop, ScanError, TrustPolicy};
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[test]
+    fn trusted_fragments() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .fragment("usr/lib/svc.d/20-b.conf", "vendor")
+            .fragment("etc/svc.d/10-a.conf", "admin")
+            .fragment("etc/svc.d/20-b.conf", "admin")
+            .mask("etc/svc.d/30-c.conf");
+        let chmod = |path: &str, mode: u32| {
+            fs::set_permissions(tree.path(path), Permissions::from_mode(mode)).unwrap();
+        };
+        chmod("usr/lib/svc.d/10-a.conf", 0o644);
+        chmod("usr/lib/svc.d/20-b.conf", 0o644);
+        chmod("etc/svc.d/10-a.conf", 0o664);
+        chmod("etc/svc.d/20-b.conf", 0o646);
+        let meta = fs::metadata(tree.path("etc")).unwrap();
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "etc"]));
+        let policy = TrustPolicy::new().uid(meta.uid()).gid(meta.gid());
+
+        // The group-writable admin `10-a.conf` and the world-writable `20-b.conf`
+        // are rejected, so that the vendor ones take over.
+        let (fragments, statuses) = scanner.clone().trust(policy).scan_with_status().unwrap();
+        let paths = fragments.into_paths();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.values().all(|p| p.starts_with(tree.path("usr/lib"))));
+        let reasons: Vec<_> = statuses
+            .iter()
+            .flat_map(DirStatus::rejected)
+            .map(|r| r.reason())
+            .collect();
+        assert_eq!(reasons.len(), 2);
+        assert!(reasons.contains(&"writable by group"));
+        assert!(reasons.contains(&"writable by others"));
+
+        let fragments = scanner
+            .clone()
+            .trust(policy.group_writable(true))
+            .scan()
+            .unwrap();
+        assert_eq!(fragments.len(), 2);
+        assert!(fragments[std::ffi::OsStr::new("10-a.conf")].starts_with(tree.path("etc")));
+
+        let other = TrustPolicy::new().uid(meta.uid().wrapping_add(1));
+        assert!(scanner.clone().trust(other).scan().unwrap().is_empty());
+        let err = scanner
+            .trust(other)
+            .fail_on_rejection(true)
+            .scan()
+            .unwrap_err();
+        assert!(matches!(err, ScanError::Rejected(_)), "{}", err);
+    }
+
+    #[test]
+    fn trusted_rescan_changed() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "vendor")
+            .base_dir("etc/svc.d");
+        let path = tree.path("usr/lib/svc.d/10-a.conf");
+        fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+        let meta = fs::metadata(tree.path("etc")).unwrap();
+        let scanner = Overdrop::new("svc.d")
+            .base_dirs(tree.base_dirs(["usr/lib", "etc"]))
+            .trust(TrustPolicy::new().uid(meta.uid()).gid(meta.gid()));
+        let mut fragments = scanner.scan_fragments().unwrap();
+
+        // An untrusted override does not take over after a change event either.
+        let tree = tree.fragment("etc/svc.d/10-a.conf", "admin");
+        let untrusted = tree.path("etc/svc.d/10-a.conf");
+        fs::set_permissions(&untrusted, Permissions::from_mode(0o646)).unwrap();
+        assert!(scanner
+            .rescan_changed(&mut fragments, [&untrusted])
+            .is_empty());
+        assert_eq!(fragments, scanner.scan_fragments().unwrap());
+        assert_eq!(fragments.get("10-a.conf").unwrap().path(), path);
+    }
+}
@@ -0,0 +1,337 @@
+//! Scanning fragments from other sources than the local filesystem.
+
+use crate::{
+    read_dir_entries, resolve_link_target, DirOptions, Fragment, LayerPolicy, Resolver, DEVNULL,
+    MAX_MASK_HOPS,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The kind of an entry, as reported by a [`FragmentSource`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EntryKind {
+    /// A regular file, with its size in bytes.
+    File {
+        /// Size of the file, in bytes.
+        len: u64,
+    },
+    /// A directory.
+    Dir,
+    /// A symbolic link.
+    Symlink,
+    /// Anything else (e.g. a socket).
+    Other,
+}
+
+impl EntryKind {
+    /// The kind of an entry with metadata `meta`.
+    pub(crate) fn of(meta: &fs::Metadata) -> Self {
+        let file_type = meta.file_type();
+        if file_type.is_file() {
+            EntryKind::File { len: meta.len() }
+        } else if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::Other
+        }
+    }
+}
+
+/// The entries listed by [`FragmentSource::list`].
+pub type EntryNames<'a> = Box<dyn Iterator<Item = io::Result<OsString>> + 'a>;
+
+/// A tree of directories and files which configuration fragments can be scanned from.
+///
+/// This abstracts the few filesystem operations needed by [`scan_source`], so that
+/// fragments can be scanned e.g. from an archive or an in-memory [`MemoryTree`]
+/// without extracting them to disk first. [`StdFs`] implements it on top of `std::fs`,
+/// and all scans of the local filesystem go through it.
+pub trait FragmentSource {
+    /// List the names of the entries in the directory at `dir`.
+    ///
+    /// Entries which cannot be read are listed as errors, and skipped by scans.
+    fn list(&self, dir: &Path) -> io::Result<EntryNames<'_>>;
+
+    /// Return the kind of the entry at `path`, without following symlinks.
+    fn entry_kind(&self, path: &Path) -> io::Result<EntryKind>;
+
+    /// Open the file at `path` for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>>;
+
+    /// Return the target of the symlink at `path`.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Return the metadata of the entry at `path` (following symlinks if `follow`), for local sources.
+    ///
+    /// Fragments scanned with metadata expose it via [`Fragment::metadata`], and
+    /// metadata-based checks (e.g. [`TrustPolicy`](crate::TrustPolicy)) need it.
+    /// The default returns `None`, for sources which are not on the local filesystem.
+    fn metadata(&self, path: &Path, follow: bool) -> Option<io::Result<fs::Metadata>> {
+        let _ = (path, follow);
+        None
+    }
+}
+
+/// The local filesystem, as a [`FragmentSource`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdFs;
+
+impl FragmentSource for StdFs {
+    fn list(&self, dir: &Path) -> io::Result<EntryNames<'_>> {
+        let entries = fs::read_dir(dir)?;
+        Ok(Box::new(
+            entries.map(|entry| entry.map(|entry| entry.file_name())),
+        ))
+    }
+
+    fn entry_kind(&self, path: &Path) -> io::Result<EntryKind> {
+        Ok(EntryKind::of(&fs::symlink_metadata(path)?))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn metadata(&self, path: &Path, follow: bool) -> Option<io::Result<fs::Metadata>> {
+        Some(match follow {
+            true => fs::metadata(path),
+            false => fs::symlink_metadata(path),
+        })
+    }
+}
+
+/// An in-memory tree of files and symlinks, as a [`FragmentSource`].
+///
+/// Parent directories are created implicitly. This is mostly meant for tests:
+///
+/// ```rust
+/// # use liboverdrop::MemoryTree;
+/// let tree = MemoryTree::new()
+///     .file("/usr/lib/my-crate/config.d/10-a.conf", "vendor a")
+///     .file("/usr/lib/my-crate/config.d/20-b.conf", "vendor b")
+///     .mask("/etc/my-crate/config.d/20-b.conf");
+/// let fragments =
+///     liboverdrop::scan_source(&tree, ["/usr/lib", "/etc"], "my-crate/config.d", &["conf"], false);
+/// assert_eq!(fragments.keys().collect::<Vec<_>>(), ["10-a.conf"]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MemoryTree {
+    nodes: BTreeMap<PathBuf, Node>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    File(Arc<[u8]>),
+    Symlink(PathBuf),
+}
+
+impl MemoryTree {
+    /// Create an empty tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file at `path`, with the given contents.
+    #[must_use]
+    pub fn file(self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Self {
+        self.node(path.as_ref(), Node::File(contents.as_ref().into()))
+    }
+
+    /// Add a symlink at `path`, pointing to `target`.
+    #[must_use]
+    pub fn symlink(self, path: impl AsRef<Path>, target: impl AsRef<Path>) -> Self {
+        self.node(path.as_ref(), Node::Symlink(target.as_ref().to_path_buf()))
+    }
+
+    /// Add a mask at `path`, i.e. a symlink to `/dev/null`.
+    #[must_use]
+    pub fn mask(self, path: impl AsRef<Path>) -> Self {
+        self.symlink(path, DEVNULL)
+    }
+
+    /// Add an (empty) directory at `path`.
+    #[must_use]
+    pub fn dir(mut self, path: impl AsRef<Path>) -> Self {
+        self.dirs
+            .extend(path.as_ref().ancestors().map(Path::to_path_buf));
+        self
+    }
+
+    fn node(mut self, path: &Path, node: Node) -> Self {
+        if let Some(parent) = path.parent() {
+            self = self.dir(parent);
+        }
+        self.nodes.insert(path.to_path_buf(), node);
+        self
+    }
+
+    fn not_found() -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, "no such entry in memory tree")
+    }
+}
+
+impl FragmentSource for MemoryTree {
+    fn list(&self, dir: &Path) -> io::Result<EntryNames<'_>> {
+        if !self.dirs.contains(dir) {
+            return Err(Self::not_found());
+        }
+        let children = self.nodes.keys().chain(&self.dirs);
+        let dir = dir.to_path_buf();
+        Ok(Box::new(
+            children
+                .filter(move |path| path.parent() == Some(&dir))
+                .filter_map(|path| path.file_name().map(|name| Ok(name.to_os_string()))),
+        ))
+    }
+
+    fn entry_kind(&self, path: &Path) -> io::Result<EntryKind> {
+        match self.nodes.get(path) {
+            Some(Node::File(contents)) => Ok(EntryKind::File {
+                len: contents.len() as u64,
+            }),
+            Some(Node::Symlink(_)) => Ok(EntryKind::Symlink),
+            None if self.dirs.contains(path) => Ok(EntryKind::Dir),
+            None => Err(Self::not_found()),
+        }
+    }
+
+    /// Symlinks are followed, with relative targets resolved against the directory of
+    /// the link; chains of more than 40 symlinks (e.g. loops) fail to open.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        let mut path = path.to_path_buf();
+        for _ in 0..MAX_MASK_HOPS {
+            match self.nodes.get(&path) {
+                Some(Node::File(contents)) => return Ok(Box::new(&contents[..])),
+                Some(Node::Symlink(target)) => path = resolve_link_target(&path, target),
+                None => return Err(Self::not_found()),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "too many levels of symbolic links in memory tree",
+        ))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.nodes.get(path) {
+            Some(Node::Symlink(target)) => Ok(target.clone()),
+            Some(Node::File(_)) => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "not a symlink"))
+            }
+            None => Err(Self::not_found()),
+        }
+    }
+}
+
+/// Scan unique configuration fragments from a [`FragmentSource`].
+///
+/// This works like [`scan`](crate::scan), with all filesystem accesses going
+/// through `source`. Fragments can then be read with [`FragmentSource::open`].
+/// Symlinks in `source` which are not masks are skipped.
+pub fn scan_source<S, BdS, BdI, Sp, As>(
+    source: &S,
+    base_dirs: BdI,
+    shared_path: Sp,
+    allowed_extensions: &[As],
+    ignore_dotfiles: bool,
+) -> BTreeMap<OsString, PathBuf>
+where
+    S: FragmentSource + ?Sized,
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    As: AsRef<OsStr>,
+{
+    let shared_path = shared_path.as_ref();
+    let options = DirOptions::new(allowed_extensions, ignore_dotfiles);
+
+    let mut resolver = Resolver::new();
+    for dir in base_dirs {
+        let dir = dir.as_ref().join(shared_path);
+        // Missing or unreadable directories are simply skipped.
+        let _ = read_dir_entries(source, &dir, &options, |fname, entry| {
+            resolver.apply(
+                LayerPolicy::default(),
+                fname,
+                entry.map(Fragment::into_path),
+            )
+        });
+    }
+    resolver.into_map()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn std_fs_matches_scan() {
+        for (treedir, bases) in [
+            ("tests/fixtures/tree-basic", ["usr/lib", "run", "etc"]),
+            ("tests/fixtures/tree-mask", ["usr/lib", "etc", "run"]),
+        ] {
+            let dirs = bases.map(|d| Path::new(treedir).join(d));
+            for (extensions, ignore_dotfiles) in [(&[][..], false), (&["toml"][..], true)] {
+                let expected = crate::scan(&dirs, "liboverdrop.d", extensions, ignore_dotfiles);
+                let fragments =
+                    scan_source(&StdFs, &dirs, "liboverdrop.d", extensions, ignore_dotfiles);
+                assert_eq!(fragments, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn memory_tree() {
+        let tree = MemoryTree::new()
+            .file("usr/lib/conf.d/10-a.conf", "vendor a")
+            .file("usr/lib/conf.d/20-b.conf", "vendor b")
+            .file("usr/lib/conf.d/.hidden.conf", "hidden")
+            .dir("usr/lib/conf.d/30-c.conf")
+            .file("etc/conf.d/10-a.conf", "admin a")
+            .mask("etc/conf.d/20-b.conf")
+            .symlink("etc/conf.d/40-d.conf", "/usr/lib/conf.d/10-a.conf");
+        let fragments = scan_source(&tree, ["usr/lib", "etc", "run"], "conf.d", &["conf"], true);
+        assert_eq!(
+            fragments,
+            BTreeMap::from([("10-a.conf".into(), "etc/conf.d/10-a.conf".into())])
+        );
+
+        let mut contents = String::new();
+        tree.open(&fragments[OsStr::new("10-a.conf")])
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "admin a");
+        assert_eq!(tree.entry_kind(Path::new("usr")).unwrap(), EntryKind::Dir);
+        assert!(tree.list(Path::new("run")).is_err());
+        assert!(tree.metadata(Path::new("usr"), false).is_none());
+    }
+
+    #[test]
+    fn memory_tree_symlinks() {
+        let tree = MemoryTree::new()
+            .file("usr/lib/conf.d/10-a.conf", "vendor a")
+            .symlink("etc/conf.d/10-a.conf", "../../usr/lib/conf.d/10-a.conf")
+            .symlink("etc/conf.d/20-b.conf", "10-a.conf")
+            .symlink("etc/conf.d/30-loop.conf", "30-loop.conf");
+        let read = |path: &str| -> io::Result<String> {
+            let mut contents = String::new();
+            tree.open(Path::new(path))?.read_to_string(&mut contents)?;
+            Ok(contents)
+        };
+        assert_eq!(read("etc/conf.d/20-b.conf").unwrap(), "vendor a");
+        assert!(read("etc/conf.d/30-loop.conf").is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! Watching of configuration fragments, for live reloads.
+
+use crate::{is_mask_link, Fragment, FragmentSet, Overdrop, ScanError};
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// A change to the effective fragment set, as reported by watchers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WatchEvent {
+    /// A fragment appeared, for a filename without any effective fragment before.
+    FragmentAdded {
+        /// Filename of the fragment.
+        name: OsString,
+        /// The new effective fragment.
+        fragment: Fragment,
+    },
+    /// The effective fragment for a filename changed.
+    ///
+    /// This happens when a higher-priority fragment overrides it, when a lower-priority
+    /// fragment takes over after its removal, or when it is modified in place.
+    FragmentOverridden {
+        /// Filename of the fragment.
+        name: OsString,
+        /// The previous effective fragment.
+        previous: Fragment,
+        /// The new effective fragment.
+        fragment: Fragment,
+    },
+    /// The effective fragment for a filename was masked.
+    FragmentMasked {
+        /// Filename of the fragment.
+        name: OsString,
+        /// The previous effective fragment.
+        previous: Fragment,
+        /// Path of the effective mask.
+        mask: PathBuf,
+    },
+    /// The effective fragment for a filename was removed, with no other one left.
+    FragmentRemoved {
+        /// Filename of the fragment.
+        name: OsString,
+        /// The previous effective fragment.
+        previous: Fragment,
+    },
+}
+
+/// Describe the changes between `previous` and `current` fragment sets for `names`.
+pub(crate) fn watch_events(
+    scanner: &Overdrop,
+    previous: &FragmentSet,
+    current: &FragmentSet,
+    names: Vec<OsString>,
+) -> Vec<WatchEvent> {
+    // Masks are only looked up for removed fragments, highest priority first.
+    let dirs = scanner.search_dirs();
+    let root = scanner.options().root.as_deref();
+    let mask_of = |name: &OsString| {
+        dirs.iter().rev().map(|dir| dir.join(name)).find(|path| {
+            fs::read_link(path).map_or(false, |target| {
+                is_mask_link(path, &target, root, |l| fs::read_link(l))
+            })
+        })
+    };
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let event = match (previous.get(&name).cloned(), current.get(&name).cloned()) {
+                (None, Some(fragment)) => WatchEvent::FragmentAdded { name, fragment },
+                (Some(previous), Some(fragment)) => WatchEvent::FragmentOverridden {
+                    name,
+                    previous,
+                    fragment,
+                },
+                (Some(previous), None) => match mask_of(&name) {
+                    Some(mask) => WatchEvent::FragmentMasked {
+                        name,
+                        previous,
+                        mask,
+                    },
+                    None => WatchEvent::FragmentRemoved { name, previous },
+                },
+                (None, None) => return None,
+            };
+            Some(event)
+        })
+        .collect()
+}
+
+/// A poll-based watcher, detecting changes to the effective fragment set.
+///
+/// On each poll, all directories are rescanned and fragments are compared by
+/// path, modification time and size. This works consistently across all mount
+/// types, including those without reliable filesystem events (e.g. NFS, 9p, or
+/// virtiofs), at the cost of a full rescan per interval.
+#[derive(Clone, Debug)]
+pub struct PollWatcher {
+    scanner: Overdrop,
+    interval: Duration,
+    current: FragmentSet,
+}
+
+impl PollWatcher {
+    /// Create a watcher polling every `interval`, performing the initial scan.
+    pub fn new(scanner: Overdrop, interval: Duration) -> Result<Self, ScanError> {
+        let current = scanner.scan_fragments()?;
+        Ok(Self {
+            scanner,
+            interval,
+            current,
+        })
+    }
+
+    /// The effective fragment set, as of the last poll.
+    pub fn fragments(&self) -> &FragmentSet {
+        &self.current
+    }
+
+    /// Interval between polls in [`wait`](Self::wait).
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Rescan once, returning the filenames whose fragment was added, removed or replaced.
+    ///
+    /// On error (e.g. because of [strictness checks](Overdrop::require_nonempty)), the
+    /// previous fragment set is kept.
+    pub fn poll(&mut self) -> Result<Vec<OsString>, ScanError> {
+        let fresh = self.scanner.scan_fragments()?;
+        let changed = self.current.changed_names(&fresh);
+        self.current = fresh;
+        Ok(changed)
+    }
+
+    /// Rescan once, describing how the effective fragment set changed.
+    ///
+    /// This works like [`poll`](Self::poll), with one event per changed filename.
+    pub fn poll_events(&mut self) -> Result<Vec<WatchEvent>, ScanError> {
+        let fresh = self.scanner.scan_fragments()?;
+        let changed = self.current.changed_names(&fresh);
+        let events = watch_events(&self.scanner, &self.current, &fresh, changed);
+        self.current = fresh;
+        Ok(events)
+    }
+
+    /// Block until the fragment set changes, polling every [`interval`](Self::interval).
+    ///
+    /// Returns the changed filenames, as [`poll`](Self::poll) does.
+    pub fn wait(&mut self) -> Result<Vec<OsString>, ScanError> {
+        loop {
+            thread::sleep(self.interval);
+            let changed = self.poll()?;
+            if !changed.is_empty() {
+                return Ok(changed);
+            }
+        }
+    }
+}
+
+/// An inotify-based watcher, detecting changes to the effective fragment set.
+///
+/// All scanned directories are watched (or, for missing ones, their closest existing
+/// ancestor), and only the filenames affected by filesystem events are resolved again,
+/// as [`Overdrop::rescan_changed`] does. Unlike [`PollWatcher`], changes are seen as
+/// soon as they happen, but events may be missing on some mount types (e.g. network
+/// filesystems).
+///
+/// This is available on Linux with the `inotify` feature.
+#[cfg(all(target_os = "linux", feature = "inotify"))]
+#[derive(Debug)]
+pub struct InotifyWatcher {
+    scanner: Overdrop,
+    inotify: std::os::fd::OwnedFd,
+    /// Watched directories, by watch descriptor.
+    watches: Vec<(i32, PathBuf)>,
+    current: FragmentSet,
+}
+
+#[cfg(all(target_os = "linux", feature = "inotify"))]
+impl InotifyWatcher {
+    /// Create a watcher, performing the initial scan.
+    pub fn new(scanner: Overdrop) -> Result<Self, ScanError> {
+        use rustix::fs::inotify;
+
+        let fd = inotify::init(inotify::CreateFlags::CLOEXEC).map_err(|e| ScanError::Io {
+            path: PathBuf::from("inotify"),
+            source: e.into(),
+        })?;
+        let mut watcher = Self {
+            current: FragmentSet::default(),
+            scanner,
+            inotify: fd,
+            watches: Vec::new(),
+        };
+        watcher.add_watches()?;
+        watcher.current = watcher.scanner.scan_fragments()?;
+        Ok(watcher)
+    }
+
+    /// The effective fragment set, as of the last change.
+    pub fn fragments(&self) -> &FragmentSet {
+        &self.current
+    }
+
+    /// Watch all scanned directories, replacing any previous watch.
+    fn add_watches(&mut self) -> Result<(), ScanError> {
+        use rustix::fs::inotify::{self, WatchFlags};
+
+        for (wd, _) in self.watches.drain(..) {
+            // Watches of removed directories are already gone.
+            let _ = inotify::remove_watch(&self.inotify, wd);
+        }
+        let flags = WatchFlags::CREATE
+            | WatchFlags::DELETE
+            | WatchFlags::CLOSE_WRITE
+            | WatchFlags::MODIFY
+            | WatchFlags::ATTRIB
+            | WatchFlags::MOVE
+            | WatchFlags::DELETE_SELF
+            | WatchFlags::MOVE_SELF
+            | WatchFlags::ONLYDIR;
+        for dir in self.scanner.search_dirs() {
+            let Some(existing) = dir.ancestors().find(|d| d.is_dir()) else {
+                continue;
+            };
+            if self.watches.iter().any(|(_, d)| d == existing) {
+                continue;
+            }
+            let wd =
+                inotify::add_watch(&self.inotify, existing, flags).map_err(|e| ScanError::Io {
+                    path: existing.to_path_buf(),
+                    source: e.into(),
+                })?;
+            self.watches.push((wd, existing.to_path_buf()));
+        }
+        Ok(())
+    }
+
+    /// Block until the effective fragment set changes, describing how it changed.
+    ///
+    /// Strictness checks (e.g. [`require_nonempty`](Overdrop::require_nonempty)) are
+    /// not applied, as for [`Overdrop::rescan_changed`].
+    pub fn wait(&mut self) -> Result<Vec<WatchEvent>, ScanError> {
+        use rustix::fs::inotify::{ReadFlags, Reader};
+        use std::mem::MaybeUninit;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut buf = [MaybeUninit::uninit(); 4096];
+        loop {
+            let mut changed = Vec::new();
+            let mut rewatch = false;
+            let mut reader = Reader::new(&self.inotify, &mut buf);
+            loop {
+                let event = reader.next().map_err(|e| ScanError::Io {
+                    path: PathBuf::from("inotify"),
+                    source: e.into(),
+                })?;
+                let dir = self.watches.iter().find(|(wd, _)| *wd == event.wd());
+                if let Some((_, dir)) = dir {
+                    let path = match event.file_name() {
+                        Some(name) => dir.join(std::ffi::OsStr::from_bytes(name.to_bytes())),
+                        None => dir.clone(),
+                    };
+                    changed.push(path);
+                }
+                rewatch |= event
+                    .events()
+                    .intersects(ReadFlags::ISDIR | ReadFlags::DELETE_SELF | ReadFlags::MOVE_SELF);
+                if reader.is_buffer_empty() {
+                    break;
+                }
+            }
+
+            // Scanned directories may have been created or removed.
+            if rewatch {
+                self.add_watches()?;
+            }
+            let previous = self.current.clone();
+            let names = self.scanner.rescan_changed(&mut self.current, changed);
+            let events = watch_events(&self.scanner, &previous, &self.current, names);
+            if !events.is_empty() {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TreeBuilder;
+    use std::fs;
+
+    #[test]
+    fn poll_changes() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "a=1\n")
+            .base_dir("etc/svc.d");
+        let admin = tree.path("etc/svc.d");
+
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "etc"]));
+        let mut watcher = PollWatcher::new(scanner, Duration::from_millis(1)).unwrap();
+        assert_eq!(watcher.fragments().len(), 1);
+        assert!(watcher.poll().unwrap().is_empty());
+
+        fs::write(admin.join("10-a.conf"), "a=2\n").unwrap();
+        fs::write(admin.join("20-b.conf"), "b=1\n").unwrap();
+        assert_eq!(watcher.wait().unwrap(), ["10-a.conf", "20-b.conf"]);
+        assert_eq!(
+            watcher.fragments().get("10-a.conf").unwrap().path(),
+            admin.join("10-a.conf")
+        );
+
+        fs::write(admin.join("20-b.conf"), "b=longer\n").unwrap();
+        fs::remove_file(admin.join("10-a.conf")).unwrap();
+        assert_eq!(watcher.poll().unwrap(), ["10-a.conf", "20-b.conf"]);
+        assert!(watcher.poll().unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn poll_events() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/10-a.conf", "a=1\n")
+            .fragment("usr/lib/svc.d/20-b.conf", "b=1\n")
+            .base_dir("etc/svc.d");
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "etc"]));
+        let mut watcher = PollWatcher::new(scanner, Duration::from_millis(1)).unwrap();
+        let vendor = watcher.fragments().clone();
+
+        let tree = tree
+            .fragment("etc/svc.d/10-a.conf", "a=2\n")
+            .mask("etc/svc.d/20-b.conf")
+            .fragment("etc/svc.d/30-c.conf", "c=1\n");
+        let fragments = watcher.scanner.scan_fragments().unwrap();
+        assert_eq!(
+            watcher.poll_events().unwrap(),
+            [
+                WatchEvent::FragmentOverridden {
+                    name: "10-a.conf".into(),
+                    previous: vendor.get("10-a.conf").unwrap().clone(),
+                    fragment: fragments.get("10-a.conf").unwrap().clone(),
+                },
+                WatchEvent::FragmentMasked {
+                    name: "20-b.conf".into(),
+                    previous: vendor.get("20-b.conf").unwrap().clone(),
+                    mask: tree.path("etc/svc.d/20-b.conf"),
+                },
+                WatchEvent::FragmentAdded {
+                    name: "30-c.conf".into(),
+                    fragment: fragments.get("30-c.conf").unwrap().clone(),
+                },
+            ]
+        );
+
+        fs::remove_file(tree.path("etc/svc.d/30-c.conf")).unwrap();
+        assert_eq!(
+            watcher.poll_events().unwrap(),
+            [WatchEvent::FragmentRemoved {
+                name: "30-c.conf".into(),
+                previous: fragments.get("30-c.conf").unwrap().clone(),
+            }]
+        );
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "inotify"))]
+    fn inotify_events() {
+        let tree = TreeBuilder::new().fragment("usr/lib/svc.d/10-a.conf", "a=1\n");
+        let scanner = Overdrop::new("svc.d").base_dirs(tree.base_dirs(["usr/lib", "etc"]));
+        let mut watcher = InotifyWatcher::new(scanner).unwrap();
+        assert_eq!(watcher.fragments().len(), 1);
+
+        // Creating a missing directory is picked up too.
+        let tree = tree.fragment("etc/svc.d/10-a.conf", "a=2\n");
+        let events = watcher.wait().unwrap();
+        assert!(matches!(
+            &events[..],
+            [WatchEvent::FragmentOverridden { fragment, .. }]
+                if fragment.path() == tree.path("etc/svc.d/10-a.conf")
+        ));
+
+        fs::write(tree.path("etc/svc.d/20-b.conf"), "b=1\n").unwrap();
+        let events = watcher.wait().unwrap();
+        assert!(matches!(
+            &events[..],
+            [WatchEvent::FragmentAdded { name, .. }] if name == "20-b.conf"
+        ));
+        fs::remove_file(tree.path("etc/svc.d/20-b.conf")).unwrap();
+        let events = watcher.wait().unwrap();
+        assert!(matches!(&events[..], [WatchEvent::FragmentRemoved { .. }]));
+    }
+}
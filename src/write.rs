@@ -0,0 +1,157 @@
+//! Writing of override fragments.
+
+use log::trace;
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counter for unique temporary filenames within this process.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Write an override fragment named `name` into the highest-priority writable base directory.
+///
+/// `base_dirs` and `shared_path` are the same as for [`scan`](crate::scan). Base
+/// directories are tried in decreasing priority order, skipping missing ones (they are
+/// never created) and those where the drop-in directory cannot be created or written
+/// to, e.g. on a read-only filesystem. The drop-in directory is created if needed,
+/// and the fragment is written atomically: its contents go to a temporary dotfile,
+/// which is then renamed over any existing fragment with the same name. Returns the
+/// path of the written fragment.
+///
+/// As the runtime layer (`/run`) is conventionally the highest-priority one, it
+/// should be left out for persistent overrides:
+///
+/// ```rust,no_run
+/// # use liboverdrop::BaseDirs;
+/// let bases = BaseDirs::from(liboverdrop::SYSTEMD_CONVENTIONAL_BASES).without("/run");
+/// let path =
+///     liboverdrop::write_override(&bases, "my-crate/config.d", "50-site.conf", "retries=5\n")?;
+/// assert_eq!(path, std::path::Path::new("/etc/my-crate/config.d/50-site.conf"));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Note that a fragment with the same name in a higher-priority, non-writable layer
+/// still overrides the written one. `name` must be a plain filename.
+pub fn write_override<BdS, BdI, Sp, N, C>(
+    base_dirs: BdI,
+    shared_path: Sp,
+    name: N,
+    contents: C,
+) -> io::Result<PathBuf>
+where
+    BdS: AsRef<Path>,
+    BdI: IntoIterator<Item = BdS>,
+    Sp: AsRef<Path>,
+    N: AsRef<OsStr>,
+    C: AsRef<[u8]>,
+{
+    let name = name.as_ref();
+    if Path::new(name).file_name() != Some(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid fragment name '{}'", name.to_string_lossy()),
+        ));
+    }
+
+    let base_dirs: Vec<_> = base_dirs.into_iter().collect();
+    let mut last_error = None;
+    for base in base_dirs.iter().rev() {
+        let base = base.as_ref();
+        if !base.is_dir() {
+            continue;
+        }
+        let dir = base.join(shared_path.as_ref());
+        match write_atomic(&dir, name, contents.as_ref()) {
+            Ok(path) => return Ok(path),
+            Err(WriteError::NotWritable(e)) => {
+                trace!("Skipping non-writable directory '{}': {}", dir.display(), e);
+                last_error = Some(e);
+            }
+            Err(WriteError::Failed(e)) => return Err(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no existing base directory")))
+}
+
+/// Error while writing a fragment into a single directory.
+enum WriteError {
+    /// The directory (or a temporary file in it) could not be created.
+    NotWritable(io::Error),
+    /// Writing failed after the temporary file was created.
+    Failed(io::Error),
+}
+
+/// Write `contents` to `dir/name` through a temporary file, creating `dir` if needed.
+fn write_atomic(dir: &Path, name: &OsStr, contents: &[u8]) -> Result<PathBuf, WriteError> {
+    fs::create_dir_all(dir).map_err(WriteError::NotWritable)?;
+    let temp = dir.join(format!(
+        ".{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .map_err(WriteError::NotWritable)?;
+
+    let path = dir.join(name);
+    let written = file
+        .write_all(contents)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&temp, &path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(WriteError::Failed(e));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan;
+    use crate::testing::TreeBuilder;
+
+    #[test]
+    fn write_in_highest_layer() {
+        let tree = TreeBuilder::new()
+            .fragment("usr/lib/svc.d/50-site.conf", "vendor\n")
+            .base_dir("etc");
+        let dirs = tree.base_dirs(["usr/lib", "etc", "run"]);
+        let path = write_override(&dirs, "svc.d", "50-site.conf", "admin\n").unwrap();
+        assert_eq!(path, tree.path("etc/svc.d/50-site.conf"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "admin\n");
+
+        // Existing fragments are replaced, leaving no temporary files behind.
+        write_override(&dirs, "svc.d", "50-site.conf", "admin 2\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "admin 2\n");
+        assert_eq!(fs::read_dir(tree.path("etc/svc.d")).unwrap().count(), 1);
+        let fragments = scan(&dirs, "svc.d", &["conf"], false);
+        assert_eq!(fragments.into_values().collect::<Vec<_>>(), [path]);
+
+        let err = write_override(&dirs, "svc.d", "../escape.conf", "").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = write_override(&dirs[2..], "svc.d", "50-site.conf", "").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn skip_non_writable_layers() {
+        // A file in place of the drop-in directory cannot be written into.
+        let tree = TreeBuilder::new()
+            .base_dir("etc")
+            .fragment("run/svc.d", "not a directory");
+        let dirs = tree.base_dirs(["usr/lib", "etc", "run"]);
+        let path = write_override(&dirs, "svc.d", "50-site.conf", "admin\n").unwrap();
+        assert_eq!(path, tree.path("etc/svc.d/50-site.conf"));
+
+        let err = write_override(&dirs[2..], "svc.d", "50-site.conf", "").unwrap_err();
+        assert_ne!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,103 @@
+//! Base directories for user-level configuration, following the XDG Base Directory specification.
+
+use crate::SYSTEMD_CONVENTIONAL_BASES;
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Return the base directories for a user-session service, in increasing priority order.
+///
+/// These are the [`SYSTEMD_CONVENTIONAL_BASES`] (vendor defaults and system
+/// overrides), followed by the directories listed in `$XDG_CONFIG_DIRS` (by default
+/// `/etc/xdg`), and finally `$XDG_CONFIG_HOME` (by default `$HOME/.config`), so that
+/// per-user fragments override all others. As `$XDG_CONFIG_DIRS` lists directories
+/// in decreasing priority order, they are returned reversed.
+///
+/// As required by the specification, relative paths in these variables are ignored;
+/// `$XDG_CONFIG_HOME` is omitted if neither it nor `$HOME` is set.
+///
+/// ```rust,no_run
+/// # use liboverdrop::Overdrop;
+/// let fragments = Overdrop::new("my-crate/config.d")
+///     .base_dirs(liboverdrop::xdg_base_dirs())
+///     .scan()?;
+/// # Ok::<(), liboverdrop::ScanError>(())
+/// ```
+///
+/// This is available with the `xdg` feature.
+pub fn xdg_base_dirs() -> Vec<PathBuf> {
+    xdg_base_dirs_with(|name| env::var_os(name))
+}
+
+/// Return the base directories for a user-session service, looking up variables via `lookup`.
+///
+/// See [`xdg_base_dirs`].
+pub fn xdg_base_dirs_with<F>(mut lookup: F) -> Vec<PathBuf>
+where
+    F: FnMut(&str) -> Option<OsString>,
+{
+    let mut lookup = |name: &str| lookup(name).filter(|value| !value.is_empty());
+    let mut dirs: Vec<PathBuf> = SYSTEMD_CONVENTIONAL_BASES
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+    let config_dirs = lookup("XDG_CONFIG_DIRS").unwrap_or_else(|| "/etc/xdg".into());
+    let mut config_dirs: Vec<PathBuf> = env::split_paths(&config_dirs)
+        .filter(|dir| dir.is_absolute())
+        .collect();
+    config_dirs.reverse();
+    dirs.extend(config_dirs);
+
+    let config_home = lookup("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            let home = PathBuf::from(lookup("HOME")?);
+            home.is_absolute().then(|| home.join(".config"))
+        });
+    dirs.extend(config_home);
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirs(vars: &[(&str, &str)]) -> Vec<PathBuf> {
+        let mut dirs = xdg_base_dirs_with(|name| {
+            let (_, value) = vars.iter().find(|(var, _)| *var == name)?;
+            Some(value.into())
+        });
+        // The system bases always come first.
+        assert_eq!(
+            dirs.drain(..SYSTEMD_CONVENTIONAL_BASES.len())
+                .collect::<Vec<_>>(),
+            SYSTEMD_CONVENTIONAL_BASES
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+        dirs
+    }
+
+    #[test]
+    fn xdg_dirs() {
+        assert_eq!(
+            dirs(&[("HOME", "/home/user")]),
+            ["/etc/xdg", "/home/user/.config"].map(PathBuf::from)
+        );
+        assert_eq!(
+            dirs(&[
+                ("HOME", "/home/user"),
+                ("XDG_CONFIG_DIRS", "/etc/xdg/high:relative:/etc/xdg/low"),
+                ("XDG_CONFIG_HOME", "/home/user/conf"),
+            ]),
+            ["/etc/xdg/low", "/etc/xdg/high", "/home/user/conf"].map(PathBuf::from)
+        );
+        assert_eq!(
+            dirs(&[("XDG_CONFIG_DIRS", ""), ("XDG_CONFIG_HOME", "relative")]),
+            ["/etc/xdg"].map(PathBuf::from)
+        );
+    }
+}